@@ -0,0 +1,32 @@
+/*!
+Symbol aliases for compiled functions
+*/
+use super::*;
+use inkwell::types::AsTypeRef;
+use inkwell::values::AsValueRef;
+use std::ffi::CString;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Emit an LLVM global alias exposing a compiled function under another symbol name
+    ///
+    /// This allows a single compiled lambda to be referenced by several export symbols without duplicating its code.
+    /// Returns `Error::SymbolExists` if `alias` already names a function or global in the module.
+    pub fn add_alias(&mut self, alias: &str, f: FunctionValue<'ctx>) -> Result<(), Error> {
+        if self.module.get_function(alias).is_some() || self.module.get_global(alias).is_some() {
+            return Err(Error::SymbolExists(alias.to_string()));
+        }
+        let name = CString::new(alias)
+            .map_err(|_| Error::InternalError("Alias names must not contain null bytes"))?;
+        let ptr_type = f.as_global_value().as_pointer_value().get_type();
+        // SAFETY: the module, type and aliasee are all valid, and belong to the same LLVM context
+        unsafe {
+            llvm_sys::core::LLVMAddAlias(
+                self.module.as_mut_ptr(),
+                ptr_type.as_type_ref(),
+                f.as_value_ref(),
+                name.as_ptr(),
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+/*!
+Stack allocation for compiled `rain` values
+*/
+use super::*;
+use inkwell::types::BasicTypeEnum;
+use inkwell::values::PointerValue;
+
+/// The name of the LLVM intrinsic marking the start of the lifetime of a stack slot
+pub const LIFETIME_START: &str = "llvm.lifetime.start.p0i8";
+
+/// The name of the LLVM intrinsic marking the end of the lifetime of a stack slot
+pub const LIFETIME_END: &str = "llvm.lifetime.end.p0i8";
+
+impl<'ctx> Codegen<'ctx> {
+    /// Get or declare a lifetime intrinsic on the current module
+    fn lifetime_intrinsic(&mut self, name: &str) -> FunctionValue<'ctx> {
+        if let Some(intrinsic) = self.module.get_function(name) {
+            return intrinsic;
+        }
+        let i8_ptr = self.byte_ptr();
+        let ty = self
+            .context
+            .void_type()
+            .fn_type(&[self.context.i64_type().into(), i8_ptr.into()], false);
+        self.module.add_function(name, ty, None)
+    }
+
+    /// Emit a call to a lifetime intrinsic for a given stack slot
+    fn build_lifetime_marker(&mut self, name: &str, slot: PointerValue<'ctx>) {
+        let intrinsic = self.lifetime_intrinsic(name);
+        let i8_ptr = self.byte_ptr();
+        let ptr = self.builder.build_pointer_cast(slot, i8_ptr, "slot_ptr");
+        // A size of -1 covers the entire slot
+        let size = self.context.i64_type().const_all_ones();
+        self.builder
+            .build_call(intrinsic, &[size.into(), ptr.into()], "");
+    }
+
+    /// Allocate a stack slot of a given type in the entry block of the current function
+    ///
+    /// If `emit_lifetime_markers` is set, the lifetime of the slot is started at the current builder position, and
+    /// should be ended with `end_stack_slot`.
+    pub fn build_stack_slot(
+        &mut self,
+        ty: BasicTypeEnum<'ctx>,
+    ) -> Result<PointerValue<'ctx>, Error> {
+        let curr = self
+            .builder
+            .get_insert_block()
+            .ok_or(Error::NoCurrentBlock)?
+            .get_parent()
+            .ok_or(Error::NoCurrentFunction)?;
+        let entry = curr
+            .get_first_basic_block()
+            .expect("The current block is in the current function");
+        // Allocas are placed at the start of the entry block, so they can be promoted to registers
+        let entry_builder = self.context.create_builder();
+        match entry.get_first_instruction() {
+            Some(first) => entry_builder.position_before(&first),
+            None => entry_builder.position_at_end(entry),
+        }
+        let slot = entry_builder.build_alloca(ty, "slot");
+        if self.emit_lifetime_markers {
+            self.build_lifetime_marker(LIFETIME_START, slot);
+        }
+        Ok(slot)
+    }
+
+    /// End the lifetime of a stack slot allocated with `build_stack_slot` at the current builder position
+    ///
+    /// This is a no-op unless `emit_lifetime_markers` is set.
+    pub fn end_stack_slot(&mut self, slot: PointerValue<'ctx>) {
+        if self.emit_lifetime_markers {
+            self.build_lifetime_marker(LIFETIME_END, slot);
+        }
+    }
+}
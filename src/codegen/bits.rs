@@ -3,10 +3,15 @@ Code generation for bits type of `rain`
 */
 
 use super::*;
-use inkwell::types::IntType;
-use rain_ir::primitive::bits::{Bits, BitsTy};
+use crate::lowering::SignedBitsOp;
+use inkwell::types::{BasicTypeEnum, IntType};
+use inkwell::values::{BasicValueEnum, IntValue};
+use inkwell::IntPredicate;
+use rain_ir::primitive::bits::{BinOp, Bits, BitsTy};
+use rain_ir::primitive::finite::Finite;
 use rain_ir::typing::Typed;
 use std::convert::TryFrom;
+use std::rc::Rc;
 
 impl<'ctx> Codegen<'ctx> {
     /// Compile a BitsTy into a LLVM value
@@ -14,10 +19,14 @@ impl<'ctx> Codegen<'ctx> {
         unimplemented!("Compile 32-bit LLVM integer constant for BitsTy {}", b)
     }
     /// Get the representation for a bitsTy type
-    pub fn repr_bitsty(&mut self, b: &BitsTy) -> Repr<'ctx> {
+    ///
+    /// Returns `Error::UnrepresentableType` if the representation is wider than the target profile allows.
+    pub fn repr_bitsty(&mut self, b: &BitsTy) -> Result<Repr<'ctx>, Error> {
         let width: u32 = b.0;
-        if width == 0 {
+        let repr = if width == 0 {
             Repr::Empty
+        } else if self.exact_int_widths {
+            Repr::Type(self.context.custom_width_int_type(width).into())
         } else if width == 1 {
             Repr::Type(self.context.bool_type().into())
         } else if width <= 8 {
@@ -31,27 +40,733 @@ impl<'ctx> Codegen<'ctx> {
         } else if width <= 128 {
             Repr::Type(self.context.i128_type().into())
         } else {
-            unimplemented!("Bits type wider than 128 bits has not been implemented.")
-        }
+            // Wider bit vectors, e.g. 256-bit hash lanes, are represented with their exact width
+            Repr::Type(self.context.custom_width_int_type(width).into())
+        };
+        self.check_int_width(repr)
     }
     /// compile an bits vector
-    pub fn build_bits(&mut self, b: &Bits) -> Val<'ctx> {
+    pub fn build_bits(&mut self, b: &Bits) -> Result<Val<'ctx>, Error> {
         let ty = match b.ty().as_enum() {
             ValueEnum::BitsTy(b) => b,
             _ => unreachable!(),
         };
         let width = ty.0;
-        match self.repr_bitsty(ty) {
+        Ok(match self.repr_bitsty(ty)? {
             Repr::Empty => Val::Contr,
             Repr::Type(t) => {
                 let t = IntType::try_from(t).expect("An integer type");
-                if b.data() < (1 << width) {
+                if width < 128 && b.data() >= (1 << width) {
+                    panic!("The width of Bits {} doesn't match with its type ", b)
+                }
+                if b.data() <= u64::MAX as u128 {
                     t.const_int(b.data() as u64, false).into()
                 } else {
-                    panic!("The width of Bits {} doesn't match with its type ", b)
+                    // Constants wider than 64 bits are built from their 64-bit limbs, least significant first
+                    let limbs = [b.data() as u64, (b.data() >> 64) as u64];
+                    t.const_int_arbitrary_precision(&limbs).into()
                 }
             }
             _ => unreachable!(),
+        })
+    }
+    /// Build the application of a bits operator to a list of arguments
+    ///
+    /// The first argument of each bits operator is the bits type it acts on, followed by its operands.
+    pub fn build_bits_app(&mut self, op: &ValueEnum, args: &[ValId]) -> Result<Val<'ctx>, Error> {
+        let arity = match op {
+            ValueEnum::BinOp(_) => 3,
+            ValueEnum::Neg(_) => 2,
+            _ => return Err(Error::InternalError("Not a bits operator")),
+        };
+        if args.len() != arity {
+            return Err(Error::NotImplemented(
+                "Partial application of bits operators",
+            ));
+        }
+        // Chains of associative operations are flattened, so their constant operands can be folded together
+        let mut operand_args: Vec<&ValId> = Vec::with_capacity(arity - 1);
+        match op {
+            ValueEnum::BinOp(b @ BinOp::Add) | ValueEnum::BinOp(b @ BinOp::Mul) => {
+                Self::flatten_bits_chain(*b, &args[0], &args[1..], &mut operand_args)
+            }
+            _ => operand_args.extend(&args[1..]),
+        }
+        let mut operands: Vec<IntValue<'ctx>> = Vec::with_capacity(operand_args.len());
+        for arg in operand_args {
+            match self.build(arg)? {
+                Val::Value(BasicValueEnum::IntValue(i)) => operands.push(i),
+                Val::Contr => return Ok(Val::Contr),
+                _ => return Err(Error::NotImplemented("Bits operators on non-bits values")),
+            }
+        }
+        match op {
+            ValueEnum::BinOp(b) => self.build_bits_nary(*b, &operands[..]),
+            ValueEnum::Neg(_) => Ok(self.builder.build_int_neg(operands[0], "__neg_").into()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Collect the operands of a chain of applications of an associative operator `op` on bit vectors of type `ty`
+    fn flatten_bits_chain<'a>(
+        op: BinOp,
+        ty: &ValId,
+        args: &'a [ValId],
+        operands: &mut Vec<&'a ValId>,
+    ) {
+        for arg in args {
+            match arg.as_enum() {
+                ValueEnum::Sexpr(s) if s.len() == 4 && &s[1] == ty => match s[0].as_enum() {
+                    ValueEnum::BinOp(b) if *b == op => {
+                        Self::flatten_bits_chain(op, ty, &s.as_slice()[2..], operands)
+                    }
+                    _ => operands.push(arg),
+                },
+                _ => operands.push(arg),
+            }
+        }
+    }
+
+    /// Build an associative operation on any number of bit vectors of the same width
+    ///
+    /// Constant operands are folded together at compile time, so that only one instruction is emitted per
+    /// non-constant operand. Non-associative operations are only supported on exactly two operands.
+    pub fn build_bits_nary(
+        &mut self,
+        op: BinOp,
+        operands: &[IntValue<'ctx>],
+    ) -> Result<Val<'ctx>, Error> {
+        match (op, operands) {
+            (BinOp::Add, _) | (BinOp::Mul, _) => {}
+            (op, [lhs, rhs]) => return self.build_bits_binop(op, *lhs, *rhs),
+            _ => {
+                return Err(Error::NotImplemented(
+                    "Non-associative bits operations on more than two operands",
+                ))
+            }
+        }
+        let ty = match operands.first() {
+            Some(operand) => operand.get_type(),
+            None => return Err(Error::InternalError("Bits operation with no operands")),
+        };
+        if operands.iter().any(|operand| operand.get_type() != ty) {
+            return Err(Error::InternalError(
+                "Binary operation on bit vectors of different widths",
+            ));
+        }
+        let (constants, variables): (Vec<IntValue<'ctx>>, Vec<IntValue<'ctx>>) = operands
+            .iter()
+            .copied()
+            .partition(|operand| operand.is_const());
+        // Operations on constants are folded by the builder, so this emits no instructions
+        let mut constant = None;
+        for c in constants {
+            constant = Some(match constant {
+                Some(acc) => self.build_assoc_binop(op, acc, c),
+                None => c,
+            });
+        }
+        let identity = match op {
+            BinOp::Mul => 1,
+            _ => 0,
+        };
+        let mut result = None;
+        for v in variables {
+            result = Some(match result {
+                Some(acc) => self.build_assoc_binop(op, acc, v),
+                None => v,
+            });
+        }
+        let result = match (result, constant) {
+            (Some(result), Some(c)) if c.get_zero_extended_constant() != Some(identity) => {
+                self.build_assoc_binop(op, result, c)
+            }
+            (Some(result), _) => result,
+            (None, Some(c)) => c,
+            (None, None) => unreachable!("Checked above that there is at least one operand"),
+        };
+        Ok(result.into())
+    }
+
+    /// Build an associative binary operation, i.e. an addition or multiplication, on two bit vectors of the same width
+    fn build_assoc_binop(
+        &mut self,
+        op: BinOp,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        match op {
+            BinOp::Mul => self.builder.build_int_mul(lhs, rhs, "__mul"),
+            _ => self.builder.build_int_add(lhs, rhs, "__add"),
+        }
+    }
+
+    /// Build a binary operation on two bit vectors of the same width
+    ///
+    /// Constant operands are folded. Taking the remainder modulo a constant zero is undefined behaviour, and hence
+    /// compiles to `Val::Contr`.
+    pub fn build_bits_binop(
+        &mut self,
+        op: BinOp,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<Val<'ctx>, Error> {
+        if lhs.get_type() != rhs.get_type() {
+            return Err(Error::InternalError(
+                "Binary operation on bit vectors of different widths",
+            ));
+        }
+        let result = match op {
+            BinOp::Add => self.builder.build_int_add(lhs, rhs, "__add"),
+            BinOp::Sub => self.builder.build_int_sub(lhs, rhs, "__sub"),
+            BinOp::Mul => self.builder.build_int_mul(lhs, rhs, "__mul"),
+            BinOp::Mod => {
+                if rhs.get_zero_extended_constant() == Some(0) {
+                    return Ok(Val::Contr);
+                }
+                self.builder.build_int_unsigned_rem(lhs, rhs, "__umod")
+            }
+        };
+        Ok(result.into())
+    }
+
+    /// Build the unsigned quotient of two bit vectors of the same width
+    ///
+    /// Since `rain`'s type system does not yet forbid it, division by a constant zero returns
+    /// `Error::NotImplemented` rather than compiling to undefined behaviour.
+    pub fn build_bits_udiv(
+        &mut self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<Val<'ctx>, Error> {
+        Self::check_divisor(lhs, rhs)?;
+        Ok(self
+            .builder
+            .build_int_unsigned_div(lhs, rhs, "__udiv")
+            .into())
+    }
+
+    /// Build the unsigned remainder of two bit vectors of the same width
+    ///
+    /// As for `build_bits_udiv`, division by a constant zero returns `Error::NotImplemented`.
+    pub fn build_bits_urem(
+        &mut self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<Val<'ctx>, Error> {
+        Self::check_divisor(lhs, rhs)?;
+        Ok(self
+            .builder
+            .build_int_unsigned_rem(lhs, rhs, "__urem")
+            .into())
+    }
+
+    /// Check that the operands of a division have the same width, and that the divisor is not a constant zero
+    fn check_divisor(lhs: IntValue<'ctx>, rhs: IntValue<'ctx>) -> Result<(), Error> {
+        if lhs.get_type() != rhs.get_type() {
+            return Err(Error::InternalError(
+                "Binary operation on bit vectors of different widths",
+            ));
+        }
+        if rhs.get_zero_extended_constant() == Some(0) {
+            return Err(Error::NotImplemented("division by zero"));
+        }
+        Ok(())
+    }
+
+    /// Build the wrapping sum of two bit vectors of the same width together with its carry bit, as a value of
+    /// `#product[#bits(n) #bool]`
+    ///
+    /// This lowers to `llvm.uadd.with.overflow`, except for constant operands, which are folded, and for bit vectors
+    /// narrower than their representation, whose carry is the bit just above their width.
+    pub fn build_bits_add_with_overflow(
+        &mut self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        width: u32,
+    ) -> Result<Val<'ctx>, Error> {
+        let ty = lhs.get_type();
+        if ty != rhs.get_type() {
+            return Err(Error::InternalError(
+                "Binary operation on bit vectors of different widths",
+            ));
+        }
+        Self::check_bits_width(lhs, width)?;
+        let bool_type = self.context.bool_type();
+        let (sum, carry) = if width < ty.get_bit_width() {
+            // The sum of two `width`-bit vectors fits in `width + 1` bits, so it cannot wrap the representation
+            let sum = self.builder.build_int_add(lhs, rhs, "__uadd");
+            let mask = self.build_bits_mask(ty, width);
+            let carry = self
+                .builder
+                .build_int_compare(IntPredicate::UGT, sum, mask, "__carry");
+            let sum = self.builder.build_and(sum, mask, "__uadd");
+            (sum, carry)
+        } else if lhs.is_const() && rhs.is_const() {
+            let sum = self.builder.build_int_add(lhs, rhs, "__uadd");
+            let carry = self
+                .builder
+                .build_int_compare(IntPredicate::ULT, sum, lhs, "__carry");
+            (sum, carry)
+        } else {
+            let name = format!("llvm.uadd.with.overflow.i{}", ty.get_bit_width());
+            let overflow_repr = self
+                .context
+                .struct_type(&[ty.into(), bool_type.into()], false);
+            let mut mapping = IxMap::with_capacity(2);
+            mapping.push_ix(0);
+            mapping.push_ix(1);
+            let ret = Repr::Product(Rc::new(ProductRepr {
+                mapping,
+                repr: overflow_repr,
+            }));
+            let result = match self.build_intrinsic(&name, ret, &[lhs.into(), rhs.into()])? {
+                Val::Value(BasicValueEnum::StructValue(s)) => s,
+                _ => {
+                    return Err(Error::InternalError(
+                        "Overflow intrinsic must return a struct",
+                    ))
+                }
+            };
+            let sum = self
+                .builder
+                .build_extract_value(result, 0, "__uadd")
+                .expect("Overflow intrinsics return a sum")
+                .into_int_value();
+            let carry = self
+                .builder
+                .build_extract_value(result, 1, "__carry")
+                .expect("Overflow intrinsics return a carry bit")
+                .into_int_value();
+            (sum, carry)
+        };
+        // Pack the result as `#product[#bits(n) #bool]`, which may store the carry bit as a byte
+        let carry_slot = self.field_repr(bool_type.into());
+        let product = self.context.struct_type(&[ty.into(), carry_slot], false);
+        let carry = self.build_field_widening(carry.into(), carry_slot);
+        if sum.is_const() && carry.into_int_value().is_const() {
+            return Ok(product.const_named_struct(&[sum.into(), carry]).into());
+        }
+        let aggregate = self.aggregate_seed(product);
+        let aggregate = self
+            .builder
+            .build_insert_value(aggregate, sum, 0, "__uadd_overflow")
+            .expect("Valid index")
+            .into_struct_value();
+        let aggregate = self
+            .builder
+            .build_insert_value(aggregate, carry, 1, "__uadd_overflow")
+            .expect("Valid index")
+            .into_struct_value();
+        Ok(aggregate.into())
+    }
+
+    /// Check that a bit vector of a given width fits in its representation
+    fn check_bits_width(x: IntValue<'ctx>, width: u32) -> Result<(), Error> {
+        if width == 0 || width > x.get_type().get_bit_width() {
+            Err(Error::InternalError(
+                "Bit vector wider than its representation",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Build the mask of the low `width` bits of an integer type
+    fn build_bits_mask(&mut self, ty: IntType<'ctx>, width: u32) -> IntValue<'ctx> {
+        let padding = ty.const_int((ty.get_bit_width() - width) as u64, false);
+        self.builder
+            .build_right_shift(ty.const_all_ones(), padding, false, "__mask")
+    }
+
+    /// Build the application of a signed bits operation to a bits type and an operand
+    pub fn build_signed_bits_app(
+        &mut self,
+        op: SignedBitsOp,
+        args: &[ValId],
+    ) -> Result<Val<'ctx>, Error> {
+        let (ty, operand) = match args {
+            [ty, operand] => (ty, operand),
+            _ => {
+                return Err(Error::NotImplemented(
+                    "Partial application of signed bits operators",
+                ))
+            }
+        };
+        let width = match ty.as_enum() {
+            ValueEnum::BitsTy(b) => b.0,
+            _ => return Err(Error::NotImplemented("Signed operations on non-bits types")),
+        };
+        match self.build(operand)? {
+            Val::Value(BasicValueEnum::IntValue(i)) => {
+                Ok(self.build_signed_bits_op(op, i, width)?.into())
+            }
+            Val::Contr => Ok(Val::Contr),
+            _ => Err(Error::NotImplemented("Bits operators on non-bits values")),
+        }
+    }
+
+    /// Build a signed operation on a bit vector of a given width, represented by an integer at least as wide
+    ///
+    /// Bit vectors narrower than their representation are sign-extended within it, and the result is masked back to
+    /// `width` bits. Constant operands are folded.
+    pub fn build_signed_bits_op(
+        &mut self,
+        op: SignedBitsOp,
+        x: IntValue<'ctx>,
+        width: u32,
+    ) -> Result<IntValue<'ctx>, Error> {
+        Self::check_bits_width(x, width)?;
+        let ty = x.get_type();
+        let repr_width = ty.get_bit_width();
+        let padding = ty.const_int((repr_width - width) as u64, false);
+        let x = if width < repr_width {
+            let shifted = self.builder.build_left_shift(x, padding, "__sext");
+            self.builder
+                .build_right_shift(shifted, padding, true, "__sext")
+        } else {
+            x
+        };
+        let result = match op {
+            SignedBitsOp::Abs => {
+                let negated = self.builder.build_int_neg(x, "__neg");
+                let is_negative = self.builder.build_int_compare(
+                    IntPredicate::SLT,
+                    x,
+                    ty.const_zero(),
+                    "__is_neg",
+                );
+                self.builder
+                    .build_select(is_negative, negated, x, "__abs")
+                    .into_int_value()
+            }
+            SignedBitsOp::Sign => {
+                let sign_bit = ty.const_int((repr_width - 1) as u64, false);
+                self.builder.build_right_shift(x, sign_bit, true, "__sign")
+            }
+        };
+        if width < repr_width {
+            let mask = self.build_bits_mask(ty, width);
+            Ok(self.builder.build_and(result, mask, "__trunc"))
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Resize an integer to a given integer type, zero-extending or truncating as necessary
+    fn build_int_resize(
+        &mut self,
+        i: IntValue<'ctx>,
+        ty: IntType<'ctx>,
+        name: &str,
+    ) -> IntValue<'ctx> {
+        let (from, to) = (i.get_type().get_bit_width(), ty.get_bit_width());
+        if from < to {
+            self.builder.build_int_z_extend(i, ty, name)
+        } else if from > to {
+            self.builder.build_int_truncate(i, ty, name)
+        } else {
+            i
+        }
+    }
+
+    /// Coerce an index into a finite type, represented as an integer, into a bit vector of a given type
+    ///
+    /// Returns `Error::ReprMismatch` if the bit vector type is not represented by an integer.
+    pub fn build_index_to_bits(&mut self, ix: Val<'ctx>, ty: &BitsTy) -> Result<Val<'ctx>, Error> {
+        let target = match self.repr_bitsty(ty)? {
+            Repr::Type(BasicTypeEnum::IntType(t)) => t,
+            Repr::Empty => return Ok(Val::Contr),
+            r => return Err(Error::ReprMismatch(format!("{:?}", r))),
+        };
+        let ix = match ix {
+            // The only index into a propositional finite type is zero
+            Val::Unit => return Ok(target.const_int(0, false).into()),
+            Val::Value(BasicValueEnum::IntValue(ix)) => ix,
+            Val::Contr => return Ok(Val::Contr),
+            _ => return Err(Error::InternalError("Index must be an integer")),
+        };
+        Ok(self.build_int_resize(ix, target, "__ix_bits").into())
+    }
+
+    /// Coerce a bit vector into an index into a given finite type, reducing it modulo the size of the finite type
+    ///
+    /// Returns `Error::ReprMismatch` if the finite type is not represented by an integer.
+    pub fn build_bits_to_index(
+        &mut self,
+        bits: IntValue<'ctx>,
+        f: &Finite,
+    ) -> Result<Val<'ctx>, Error> {
+        let target = match self.repr_finite(f)? {
+            Repr::Type(BasicTypeEnum::IntType(t)) => t,
+            Repr::Prop => return Ok(Val::Unit),
+            Repr::Empty => return Ok(Val::Contr),
+            r => return Err(Error::ReprMismatch(format!("{:?}", r))),
+        };
+        let width = bits.get_type().get_bit_width();
+        // Bounds-mask the bit vector if it may hold values not in the finite type
+        let masked = if width < 128 && f.0 >= (1 << width) {
+            bits
+        } else if f.0 <= u64::MAX as u128 {
+            let modulus = bits.get_type().const_int(f.0 as u64, false);
+            self.builder
+                .build_int_unsigned_rem(bits, modulus, "__bits_ix_mask")
+        } else {
+            return Err(Error::NotImplemented(
+                "Masking by finite types larger than 2^64",
+            ));
+        };
+        Ok(self.build_int_resize(masked, target, "__bits_ix").into())
+    }
+
+    /// Build a bit counting intrinsic on a bit vector of a given width, folding it with `fold` if the bit vector is a
+    /// constant
+    ///
+    /// The padding bits of a bit vector narrower than its representation are assumed to be zero. They are excluded from
+    /// the count by setting the lowest padding bit before counting trailing zeros, and by subtracting the padding
+    /// after counting leading zeros.
+    fn build_bit_count<F>(
+        &mut self,
+        intrinsic: &str,
+        x: IntValue<'ctx>,
+        width: u32,
+        fold: F,
+    ) -> Result<Val<'ctx>, Error>
+    where
+        F: FnOnce(u64, u32) -> u32,
+    {
+        Self::check_bits_width(x, width)?;
+        let ty = x.get_type();
+        let repr_width = ty.get_bit_width();
+        if width <= 64 {
+            if let Some(c) = x.get_zero_extended_constant() {
+                return Ok(ty.const_int(fold(c, width) as u64, false).into());
+            }
+        }
+        let x = if intrinsic == "cttz" && width < repr_width {
+            let sentinel = ty
+                .const_int(1, false)
+                .const_shl(ty.const_int(width as u64, false));
+            self.builder.build_or(x, sentinel, "__cttz_sentinel")
+        } else {
+            x
+        };
+        let name = format!("llvm.{}.i{}", intrinsic, repr_width);
+        let mut args: Vec<Val<'ctx>> = vec![x.into()];
+        if intrinsic != "ctpop" {
+            // `rain` defines counting the zeros of zero to give the bit width, so zero is not poison
+            args.push(self.context.bool_type().const_int(0, false).into());
+        }
+        let count = self.build_intrinsic(&name, Repr::Type(ty.into()), &args[..])?;
+        match count {
+            Val::Value(BasicValueEnum::IntValue(count))
+                if intrinsic == "ctlz" && width < repr_width =>
+            {
+                let padding = ty.const_int((repr_width - width) as u64, false);
+                Ok(self.builder.build_int_sub(count, padding, "__ctlz").into())
+            }
+            count => Ok(count),
+        }
+    }
+
+    /// Build the population count, i.e. number of set bits, of a bit vector of a given width
+    pub fn build_popcount(&mut self, x: IntValue<'ctx>, width: u32) -> Result<Val<'ctx>, Error> {
+        self.build_bit_count("ctpop", x, width, |c, _| c.count_ones())
+    }
+
+    /// Build the number of leading zeros of a bit vector of a given width, which is its width if it is zero
+    pub fn build_ctlz(&mut self, x: IntValue<'ctx>, width: u32) -> Result<Val<'ctx>, Error> {
+        self.build_bit_count("ctlz", x, width, |c, width| {
+            c.leading_zeros() - (64 - width)
+        })
+    }
+
+    /// Build the number of trailing zeros of a bit vector of a given width, which is its width if it is zero
+    pub fn build_cttz(&mut self, x: IntValue<'ctx>, width: u32) -> Result<Val<'ctx>, Error> {
+        self.build_bit_count("cttz", x, width, |c, width| c.trailing_zeros().min(width))
+    }
+
+    /// Build a rotation of a bit vector of a given width, folding it if both arguments are constant
+    ///
+    /// Bit vectors as wide as their representation are rotated via a funnel shift, whereas narrower bit vectors are
+    /// rotated within their low `width` bits so that no bits are rotated into or out of the padding.
+    fn build_rotate(
+        &mut self,
+        x: IntValue<'ctx>,
+        amount: IntValue<'ctx>,
+        width: u32,
+        left: bool,
+    ) -> Result<Val<'ctx>, Error> {
+        Self::check_bits_width(x, width)?;
+        let ty = x.get_type();
+        let amount = self.build_int_resize(amount, ty, "__rot_amount");
+        if width <= 64 {
+            if let (Some(c), Some(a)) = (
+                x.get_zero_extended_constant(),
+                amount.get_zero_extended_constant(),
+            ) {
+                let a = (a % width as u64) as u32;
+                let mask = u64::MAX >> (64 - width);
+                let rotated = if a == 0 {
+                    c
+                } else if left {
+                    (c << a) | (c >> (width - a))
+                } else {
+                    (c >> a) | (c << (width - a))
+                };
+                return Ok(ty.const_int(rotated & mask, false).into());
+            }
+        }
+        if width == ty.get_bit_width() {
+            let name = format!("llvm.{}.i{}", if left { "fshl" } else { "fshr" }, width);
+            return self.build_intrinsic(
+                &name,
+                Repr::Type(ty.into()),
+                &[x.into(), x.into(), amount.into()],
+            );
         }
+        // Both shift amounts are at most `width`, which is less than the representation width, so neither is poison
+        let width_val = ty.const_int(width as u64, false);
+        let amount = self
+            .builder
+            .build_int_unsigned_rem(amount, width_val, "__rot_amount");
+        let complement = self
+            .builder
+            .build_int_sub(width_val, amount, "__rot_complement");
+        let (high, low) = if left {
+            (amount, complement)
+        } else {
+            (complement, amount)
+        };
+        let high = self.builder.build_left_shift(x, high, "__rot_high");
+        let low = self.builder.build_right_shift(x, low, false, "__rot_low");
+        let rotated = self.builder.build_or(high, low, "__rot");
+        let mask = self.build_bits_mask(ty, width);
+        Ok(self.builder.build_and(rotated, mask, "__trunc").into())
+    }
+
+    /// Build the rotation of a bit vector of a given width to the left by a given amount, taken modulo its width
+    pub fn build_rotate_left(
+        &mut self,
+        x: IntValue<'ctx>,
+        amount: IntValue<'ctx>,
+        width: u32,
+    ) -> Result<Val<'ctx>, Error> {
+        self.build_rotate(x, amount, width, true)
+    }
+
+    /// Build the rotation of a bit vector of a given width to the right by a given amount, taken modulo its width
+    pub fn build_rotate_right(
+        &mut self,
+        x: IntValue<'ctx>,
+        amount: IntValue<'ctx>,
+        width: u32,
+    ) -> Result<Val<'ctx>, Error> {
+        self.build_rotate(x, amount, width, false)
+    }
+
+    /// Build a left shift or logical right shift of a bit vector of a given width
+    ///
+    /// Shifting by a constant amount greater than or equal to the width of the bit vector yields a constant zero,
+    /// rather than a poison value. Bits shifted left past the width of a bit vector narrower than its representation
+    /// are cleared, rather than kept as padding.
+    fn build_shift(
+        &mut self,
+        x: IntValue<'ctx>,
+        amount: IntValue<'ctx>,
+        width: u32,
+        left: bool,
+    ) -> Result<Val<'ctx>, Error> {
+        Self::check_bits_width(x, width)?;
+        let ty = x.get_type();
+        if let Some(a) = amount.get_zero_extended_constant() {
+            if a >= width as u64 {
+                return Ok(ty.const_zero().into());
+            }
+        }
+        let amount = self.build_int_resize(amount, ty, "__shift_amount");
+        let shifted = if left {
+            let shifted = self.builder.build_left_shift(x, amount, "__shl");
+            if width < ty.get_bit_width() {
+                let mask = self.build_bits_mask(ty, width);
+                self.builder.build_and(shifted, mask, "__trunc")
+            } else {
+                shifted
+            }
+        } else {
+            self.builder.build_right_shift(x, amount, false, "__lshr")
+        };
+        Ok(shifted.into())
+    }
+
+    /// Build the left shift of a bit vector of a given width by a given amount
+    pub fn build_shift_left(
+        &mut self,
+        x: IntValue<'ctx>,
+        amount: IntValue<'ctx>,
+        width: u32,
+    ) -> Result<Val<'ctx>, Error> {
+        self.build_shift(x, amount, width, true)
+    }
+
+    /// Build the logical right shift of a bit vector of a given width by a given amount, shifting in zeroes
+    pub fn build_logical_shift_right(
+        &mut self,
+        x: IntValue<'ctx>,
+        amount: IntValue<'ctx>,
+        width: u32,
+    ) -> Result<Val<'ctx>, Error> {
+        self.build_shift(x, amount, width, false)
+    }
+
+    /// Build a comparison of two bit vectors of the same width, returning a `#bool`
+    ///
+    /// The result is an `i1`, as for the representation of `#finite(2)`, so it can be used directly as the condition
+    /// of a ternary node. Comparisons of constants are folded.
+    pub fn build_bits_compare(
+        &mut self,
+        predicate: IntPredicate,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+    ) -> Result<Val<'ctx>, Error> {
+        if lhs.get_type() != rhs.get_type() {
+            return Err(Error::InternalError(
+                "Comparison of bit vectors of different widths",
+            ));
+        }
+        Ok(self
+            .builder
+            .build_int_compare(predicate, lhs, rhs, "__bits_cmp")
+            .into())
+    }
+
+    /// Build the minimum or maximum of two bit vectors of the same width, interpreted as signed or unsigned
+    ///
+    /// This is lowered to a comparison followed by a select, which is folded if both arguments are constant.
+    pub fn build_minmax(
+        &mut self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        signed: bool,
+        max: bool,
+    ) -> Result<Val<'ctx>, Error> {
+        if lhs.get_type() != rhs.get_type() {
+            return Err(Error::InternalError(
+                "Comparison of bit vectors of different widths",
+            ));
+        }
+        let predicate = match (signed, max) {
+            (false, false) => IntPredicate::ULT,
+            (false, true) => IntPredicate::UGT,
+            (true, false) => IntPredicate::SLT,
+            (true, true) => IntPredicate::SGT,
+        };
+        let select_lhs = self
+            .builder
+            .build_int_compare(predicate, lhs, rhs, "__minmax_cmp");
+        Ok(self
+            .builder
+            .build_select(select_lhs, lhs, rhs, "__minmax")
+            .into())
     }
 }
@@ -0,0 +1,30 @@
+/*!
+Code generation for constant byte strings
+*/
+use super::*;
+use inkwell::module::Linkage;
+use inkwell::values::IntValue;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Build a constant byte string, returning an `i8*` pointing to its first byte
+    ///
+    /// The bytes are stored, without a null terminator, in a private constant global array. Note that `rain` does not
+    /// yet have byte string literals, so this is not dispatched to by `build`.
+    pub fn build_byte_string(&mut self, bytes: &[u8]) -> Val<'ctx> {
+        let i8_type = self.context.i8_type();
+        let elements: Vec<IntValue<'ctx>> = bytes
+            .iter()
+            .map(|b| i8_type.const_int(*b as u64, false))
+            .collect();
+        let array = i8_type.const_array(&elements[..]);
+        let name = self.fresh_name("__bytes");
+        let global = self
+            .module
+            .add_global(i8_type.array_type(bytes.len() as u32), None, &name);
+        global.set_initializer(&array);
+        global.set_constant(true);
+        global.set_linkage(Linkage::Private);
+        let ptr = global.as_pointer_value().const_cast(self.byte_ptr());
+        Val::Value(ptr.into())
+    }
+}
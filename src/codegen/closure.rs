@@ -0,0 +1,386 @@
+/*!
+Code generation for closures, i.e. `rain` lambdas capturing values from enclosing regions, and partial applications
+*/
+use super::*;
+use inkwell::types::{BasicType, BasicTypeEnum, FunctionType, StructType};
+use inkwell::values::{BasicValueEnum, PointerValue, StructValue};
+use inkwell::AddressSpace;
+use rain_ir::function::lambda::Lambda;
+use rain_ir::region::Regional;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Get the values captured by a closure, i.e. its dependencies on the parameters of enclosing regions
+    pub fn closure_captures(lambda: &Lambda) -> Vec<ValId> {
+        lambda
+            .deps()
+            .iter()
+            .filter(|dep| dep.depth() != 0 && dep.depth() <= lambda.depth())
+            .cloned()
+            .collect()
+    }
+
+    /// Get the type of closures whose function has a given type
+    ///
+    /// A closure is a pair of a pointer to its function, which takes a pointer to the closure's environment as its
+    /// first parameter, and that pointer, as an `i8*`.
+    pub fn closure_type(&self, f: FunctionType<'ctx>) -> StructType<'ctx> {
+        self.context
+            .struct_type(&[self.fn_ptr_to(f).into(), self.byte_ptr().into()], false)
+    }
+
+    /// Build a closure in the current context
+    ///
+    /// If all captured values are constants, the closure is specialized to a function in which they are inlined,
+    /// returned as a `Val::Function`. Otherwise, the captured values are stored in an environment struct allocated on
+    /// the stack of the current function, and the closure is returned as a `Val::Closure`, which must not escape it.
+    /// Returns `Val::Unit` for closures with propositional results, as for lambdas.
+    pub fn build_closure(&mut self, lambda: &Lambda) -> Result<Val<'ctx>, Error> {
+        // Step 1: build the captured values in the current context
+        let mut captured: Vec<(ValId, Val<'ctx>)> = Vec::new();
+        for capture in Self::closure_captures(lambda) {
+            match self.build(&capture)? {
+                Val::Contr => return Ok(Val::Contr),
+                v => captured.push((capture, v)),
+            }
+        }
+
+        // Step 2: compute the representation of the closure's function
+        let prototype = match self.build_function_repr(lambda.get_ty())? {
+            Repr::Function(prototype) => prototype,
+            Repr::Prop => return Ok(Val::Unit),
+            _ => return Err(Error::InvalidFuncRepr),
+        };
+
+        // Step 3: specialize closures over constants
+        if captured.iter().all(|(_, v)| v.is_const()) {
+            let mapping = &prototype.mapping;
+            let result_fn = self.build_inlined_fn(
+                "__lambda",
+                lambda,
+                prototype.repr,
+                &mut |_cg, result_fn| {
+                    let mut locals = SymbolTable::default();
+                    for (capture, v) in captured.iter() {
+                        locals.insert(capture.clone(), v.clone());
+                    }
+                    (locals, param_values(mapping, result_fn, 0))
+                },
+            )?;
+            return Ok(Val::Function(result_fn));
+        }
+
+        // Step 4: store the captured values in a stack-allocated environment, with functions stored by pointer
+        let env_values: Vec<(ValId, BasicValueEnum<'ctx>, bool)> = captured
+            .iter()
+            .filter_map(|(capture, v)| match v {
+                Val::Value(v) => Some((capture.clone(), *v, false)),
+                Val::Function(f) => Some((
+                    capture.clone(),
+                    f.as_global_value().as_pointer_value().into(),
+                    false,
+                )),
+                Val::Closure(c) => Some((capture.clone(), (*c).into(), true)),
+                _ => None,
+            })
+            .collect();
+        let stored: Vec<BasicValueEnum<'ctx>> = env_values.iter().map(|(_, v, _)| *v).collect();
+        let is_closure: Vec<bool> = env_values.iter().map(|(_, _, c)| *c).collect();
+        let (env_ty, env_arg) = self.build_env(&stored[..])?;
+
+        // Step 5: build the closure's function, with the environment as a leading parameter
+        let fn_ty = self.env_fn_type(prototype.repr, 0);
+        let mapping = &prototype.mapping;
+        let closure_fn =
+            self.build_inlined_fn("__closure", lambda, fn_ty, &mut |cg, closure_fn| {
+                // Rebind the captured values to their values loaded from the environment
+                let mut locals = SymbolTable::default();
+                for (capture, v) in captured.iter() {
+                    if *v == Val::Unit {
+                        locals.insert(capture.clone(), Val::Unit);
+                    }
+                }
+                let loaded = cg.load_env(closure_fn, env_ty, &is_closure[..]);
+                for ((capture, _, _), capture_val) in env_values.iter().zip(loaded) {
+                    locals.insert(capture.clone(), capture_val);
+                }
+                (locals, param_values(mapping, closure_fn, 1))
+            })?;
+
+        // Step 6: pair the closure's function with its environment
+        Ok(Val::Closure(self.build_closure_pair(closure_fn, env_arg)))
+    }
+
+    /// Build the partial application of a lambda to fewer arguments than it has parameters
+    ///
+    /// The lambda is specialized to a function of its remaining parameters, in which constant arguments are inlined.
+    /// If all arguments are constants, this function is returned as a `Val::Function`. Otherwise, the non-constant
+    /// arguments, e.g. parameters of the enclosing function, are stored in an environment allocated on the stack of
+    /// the current function, as for `build_closure`, and the result is a `Val::Closure`, which must not escape it.
+    pub fn build_partial_app(
+        &mut self,
+        lambda: &Lambda,
+        args: &[ValId],
+    ) -> Result<Val<'ctx>, Error> {
+        let arity = lambda.def_region().len();
+        if args.len() >= arity {
+            return Err(Error::InternalError(
+                "Partial application must leave some parameters unapplied",
+            ));
+        }
+        let mut arg_values: Vec<Val<'ctx>> = Vec::with_capacity(args.len());
+        for arg in args {
+            match self.build(arg)? {
+                Val::Contr => return Ok(Val::Contr),
+                v => arg_values.push(v),
+            }
+        }
+        let prototype = match self.build_function_repr(lambda.get_ty())? {
+            Repr::Function(prototype) => prototype,
+            Repr::Prop => return Ok(Val::Unit),
+            _ => return Err(Error::InvalidFuncRepr),
+        };
+        // The applied parameters are a prefix of the parameters of the lambda's representation
+        let applied = prototype
+            .mapping
+            .iter()
+            .take(args.len())
+            .filter(|ix| *ix != ReprIx::Prop)
+            .count();
+        let mapping = &prototype.mapping;
+        let remaining_values = |f: FunctionValue<'ctx>, offset: u32| -> Vec<Val<'ctx>> {
+            mapping
+                .iter()
+                .skip(args.len())
+                .map(|ix| match ix {
+                    ReprIx::Prop => Val::Unit,
+                    ReprIx::Val(ix) => Val::Value(
+                        f.get_nth_param(ix as u32 - applied as u32 + offset)
+                            .expect("Index in vector is in bounds"),
+                    ),
+                })
+                .collect()
+        };
+
+        // Non-constant arguments are stored in an environment, by their position in the argument list
+        let runtime: Vec<(usize, BasicValueEnum<'ctx>, bool)> = arg_values
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, v)| match v {
+                v if v.is_const() => None,
+                Val::Value(v) => Some((ix, *v, false)),
+                Val::Closure(c) => Some((ix, (*c).into(), true)),
+                _ => None,
+            })
+            .collect();
+        if runtime.is_empty() {
+            let param_tys = prototype.repr.get_param_types();
+            let fn_ty = match prototype.repr.get_return_type() {
+                Some(ret) => ret.fn_type(&param_tys[applied..], false),
+                None => self
+                    .context
+                    .void_type()
+                    .fn_type(&param_tys[applied..], false),
+            };
+            let result_fn =
+                self.build_inlined_fn("__partial", lambda, fn_ty, &mut |_cg, result_fn| {
+                    let mut parameter_values = arg_values.clone();
+                    parameter_values.extend(remaining_values(result_fn, 0));
+                    (SymbolTable::default(), parameter_values)
+                })?;
+            return Ok(Val::Function(result_fn));
+        }
+
+        let stored: Vec<BasicValueEnum<'ctx>> = runtime.iter().map(|(_, v, _)| *v).collect();
+        let is_closure: Vec<bool> = runtime.iter().map(|(_, _, c)| *c).collect();
+        let (env_ty, env_arg) = self.build_env(&stored[..])?;
+        let fn_ty = self.env_fn_type(prototype.repr, applied);
+        let closure_fn =
+            self.build_inlined_fn("__partial", lambda, fn_ty, &mut |cg, closure_fn| {
+                // Rebind the non-constant arguments to their values loaded from the environment
+                let mut parameter_values = arg_values.clone();
+                let loaded = cg.load_env(closure_fn, env_ty, &is_closure[..]);
+                for ((arg_ix, _, _), arg_val) in runtime.iter().zip(loaded) {
+                    parameter_values[*arg_ix] = arg_val;
+                }
+                parameter_values.extend(remaining_values(closure_fn, 1));
+                (SymbolTable::default(), parameter_values)
+            })?;
+        Ok(Val::Closure(self.build_closure_pair(closure_fn, env_arg)))
+    }
+
+    /// Store values in an environment struct allocated on the stack of the current function
+    ///
+    /// Returns the type of the environment, along with a pointer to it as an `i8*`.
+    fn build_env(
+        &mut self,
+        values: &[BasicValueEnum<'ctx>],
+    ) -> Result<(StructType<'ctx>, PointerValue<'ctx>), Error> {
+        let tys: Vec<BasicTypeEnum<'ctx>> = values.iter().map(|v| v.get_type()).collect();
+        let env_ty = self.context.struct_type(&tys[..], false);
+        let env = self.build_stack_slot(env_ty.into())?;
+        for (ix, v) in values.iter().enumerate() {
+            let capture_ptr = self
+                .builder
+                .build_struct_gep(env, ix as u32, "capture_ptr")
+                .map_err(|_| Error::InternalError("Invalid index into closure environment"))?;
+            self.builder.build_store(capture_ptr, *v);
+        }
+        let env_arg = self.builder.build_pointer_cast(env, self.byte_ptr(), "env");
+        Ok((env_ty, env_arg))
+    }
+
+    /// Get the type of a function taking a pointer to a closure environment, followed by the parameters of a function
+    /// type after a given number of them
+    fn env_fn_type(&self, f: FunctionType<'ctx>, skip: usize) -> FunctionType<'ctx> {
+        let mut param_tys: Vec<BasicTypeEnum<'ctx>> = vec![self.byte_ptr().into()];
+        param_tys.extend(f.get_param_types().into_iter().skip(skip));
+        match f.get_return_type() {
+            Some(ret) => ret.fn_type(&param_tys[..], false),
+            None => self.context.void_type().fn_type(&param_tys[..], false),
+        }
+    }
+
+    /// Load the values stored in a closure environment, which is passed as the first parameter of a closure's function
+    ///
+    /// Values flagged in `is_closure` are loaded as closures, and the others as plain values.
+    fn load_env(
+        &self,
+        closure_fn: FunctionValue<'ctx>,
+        env_ty: StructType<'ctx>,
+        is_closure: &[bool],
+    ) -> Vec<Val<'ctx>> {
+        let env_param = closure_fn
+            .get_nth_param(0)
+            .expect("Closures take their environment as their first parameter")
+            .into_pointer_value();
+        let env_ptr_ty = self.ptr_to(env_ty, AddressSpace::Generic);
+        let env_ptr = self
+            .builder
+            .build_pointer_cast(env_param, env_ptr_ty, "env");
+        is_closure
+            .iter()
+            .enumerate()
+            .map(|(ix, is_closure)| {
+                let capture_ptr = self
+                    .builder
+                    .build_struct_gep(env_ptr, ix as u32, "capture_ptr")
+                    .expect("Internal error: valid index guaranteed by environment type");
+                let capture_val = self.builder.build_load(capture_ptr, "capture");
+                if *is_closure {
+                    Val::Closure(capture_val.into_struct_value())
+                } else {
+                    Val::Value(capture_val)
+                }
+            })
+            .collect()
+    }
+
+    /// Pair a closure's function with a pointer to its environment
+    fn build_closure_pair(
+        &self,
+        closure_fn: FunctionValue<'ctx>,
+        env_arg: PointerValue<'ctx>,
+    ) -> StructValue<'ctx> {
+        let closure_ty = self.closure_type(closure_fn.get_type());
+        let closure = self
+            .builder
+            .build_insert_value(
+                closure_ty.get_undef(),
+                closure_fn.as_global_value().as_pointer_value(),
+                0,
+                "closure",
+            )
+            .expect("Internal error: valid index guaranteed by closure type")
+            .into_struct_value();
+        self.builder
+            .build_insert_value(closure, env_arg, 1, "closure")
+            .expect("Internal error: valid index guaranteed by closure type")
+            .into_struct_value()
+    }
+
+    /// Build a new function of a given type by inlining a lambda into it
+    ///
+    /// `bind` is called with the builder positioned in the new function's entry block, and returns the local symbol
+    /// table to build the lambda's body in, along with the values of the lambda's parameters. On error, the new
+    /// function is removed from the module.
+    fn build_inlined_fn(
+        &mut self,
+        kind: &str,
+        lambda: &Lambda,
+        fn_ty: FunctionType<'ctx>,
+        bind: &mut dyn FnMut(
+            &mut Codegen<'ctx>,
+            FunctionValue<'ctx>,
+        ) -> (SymbolTable<ValId, Val<'ctx>>, Vec<Val<'ctx>>),
+    ) -> Result<FunctionValue<'ctx>, Error> {
+        let fn_counter = self.counter;
+        let name = self.fresh_name(kind);
+        let result_fn = self.define_function(&name, fn_ty, None);
+        let entry_bb = self.context.append_basic_block(result_fn, "entry");
+        self.builder.position_at_end(entry_bb);
+
+        let old_curr = self.curr;
+        let old_head = self.head;
+        let old_locals = self.locals.take();
+        let old_region = self.region.clone();
+        self.curr = Some(result_fn);
+        self.head = Some(entry_bb);
+        self.region = lambda.def_region().clone_region();
+
+        let (locals, parameter_values) = bind(self, result_fn);
+        self.locals = Some(locals);
+        let retv_build = match self.build_lambda_inline(lambda, &parameter_values[..]) {
+            Ok(Val::Value(v)) => {
+                self.builder.build_return(Some(&v));
+                Ok(())
+            }
+            Ok(Val::Function(f)) => {
+                self.builder
+                    .build_return(Some(&f.as_global_value().as_pointer_value()));
+                Ok(())
+            }
+            Ok(Val::Contr) => {
+                self.builder.build_unreachable();
+                Ok(())
+            }
+            // Closures are built with stack-allocated environments, so cannot be returned
+            Ok(Val::Closure(_)) => Err(Error::NotImplemented("Returning closures from functions")),
+            Ok(Val::Unit) => Err(Error::InternalError(
+                "Propositional value returned from a function with a non-propositional result",
+            )),
+            Err(err) => Err(err),
+        };
+
+        self.curr = old_curr;
+        self.head = old_head;
+        if let Some(head) = old_head {
+            self.builder.position_at_end(head);
+        }
+        self.locals = old_locals;
+        self.region = old_region;
+
+        if let Err(err) = retv_build {
+            // SAFETY: `result_fn` was created above, and is not referenced by any other compiled value
+            unsafe { result_fn.delete() };
+            if self.counter == fn_counter + 1 {
+                self.counter = fn_counter;
+            }
+            return Err(err);
+        }
+        Ok(result_fn)
+    }
+}
+
+/// Get the values of the parameters of a lambda from the parameters of a function, starting at a given offset
+fn param_values<'ctx>(mapping: &IxMap, f: FunctionValue<'ctx>, offset: u32) -> Vec<Val<'ctx>> {
+    mapping
+        .iter()
+        .map(|ix| match ix {
+            ReprIx::Prop => Val::Unit,
+            ReprIx::Val(ix) => Val::Value(
+                f.get_nth_param(ix as u32 + offset)
+                    .expect("Index in vector is in bounds"),
+            ),
+        })
+        .collect()
+}
@@ -0,0 +1,53 @@
+/*!
+Module constructors, for globals which must be initialized at load time
+*/
+use super::*;
+use inkwell::module::Linkage;
+use inkwell::types::BasicType;
+use inkwell::values::BasicValue;
+
+/// The name of the LLVM global listing a module's constructors
+pub const GLOBAL_CTORS: &str = "llvm.global_ctors";
+
+impl<'ctx> Codegen<'ctx> {
+    /// Register a function to be called when the module is loaded, appending it to `llvm.global_ctors`
+    ///
+    /// Constructors with lower priorities run first. `f` must take no parameters and return `void`, otherwise
+    /// `Error::InvalidFuncRepr` is returned.
+    pub fn add_global_ctor(&mut self, f: FunctionValue<'ctx>, priority: u32) -> Result<(), Error> {
+        let f_type = f.get_type();
+        if f_type.count_param_types() != 0 || f_type.get_return_type().is_some() {
+            return Err(Error::InvalidFuncRepr);
+        }
+        self.global_ctors.push((f, priority));
+
+        // `llvm.global_ctors` is rebuilt from scratch, since its length is part of its type
+        if let Some(old_ctors) = self.module.get_global(GLOBAL_CTORS) {
+            // SAFETY: `llvm.global_ctors` is only referenced by the module itself
+            unsafe { old_ctors.delete() };
+        }
+        let i32_type = self.context.i32_type();
+        let fn_ptr_type = self.fn_ptr_to(self.context.void_type().fn_type(&[], false));
+        let data_ptr_type = self.byte_ptr();
+        let entry_type = self.context.struct_type(
+            &[i32_type.into(), fn_ptr_type.into(), data_ptr_type.into()],
+            false,
+        );
+        let entries: Vec<_> = self
+            .global_ctors
+            .iter()
+            .map(|(f, priority)| {
+                entry_type.const_named_struct(&[
+                    i32_type.const_int(*priority as u64, false).into(),
+                    f.as_global_value().as_pointer_value().into(),
+                    data_ptr_type.const_null().into(),
+                ])
+            })
+            .collect();
+        let ctors_type = entry_type.array_type(entries.len() as u32);
+        let ctors = self.module.add_global(ctors_type, None, GLOBAL_CTORS);
+        ctors.set_linkage(Linkage::Appending);
+        ctors.set_initializer(&entry_type.const_array(&entries[..]).as_basic_value_enum());
+        Ok(())
+    }
+}
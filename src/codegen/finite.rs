@@ -2,20 +2,31 @@
 Code generation for finite `rain` types
 */
 use super::*;
+use inkwell::module::Linkage;
+use inkwell::targets::TargetData;
 use inkwell::types::IntType;
+use inkwell::values::{BasicValueEnum, IntValue};
 use rain_ir::primitive::finite::{Finite, Index};
 use std::convert::TryFrom;
 
 impl<'ctx> Codegen<'ctx> {
     /// Get the representation for a finite type
-    pub fn repr_finite(&mut self, f: &Finite) -> Repr<'ctx> {
+    ///
+    /// Returns `Error::UnrepresentableType` if the representation is wider than the target profile allows.
+    pub fn repr_finite(&mut self, f: &Finite) -> Result<Repr<'ctx>, Error> {
         let value: u128 = f.0;
-        if value == 0 {
+        let repr = if value == 0 {
             Repr::Empty
         } else if value == 1 {
             Repr::Prop
         } else if value == 2 {
             Repr::Type(self.context.bool_type().into())
+        } else if let Some(ptr_sized) = self.ptr_sized_index_type(value) {
+            Repr::Type(ptr_sized.into())
+        } else if self.packed_finite {
+            // The number of bits needed to hold the indices `0..value`, i.e. the ceiling of `log2(value)`
+            let width = 128 - (value - 1).leading_zeros();
+            Repr::Type(self.context.custom_width_int_type(width).into())
         } else if value < (1 << 8) {
             Repr::Type(self.context.i8_type().into())
         } else if value < (1 << 16) {
@@ -26,18 +37,47 @@ impl<'ctx> Codegen<'ctx> {
             Repr::Type(self.context.i64_type().into())
         } else {
             Repr::Type(self.context.i128_type().into())
+        };
+        self.check_int_width(repr)
+    }
+    /// Get the pointer-sized integer type of the target, if finite types are represented by it and it is wide enough
+    /// to hold indices into a finite type with `value` elements
+    fn ptr_sized_index_type(&self, value: u128) -> Option<IntType<'ctx>> {
+        if !self.ptr_sized_indices {
+            return None;
+        }
+        let target_data = TargetData::create(self.module.get_data_layout().as_str().to_str().ok()?);
+        let ty = self.context.ptr_sized_int_type(&target_data, None);
+        let width = ty.get_bit_width();
+        if width >= 128 || value <= (1 << width) {
+            Some(ty)
+        } else {
+            None
         }
     }
-    /// Compile a finite type into an LLVM value
-    pub fn build_finite(&mut self, f: &Finite) -> Val<'ctx> {
-        unimplemented!(
-            "Compile 128-bit LLVM integer constant for finite type {}",
-            f
-        )
+    /// Compile a finite type into an LLVM value, e.g. for passing it to a dependent function
+    ///
+    /// A finite type `#finite(n)` is encoded by its cardinality `n`, as an `i128` constant, since cardinalities may
+    /// be as large as `u128::MAX`. Target profiles capping integer widths below 128 bits use the widest integer type
+    /// they allow instead, returning `Error::UnrepresentableType` for cardinalities which do not fit.
+    pub fn build_finite(&mut self, f: &Finite) -> Result<Val<'ctx>, Error> {
+        let width = self.target_profile.max_int_width().min(128);
+        if width < 128 && f.0 >> width != 0 {
+            return Err(Error::UnrepresentableType);
+        }
+        let ty = self.context.custom_width_int_type(width);
+        if f.0 <= u64::MAX as u128 {
+            Ok(ty.const_int(f.0 as u64, false).into())
+        } else {
+            let limbs = [f.0 as u64, (f.0 >> 64) as u64];
+            Ok(ty.const_int_arbitrary_precision(&limbs).into())
+        }
     }
     /// Compile an index into an LLVM value
-    pub fn build_index(&mut self, i: &Index) -> Val<'ctx> {
-        match self.repr_finite(&i.get_ty()) {
+    ///
+    /// Indices are always in range of their finite type, since `rain` rejects out-of-range indices on construction.
+    pub fn build_index(&mut self, i: &Index) -> Result<Val<'ctx>, Error> {
+        Ok(match self.repr_finite(&i.get_ty())? {
             Repr::Empty => Val::Contr,
             Repr::Prop => Val::Unit,
             Repr::Type(t) => {
@@ -45,10 +85,144 @@ impl<'ctx> Codegen<'ctx> {
                 if i.ix() <= u64::MAX as u128 {
                     t.const_int(i.ix() as u64, false).into()
                 } else {
-                    unimplemented!("Big constants...")
+                    let limbs = [i.ix() as u64, (i.ix() >> 64) as u64];
+                    t.const_int_arbitrary_precision(&limbs).into()
                 }
             }
             _ => unreachable!(),
+        })
+    }
+    /// Build the successor of an index into a given finite type, wrapping around from the last index to zero
+    pub fn build_index_succ(&mut self, ix: Val<'ctx>, f: &Finite) -> Result<Val<'ctx>, Error> {
+        self.build_index_step(ix, f, true)
+    }
+    /// Build the predecessor of an index into a given finite type, wrapping around from zero to the last index
+    pub fn build_index_pred(&mut self, ix: Val<'ctx>, f: &Finite) -> Result<Val<'ctx>, Error> {
+        self.build_index_step(ix, f, false)
+    }
+    /// Step an index into a given finite type forwards or backwards by one, modulo the cardinality of the type
+    ///
+    /// Since the cardinality may not be a power of two, the step is computed one bit wider than the representation
+    /// and then reduced with `urem`, which avoids overflow for both directions.
+    fn build_index_step(
+        &mut self,
+        ix: Val<'ctx>,
+        f: &Finite,
+        succ: bool,
+    ) -> Result<Val<'ctx>, Error> {
+        let ix = match ix {
+            Val::Value(BasicValueEnum::IntValue(ix)) => ix,
+            // The only index of a unit type is its own successor and predecessor
+            Val::Unit | Val::Contr => return Ok(ix),
+            _ => return Err(Error::InternalError("Finite index must be an integer")),
+        };
+        if f.0 > u64::MAX as u128 {
+            return Err(Error::NotImplemented(
+                "Index arithmetic on finite types larger than 2^64",
+            ));
+        }
+        let n = f.0 as u64;
+        if n == 0 {
+            return Ok(Val::Contr);
+        }
+        let step = if succ { 1 } else { n - 1 };
+        let ty = ix.get_type();
+        if let Some(c) = ix.get_zero_extended_constant() {
+            let stepped = ((c as u128 + step as u128) % n as u128) as u64;
+            return Ok(ty.const_int(stepped, false).into());
+        }
+        let wide_ty = self.context.custom_width_int_type(ty.get_bit_width() + 1);
+        let wide = self.builder.build_int_z_extend(ix, wide_ty, "__ix_wide");
+        let stepped = self.builder.build_int_add(
+            wide,
+            wide_ty.const_int(step, false),
+            if succ { "__ix_succ" } else { "__ix_pred" },
+        );
+        let wrapped =
+            self.builder
+                .build_int_unsigned_rem(stepped, wide_ty.const_int(n, false), "__ix_wrap");
+        Ok(self
+            .builder
+            .build_int_truncate(wrapped, ty, "__ix_step")
+            .into())
+    }
+    /// Select among a list of constant entries using a finite index, via a constant lookup table
+    ///
+    /// When the index is itself a constant, the selected entry is returned directly. Otherwise, all entries must
+    /// compile to integer constants of the same type, which are emitted as a private constant global array indexed
+    /// by `ix` in the current basic block. Returns `Error::NotImplemented` for non-constant indices wider than 64 bits.
+    pub fn build_table_select(
+        &mut self,
+        ix: Val<'ctx>,
+        entries: &[ValId],
+    ) -> Result<Val<'ctx>, Error> {
+        let ix = match ix {
+            Val::Contr => return Ok(Val::Contr),
+            Val::Unit => {
+                return match entries {
+                    [entry] => self.build(entry),
+                    _ => Err(Error::InternalError(
+                        "Propositional index into a table with more than one entry",
+                    )),
+                }
+            }
+            Val::Value(BasicValueEnum::IntValue(ix)) => ix,
+            _ => return Err(Error::InternalError("Table index must be an integer")),
+        };
+        // Constant index: select the entry directly
+        if let Some(c) = ix.get_zero_extended_constant() {
+            return match entries.get(c as usize) {
+                Some(entry) => self.build(entry),
+                None => Ok(Val::Contr),
+            };
+        }
+        // GEP indices are at most 64 bits wide, and truncating a wider index could select the wrong entry
+        if ix.get_type().get_bit_width() > 64 {
+            return Err(Error::NotImplemented(
+                "Lookup table indices wider than 64 bits",
+            ));
+        }
+        let mut values: Vec<IntValue<'ctx>> = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match self.build(entry)? {
+                Val::Value(BasicValueEnum::IntValue(v)) if v.is_const() => values.push(v),
+                Val::Contr => return Ok(Val::Contr),
+                _ => {
+                    return Err(Error::NotImplemented(
+                        "Lookup tables of non-constant or non-integer entries",
+                    ))
+                }
+            }
+        }
+        let entry_ty = match values.first() {
+            Some(v) => v.get_type(),
+            None => return Ok(Val::Contr),
+        };
+        if values.iter().any(|v| v.get_type() != entry_ty) {
+            return Err(Error::InternalError(
+                "Lookup table entries must have the same type",
+            ));
         }
+        let table_ty = entry_ty.array_type(values.len() as u32);
+        let name = format!("{}__table_{}", self.name_prefix, self.table_counter);
+        self.table_counter += 1;
+        let table = self.module.add_global(table_ty, None, &name);
+        table.set_initializer(&entry_ty.const_array(&values[..]));
+        table.set_constant(true);
+        table.set_linkage(Linkage::Private);
+        // Zero-extend the index, since GEP indices are interpreted as signed
+        let i64_ty = self.context.i64_type();
+        let wide_ix = self
+            .builder
+            .build_int_z_extend_or_bit_cast(ix, i64_ty, "table_ix");
+        let zero = i64_ty.const_int(0, false);
+        let ptr = unsafe {
+            self.builder.build_in_bounds_gep(
+                table.as_pointer_value(),
+                &[zero, wide_ix],
+                "table_ptr",
+            )
+        };
+        Ok(self.builder.build_load(ptr, "table_sel").into())
     }
 }
@@ -4,17 +4,19 @@ Code generation for rain functions
 use super::*;
 use either::Either;
 use hayami_im_rc::SymbolStack;
-use inkwell::IntPredicate;
+use inkwell::comdat::ComdatSelectionKind;
 use inkwell::module::Linkage;
-use inkwell::types::{BasicType, BasicTypeEnum, IntType};
-use inkwell::values::{BasicValueEnum, FunctionValue, IntValue};
+use inkwell::types::{BasicType, BasicTypeEnum, FunctionType, IntType};
+use inkwell::values::{
+    BasicValueEnum, FunctionValue, InstructionOpcode, PointerValue, StructValue,
+};
+use inkwell::IntPredicate;
 use rain_ir::function::{lambda::Lambda, pi::Pi};
-use rain_ir::primitive::bits::BinOp;
+use rain_ir::primitive::logical;
 use rain_ir::region::Regional;
 use rain_ir::typing::Typed;
 use rain_ir::value::expr::Sexpr;
 use rain_ir::value::{Value, ValId};
-use std::convert::TryInto;
 use std::convert::TryFrom;
 use std::rc::Rc;
 
@@ -22,28 +24,139 @@ use std::rc::Rc;
 pub const DEFAULT_LAMBDA_LINKAGE: Option<Linkage> = None;
 
 impl<'ctx> Codegen<'ctx> {
-    /// Build a constant `rain` function
-    pub fn build_constant(&mut self, _ty: &Pi, _val: &ValId) -> FunctionValue<'ctx> {
-        unimplemented!()
+    /// Build a constant `rain` function of a given type, which ignores its arguments and always returns `val`
+    ///
+    /// Returns `Val::Unit` if the result type of `ty` is propositional, as for lambdas.
+    pub fn build_constant(&mut self, ty: &Pi, val: &ValId) -> Result<Val<'ctx>, Error> {
+        if val.depth() != 0 {
+            return Err(Error::NotImplemented(
+                "Constant functions returning non-constant values",
+            ));
+        }
+        let prototype = match self.build_function_repr(ty)? {
+            Repr::Function(prototype) => prototype,
+            Repr::Prop => return Ok(Val::Unit),
+            _ => return Err(Error::InvalidFuncRepr),
+        };
+        let fn_counter = self.counter;
+        let name = self.fresh_name("__const");
+        let result_fn = self.define_function(&name, prototype.repr, DEFAULT_LAMBDA_LINKAGE);
+        let entry_bb = self.context.append_basic_block(result_fn, "entry");
+        self.builder.position_at_end(entry_bb);
+
+        // Build the returned value in the new function, without access to the locals of the enclosing function, if any
+        let old_curr = self.curr;
+        let old_head = self.head;
+        let old_locals = self.locals.take();
+        self.curr = Some(result_fn);
+        self.head = Some(entry_bb);
+        let retv_build = match self.build(val) {
+            Ok(Val::Value(v)) => {
+                self.builder.build_return(Some(&v));
+                Ok(())
+            }
+            Ok(Val::Function(f)) => {
+                self.builder
+                    .build_return(Some(&f.as_global_value().as_pointer_value()));
+                Ok(())
+            }
+            Ok(Val::Contr) => {
+                self.builder.build_unreachable();
+                Ok(())
+            }
+            Ok(Val::Closure(_)) => Err(Error::NotImplemented("Returning closures from functions")),
+            Ok(Val::Unit) => Err(Error::InternalError(
+                "Propositional value returned from a constant function with a non-propositional result",
+            )),
+            Err(err) => Err(err),
+        };
+        self.curr = old_curr;
+        self.head = old_head;
+        if let Some(head) = old_head {
+            self.builder.position_at_end(head);
+        }
+        self.locals = old_locals;
+
+        if let Err(err) = retv_build {
+            // SAFETY: `result_fn` was created above, and is not referenced by any other compiled value
+            unsafe { result_fn.delete() };
+            if self.counter == fn_counter + 1 {
+                self.counter = fn_counter;
+            }
+            return Err(err);
+        }
+        Ok(Val::Function(result_fn))
     }
 
-    /// Build a function call with arguments
-    pub fn build_function_call(
+    /// Get the map from the parameters of a function type to the parameters of its representation
+    ///
+    /// Unlike `build_function_repr`, this does not depend on the representation of the result type, so is also
+    /// available for functions returning propositions.
+    pub fn param_mapping(&mut self, pi: &Pi) -> Result<IxMap, Error> {
+        let region = pi.def_region();
+        let mut mapping = IxMap::with_capacity(region.len() as u32);
+        let mut repr_ix = 0;
+        for input_ty in region.param_tys().iter() {
+            match self.repr(input_ty)? {
+                Repr::Prop => mapping.push_prop(),
+                _ => {
+                    mapping.push_ix(repr_ix);
+                    repr_ix += 1;
+                }
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Build the arguments of a function call, or the value of the call if it can be determined without calling
+    ///
+    /// Arguments mapped to `ReprIx::Prop` have no representation, and are skipped without being built.
+    fn build_call_args(
         &mut self,
-        f: FunctionValue<'ctx>,
+        mapping: &IxMap,
         args: &[ValId],
-    ) -> Result<Val<'ctx>, Error> {
-        let mut this_args: Vec<BasicValueEnum<'ctx>> = Vec::new();
-        for arg in args {
+    ) -> Result<Either<Vec<BasicValueEnum<'ctx>>, Val<'ctx>>, Error> {
+        if mapping.len() != args.len() {
+            return Err(Error::NotImplemented(
+                "Partial application of compiled functions",
+            ));
+        }
+        let mut this_args: Vec<BasicValueEnum<'ctx>> = Vec::with_capacity(args.len());
+        for (arg, mapped) in args.iter().zip(mapping.iter()) {
+            if mapped == ReprIx::Prop {
+                continue;
+            }
             match self.build(arg)? {
-                Val::Contr => return Ok(Val::Contr),
+                Val::Contr => return Ok(Either::Right(Val::Contr)),
                 Val::Unit => {
-                    return Ok(Val::Unit);
+                    return Err(Error::InternalError(
+                        "Unit argument to a parameter with a value representation",
+                    ))
                 }
                 Val::Value(v) => this_args.push(v),
-                Val::Function(_) => unimplemented!("Higher order functions not yet implemented!"),
+                Val::Function(_) => return Err(Error::NotImplemented("Function arguments")),
+                Val::Closure(_) => return Err(Error::NotImplemented("Closure arguments")),
             }
         }
+        Ok(Either::Left(this_args))
+    }
+
+    /// Build a function call with arguments, where `mapping` is the parameter mapping of the function's type
+    pub fn build_function_call(
+        &mut self,
+        f: FunctionValue<'ctx>,
+        mapping: &IxMap,
+        args: &[ValId],
+    ) -> Result<Val<'ctx>, Error> {
+        if self.flatten {
+            return Err(Error::NotImplemented(
+                "Flattening calls to compiled functions",
+            ));
+        }
+        let this_args = match self.build_call_args(mapping, args)? {
+            Either::Left(this_args) => this_args,
+            Either::Right(result) => return Ok(result),
+        };
         match self
             .builder
             .build_call::<FunctionValue<'ctx>>(f, &this_args[..], "call")
@@ -55,67 +168,155 @@ impl<'ctx> Codegen<'ctx> {
         }
     }
 
+    /// Build an indirect function call through a function pointer with arguments, where `mapping` is the parameter
+    /// mapping of the function's type
+    pub fn build_indirect_call(
+        &mut self,
+        f: PointerValue<'ctx>,
+        mapping: &IxMap,
+        args: &[ValId],
+    ) -> Result<Val<'ctx>, Error> {
+        if self.flatten {
+            return Err(Error::NotImplemented("Flattening indirect calls"));
+        }
+        let this_args = match self.build_call_args(mapping, args)? {
+            Either::Left(this_args) => this_args,
+            Either::Right(result) => return Ok(result),
+        };
+        match self
+            .builder
+            .build_call::<PointerValue<'ctx>>(f, &this_args[..], "icall")
+            .try_as_basic_value()
+            .left()
+        {
+            Some(b) => Ok(b.into()),
+            None => Ok(Val::Unit),
+        }
+    }
+
+    /// Build a call to a closure built with `build_closure` with arguments, where `mapping` is the parameter mapping of
+    /// the closure's type
+    pub fn build_closure_call(
+        &mut self,
+        closure: StructValue<'ctx>,
+        mapping: &IxMap,
+        args: &[ValId],
+    ) -> Result<Val<'ctx>, Error> {
+        if self.flatten {
+            return Err(Error::NotImplemented("Flattening closure calls"));
+        }
+        let mut this_args = match self.build_call_args(mapping, args)? {
+            Either::Left(this_args) => this_args,
+            Either::Right(result) => return Ok(result),
+        };
+        let f = self
+            .builder
+            .build_extract_value(closure, 0, "closure_fn")
+            .ok_or(Error::InternalError("Invalid closure"))?;
+        let env = self
+            .builder
+            .build_extract_value(closure, 1, "closure_env")
+            .ok_or(Error::InternalError("Invalid closure"))?;
+        let f = match f {
+            BasicValueEnum::PointerValue(f) => f,
+            _ => return Err(Error::InternalError("Invalid closure")),
+        };
+        this_args.insert(0, env);
+        match self
+            .builder
+            .build_call::<PointerValue<'ctx>>(f, &this_args[..], "ccall")
+            .try_as_basic_value()
+            .left()
+        {
+            Some(b) => Ok(b.into()),
+            None => Ok(Val::Unit),
+        }
+    }
+
     /// Build a function application
     pub fn build_app(&mut self, f: &ValId, args: &[ValId]) -> Result<Val<'ctx>, Error> {
         if args.is_empty() {
             return self.build(f);
         }
 
+        // Primitive lowerings, most recently registered first
+        for ix in (0..self.lowerings.len()).rev() {
+            let lowering = self.lowerings[ix].clone();
+            if let Some(result) = lowering.lower(self, f, args) {
+                return result;
+            }
+        }
+
         let f_enum = match f.as_enum() {
-            ValueEnum::Logical(l) => return self.build_logical_expr(*l, args),
-            ValueEnum::BinOp(b) => {
-                if args.len() < 3 {
-                    unimplemented!("Partial add application");
+            // Partially applied lambdas are specialized to functions of their remaining parameters
+            ValueEnum::Lambda(l) if args.len() < l.def_region().len() && !self.flatten => {
+                return self.build_partial_app(l, args);
+            }
+            // Over-applied lambdas, e.g. curried lambdas applied to all their arguments at once, are applied in stages
+            ValueEnum::Lambda(l) if args.len() > l.def_region().len() && !self.flatten => {
+                let arity = l.def_region().len();
+                let mut applied = Vec::with_capacity(arity + 1);
+                applied.push(f.clone());
+                applied.extend_from_slice(&args[..arity]);
+                let applied: ValId = Sexpr::try_new(applied.into())?.into();
+                return self.build_app(&applied, &args[arity..]);
+            }
+            // Fully applied lambdas, e.g. `let` bindings, are inlined rather than emitted as a function and called
+            ValueEnum::Lambda(l) if l.def_region().len() == args.len() || self.flatten => {
+                if l.def_region().len() != args.len() {
+                    return Err(Error::NotImplemented(
+                        "Flattening partial application of lambdas",
+                    ));
                 }
-                let arg_1 = match args[1].as_enum() {
-                    ValueEnum::Bits(b) => self.build_bits(b),
-                    _ => unimplemented!(),
-                };
-                let arg_2 = match args[2].as_enum() {
-                    ValueEnum::Bits(b) => self.build_bits(b),
-                    _ => unimplemented!(),
-                };
-                match (arg_1, arg_2) {
-                    (Val::Value(v1), Val::Value(v2)) => {
-                        let int_1: IntValue<'ctx> = v1.try_into().unwrap();
-                        let int_2: IntValue<'ctx> = v2.try_into().unwrap();
-                        let result = match b {
-                            BinOp::Add => self.builder.build_int_add(int_1, int_2, "__add"),
-                            BinOp::Sub => self.builder.build_int_sub(int_1, int_2, "__sub"),
-                            BinOp::Mul => self.builder.build_int_mul(int_1, int_2, "__mul"),
-                            BinOp::Mod => {
-                                self.builder.build_int_unsigned_rem(int_1, int_2, "__umod")
-                            }
-                        };
-                        return Ok(Val::Value(result.into()));
+                // Identity combinators return their argument unchanged, whatever its type, without building the
+                // other arguments, e.g. type arguments of a polymorphic identity
+                if let Some(ix) = identity_param(l) {
+                    return self.build(&args[ix]);
+                }
+                let mut parameter_values: Vec<Val<'ctx>> = Vec::with_capacity(args.len());
+                for arg in args {
+                    match self.build(arg)? {
+                        Val::Contr => return Ok(Val::Contr),
+                        v => parameter_values.push(v),
                     }
-                    _ => unimplemented!("Add only applies to bits"),
                 }
+                return self.build_lambda_inline(l, &parameter_values[..]);
             }
-            ValueEnum::Neg(_n) => {
-                if args.len() < 2 {
-                    unimplemented!("Partial negation application")
-                }
-                let arg = match args[1].as_enum() {
-                    ValueEnum::Bits(b) => self.build_bits(b),
-                    _ => unimplemented!(),
+            // Applied ternary nodes are inlined into the control flow of the current function, if any
+            ValueEnum::Ternary(t) if args.len() == 1 && self.curr.is_some() => {
+                // Branching on `#not x` is compiled as branching on `x` with the branches swapped
+                let (cond, inverted) = match args[0].as_enum() {
+                    ValueEnum::Sexpr(s) if s.len() == 2 => match s[0].as_enum() {
+                        ValueEnum::Logical(l) if *l == logical::Not => (&s[1], true),
+                        _ => (&args[0], false),
+                    },
+                    _ => (&args[0], false),
                 };
-                match arg {
-                    Val::Value(v) => {
-                        let int: IntValue<'ctx> = v.try_into().unwrap();
-                        let result = self.builder.build_int_neg(int, "__neg_");
-                        return Ok(Val::Value(result.into()));
-                    }
-                    _ => unimplemented!("Mul only applies to bits"),
+                let cond = match self.build(cond)? {
+                    Val::Value(BasicValueEnum::IntValue(cond)) => cond,
+                    Val::Contr => return Ok(Val::Contr),
+                    _ => return Err(Error::InternalError("Ternary condition must be a boolean")),
+                };
+                if let Some(c) = cond.get_zero_extended_constant() {
+                    return self.build(if (c != 0) != inverted {
+                        &t.high()
+                    } else {
+                        &t.low()
+                    });
                 }
-            },
+                return self.build_ternary_inline_with(t, cond, inverted);
+            }
             ValueEnum::Bits(b) => {
                 if args.len() != 1 {
-                    unimplemented!();
+                    return Err(Error::NotImplemented("Bit indexing with multiple indices"));
                 }
                 let ix = match args[0].as_enum() {
                     ValueEnum::Index(ix) => ix.ix() as usize,
-                    _ => unimplemented!(),
+                    _ => {
+                        return Err(Error::NotImplemented(
+                            "Bit indexing by non-constant indices",
+                        ))
+                    }
                 };
                 let built_bitvec = match self.build(&b.clone().into_val())? {
                     Val::Value(BasicValueEnum::IntValue(i)) => i,
@@ -127,7 +328,7 @@ impl<'ctx> Codegen<'ctx> {
                     ValueEnum::BitsTy(b) => b,
                     _ => unreachable!(),
                 };
-                let this_type = match self.repr_bitsty(b_ty) {
+                let this_type = match self.repr_bitsty(b_ty)? {
                     Repr::Type(t) => t,
                     _ => unreachable!(),
                 };
@@ -152,46 +353,77 @@ impl<'ctx> Codegen<'ctx> {
         let ty = f_enum.ty();
 
         match ty.as_enum() {
-            ValueEnum::Product(_p) => {
+            ValueEnum::Product(product) => {
                 match self.repr(&ty.clone_ty())? {
                     Repr::Prop => Ok(Val::Unit),
                     Repr::Empty => Ok(Val::Contr),
-                    Repr::Type(_t) => unimplemented!(),
-                    Repr::Function(_f) => unimplemented!(),
+                    Repr::Type(_) | Repr::Function(_) => Err(Error::InternalError(
+                        "Product types are represented by structs",
+                    )),
                     Repr::Product(p) => {
                         // Generate GEP.
                         if args.len() != 1 {
-                            unimplemented!();
+                            return Err(Error::NotImplemented("Projection with multiple indices"));
                         }
                         let ix = match args[0].as_enum() {
                             ValueEnum::Index(ix) => ix.ix() as usize,
-                            _ => unimplemented!(),
+                            _ => {
+                                return Err(Error::NotImplemented(
+                                    "Projection by non-constant indices",
+                                ))
+                            }
                         };
                         let repr_ix = if let Some(ix) = p.mapping.get(ix) {
                             ix
                         } else {
                             return Ok(Val::Unit);
                         };
-                        let struct_value = match self.build(f)? {
-                            Val::Value(BasicValueEnum::StructValue(s)) => s,
-                            Val::Contr => return Ok(Val::Contr),
-                            _ => panic!("Internal error: Repr::Product guarantees BasicValueEnum::StructValue")
-                        };
-                        let element = self
-                            .builder
-                            .build_extract_value(struct_value, repr_ix, "idx")
-                            .expect("Internal error: valid index guaranteed by IR construction");
-                        Ok(Val::Value(element))
+                        let member =
+                            match product.iter().nth(ix).map(|ty| self.repr(ty)).transpose()? {
+                                Some(Repr::Type(member)) => Some(member),
+                                _ => None,
+                            };
+                        let aggregate = self.build(f)?;
+                        let element = self.build_projection(aggregate, repr_ix)?;
+                        Ok(match member {
+                            Some(member) => self.build_field_narrowing(element, member),
+                            None => element,
+                        })
                     }
                 }
             },
             ValueEnum::Lambda(l) => match self.build_lambda(l)? {
                 Val::Contr => Ok(Val::Contr),
-                Val::Unit => unimplemented!("Unit lambda representation"), //TODO: think about this...
-                Val::Value(v) => unimplemented!("Value lambda representation {:?}", v),
-                Val::Function(f) => self.build_function_call(f, args),
+                // Lambdas with propositional results have propositional applications
+                Val::Unit => Ok(Val::Unit),
+                Val::Value(_) => Err(Error::InvalidFuncRepr),
+                Val::Function(f) => {
+                    let mapping = self.param_mapping(l.get_ty())?;
+                    self.build_function_call(f, &mapping, args)
+                }
+                Val::Closure(closure) => {
+                    let mapping = self.param_mapping(l.get_ty())?;
+                    self.build_closure_call(closure, &mapping, args)
+                }
             },
-            v => unimplemented!("Application of value {}", v),
+            ValueEnum::Pi(pi) => match self.build(f)? {
+                Val::Contr => Ok(Val::Contr),
+                Val::Unit => Ok(Val::Unit),
+                Val::Function(f) => {
+                    let mapping = self.param_mapping(pi)?;
+                    self.build_function_call(f, &mapping, args)
+                }
+                Val::Value(BasicValueEnum::PointerValue(p)) => {
+                    let mapping = self.param_mapping(pi)?;
+                    self.build_indirect_call(p, &mapping, args)
+                }
+                Val::Closure(closure) => {
+                    let mapping = self.param_mapping(pi)?;
+                    self.build_closure_call(closure, &mapping, args)
+                }
+                Val::Value(_) => Err(Error::InvalidFuncRepr),
+            },
+            _ => Err(Error::NotImplemented("Application of non-function values")),
         }
     }
 
@@ -213,20 +445,31 @@ impl<'ctx> Codegen<'ctx> {
         let gcr = self.curr_region.gcr(lambda)?;
 
         // Step 2: cache the old symbol table, and push a new one
-        let mut base = self.locals.as_ref();
         let dd = self.curr_region.depth() - gcr.depth();
-        for _ in 0..dd {
-            base = base
-                .expect("Too few layers in symbol table for region")
-                .prev();
-        }
-        let new_table = if let Some(base) = base {
-            //TODO: use `Rc`-reference for efficiency here, special casing dd-0
-            base.clone().extend()
+        let in_place = dd == 0 && self.locals.is_some();
+        let old_table = if in_place {
+            // Special case: we are inlining at the same region level, so just push a layer onto the current table
+            self.locals
+                .as_mut()
+                .expect("Checked above that a table is present")
+                .push();
+            None
         } else {
-            SymbolTable::default()
+            // At the global level there is no symbol table to walk, so we start from an empty one
+            let mut base = self.locals.as_ref();
+            for _ in 0..dd {
+                base = match base {
+                    Some(base) => base.prev(),
+                    None => break,
+                };
+            }
+            let new_table = if let Some(base) = base {
+                base.clone().extend()
+            } else {
+                SymbolTable::default()
+            };
+            self.locals.replace(new_table)
         };
-        let old_table = self.locals.replace(new_table);
 
         // Step 3: register parameters
         let locals = self.locals.as_mut().unwrap();
@@ -246,7 +489,14 @@ impl<'ctx> Codegen<'ctx> {
         let result = self.build(lambda.result());
 
         // Step 5: restore the old table
-        self.locals = old_table;
+        if in_place {
+            self.locals
+                .as_mut()
+                .expect("Nested builds restore the symbol table")
+                .pop();
+        } else {
+            self.locals = old_table;
+        }
 
         // Step 6: return
         result
@@ -264,7 +514,7 @@ impl<'ctx> Codegen<'ctx> {
         }
         let result_repr = match self.repr(result)? {
             Repr::Type(t) => t,
-            Repr::Function(_f) => unimplemented!(),
+            Repr::Function(f) => self.fn_ptr_to(f.repr).into(),
             Repr::Prop | Repr::Empty => return Ok(Repr::Prop),
             Repr::Product(p) => p.repr.into(),
         };
@@ -282,7 +532,7 @@ impl<'ctx> Codegen<'ctx> {
                         input_reprs.push(t);
                     }
                 }
-                Repr::Function(_) => unimplemented!(),
+                Repr::Function(_) => return Err(Error::NotImplemented("Function parameters")),
                 Repr::Prop => {
                     if !has_empty {
                         input_ixes.push_prop();
@@ -312,25 +562,108 @@ impl<'ctx> Codegen<'ctx> {
         })))
     }
 
+    /// Build a `void` LLVM function for a lambda with a propositional result
+    ///
+    /// The function takes the representable parameters of the lambda, and does nothing. It is named as any other
+    /// lambda would be, see `define_lambda_function`.
+    pub fn build_unit_function(&mut self, lambda: &Lambda) -> Result<FunctionValue<'ctx>, Error> {
+        let region = lambda.def_region();
+        let mut input_reprs: Vec<BasicTypeEnum> = Vec::with_capacity(region.len());
+        for input_ty in region.param_tys().iter() {
+            match self.repr(input_ty)? {
+                Repr::Type(t) => input_reprs.push(t),
+                Repr::Product(p) => input_reprs.push(p.repr.into()),
+                Repr::Function(_) => return Err(Error::NotImplemented("Function parameters")),
+                Repr::Prop | Repr::Empty => {}
+            }
+        }
+        let repr = self.context.void_type().fn_type(&input_reprs, false);
+        let (result_fn, defined) = self.define_lambda_function(lambda, repr)?;
+        if !defined {
+            return Ok(result_fn);
+        }
+        let entry_bb = self.context.append_basic_block(result_fn, "entry");
+        self.builder.position_at_end(entry_bb);
+        self.builder.build_return(None);
+        if let Some(head) = self.head {
+            self.builder.position_at_end(head);
+        }
+        Ok(result_fn)
+    }
+
+    /// Define the LLVM function emitted for a lambda with a given type, returning it along with whether it was newly
+    /// defined
+    ///
+    /// The function is named by `set_name` if a name was set for the lambda. Otherwise, deduplicated lambdas are
+    /// named by a stable hash of their definition, reusing an existing function of the same name, and other lambdas
+    /// are given a fresh name. Returns `Error::SymbolExists` if an existing function of the same name has a different
+    /// type.
+    fn define_lambda_function(
+        &mut self,
+        lambda: &Lambda,
+        ty: FunctionType<'ctx>,
+    ) -> Result<(FunctionValue<'ctx>, bool), Error> {
+        if let Some(name) = self.names.get(&lambda.clone().into_val()).cloned() {
+            let result_fn = self.define_function(&name, ty, DEFAULT_LAMBDA_LINKAGE);
+            return Ok((result_fn, true));
+        }
+        if !self.dedup_lambdas {
+            let name = self.fresh_name("__lambda");
+            let result_fn = self.define_function(&name, ty, DEFAULT_LAMBDA_LINKAGE);
+            return Ok((result_fn, true));
+        }
+        // Deduplicated lambdas are named by a stable hash of their definition, so the same lambda always gets the same
+        // symbol, both in this module and in others
+        let name = format!(
+            "{}__lambda_{:016x}",
+            self.name_prefix,
+            fxhash::hash64(&lambda.to_string())
+        );
+        if let Some(existing) = self.module.get_function(&name) {
+            // Reusing a function of another type, e.g. on a hash collision, would produce ill-typed calls
+            if existing.get_type() != ty {
+                return Err(Error::SymbolExists(name));
+            }
+            return Ok((existing, false));
+        }
+        let result_fn = self.define_function(&name, ty, Some(Linkage::LinkOnceODR));
+        let comdat = self.module.get_or_insert_comdat(&name);
+        comdat.set_selection_kind(ComdatSelectionKind::Any);
+        result_fn.as_global_value().set_comdat(comdat);
+        Ok((result_fn, true))
+    }
+
     /// Build a `rain` lambda function
+    ///
+    /// Compiled lambdas are cached in the global symbol table, so each lambda is only emitted once however many times
+    /// it is referenced. Lambdas capturing values from enclosing regions are compiled to closures with `build_closure`.
     pub fn build_lambda(&mut self, lambda: &Lambda) -> Result<Val<'ctx>, Error> {
+        if lambda.depth() != 0 {
+            return self.build_closure(lambda);
+        }
+        let key = lambda.clone().into_val();
+        if let Some(val) = self.globals.get(&key) {
+            return Ok(val.clone());
+        }
+        let val = self.build_lambda_uncached(lambda)?;
+        self.globals.insert(key, val.clone());
+        Ok(val)
+    }
+
+    /// Build a `rain` lambda function, ignoring the cache
+    fn build_lambda_uncached(&mut self, lambda: &Lambda) -> Result<Val<'ctx>, Error> {
         // Step 1: Cache and initialize region
-        let old_region = if lambda.depth() != 0 {
-            unimplemented!(
-                "Closures not implemented for lambda {} (depth = {})!",
-                lambda,
-                lambda.depth()
-            )
-        } else {
-            self.region.clone()
-        };
+        let old_region = self.region.clone();
 
         // Step 2: construct prototype, construct function, handle edge cases
         //TODO: general get_repr
         let prototype_or_return = match self.build_function_repr(lambda.get_ty()) {
             Ok(Repr::Function(prototype)) => Either::Left(prototype),
+            Ok(Repr::Prop) if self.emit_unit_functions => {
+                Either::Right(self.build_unit_function(lambda).map(Val::Function))
+            }
             Ok(Repr::Prop) => Either::Right(Ok(Val::Unit)),
-            Ok(r) => panic!("Invalid function representation: {:?}", r),
+            Ok(_) => Either::Right(Err(Error::InvalidFuncRepr)),
             Err(err) => Either::Right(Err(err)),
         };
 
@@ -344,14 +677,85 @@ impl<'ctx> Codegen<'ctx> {
             }
         };
 
-        let result_fn = self.module.add_function(
-            &format!("__lambda_{}", self.counter),
-            prototype.repr,
-            DEFAULT_LAMBDA_LINKAGE,
-        );
-        self.counter += 1;
+        let fn_counter = self.counter;
+        let (result_fn, defined) = match self.define_lambda_function(lambda, prototype.repr) {
+            Ok(defined) => defined,
+            Err(err) => {
+                self.region = old_region;
+                return Err(err);
+            }
+        };
+        if !defined {
+            self.region = old_region;
+            return Ok(Val::Function(result_fn));
+        }
+
+        // Step 3: build the body of the function
+        let retv_build = self.build_lambda_body(lambda, &prototype, result_fn);
 
-        // Step 3: set region, load parameter vector
+        // Step 4: Return, handling errors
+        // Reset region
+        self.region = old_region;
+        // On error, remove the partially built function from the module before bubbling up
+        if let Err(err) = retv_build {
+            // SAFETY: `result_fn` was created above, and is not referenced by any other compiled value
+            unsafe { result_fn.delete() };
+            // Only roll back the counter if no other functions were named in the meantime
+            if self.counter == fn_counter + 1 {
+                self.counter = fn_counter;
+            }
+            return Err(err);
+        }
+        // Thunks returning a constant are replaced by that constant, if requested
+        if self.fold_constant_thunks && lambda.def_region().len() == 0 {
+            if let Some(constant) = constant_return(result_fn) {
+                // SAFETY: `result_fn` was created above, and is not referenced by any other compiled value
+                unsafe { result_fn.delete() };
+                if self.counter == fn_counter + 1 {
+                    self.counter = fn_counter;
+                }
+                return Ok(Val::Value(constant));
+            }
+        }
+        // Otherwise, return successfully constructed function
+        Ok(Val::Function(result_fn))
+    }
+
+    /// Build the body of a `rain` lambda function into an existing, pre-declared LLVM function
+    ///
+    /// The function must have no basic blocks and a type matching the representation of the lambda's type.
+    /// This is useful for forward declarations, e.g. to set a custom name, linkage or attributes.
+    pub fn build_lambda_body_into(
+        &mut self,
+        lambda: &Lambda,
+        f: FunctionValue<'ctx>,
+    ) -> Result<(), Error> {
+        if lambda.depth() != 0 {
+            return Err(Error::NotImplemented("Closures"));
+        }
+        let prototype = match self.build_function_repr(lambda.get_ty())? {
+            Repr::Function(prototype) => prototype,
+            _ => return Err(Error::InvalidFuncRepr),
+        };
+        if f.get_type() != prototype.repr || f.get_first_basic_block().is_some() {
+            return Err(Error::InvalidFuncRepr);
+        }
+        let old_region = self.region.clone();
+        let result = self.build_lambda_body(lambda, &prototype, f);
+        self.region = old_region;
+        result
+    }
+
+    /// Build the body of a `rain` lambda function into `result_fn`, which must have type `prototype`
+    ///
+    /// Sets the current region to the lambda's definition region, which callers are responsible for resetting.
+    fn build_lambda_body(
+        &mut self,
+        lambda: &Lambda,
+        prototype: &FunctionRepr<'ctx>,
+        result_fn: FunctionValue<'ctx>,
+    ) -> Result<(), Error> {
+        // Step 1: set region, load parameter vector
         let region = lambda.def_region();
         self.region = region.clone_region();
 
@@ -371,44 +775,50 @@ impl<'ctx> Codegen<'ctx> {
             }
         }
 
-        // Step 4: add an entry basic block, registering it, and setting the builder position
+        // Step 2: add an entry basic block, registering it, and setting the builder position
         let entry_bb = self.context.append_basic_block(result_fn, "entry");
         self.builder.position_at_end(entry_bb);
 
-        // Step 5: cache old head, current, and locals, and set new values
+        // Step 3: cache old head, current, and locals, and set new values
         let old_curr = self.curr;
         let old_head = self.head;
         let old_locals = self.locals.take();
         self.curr = Some(result_fn);
         self.head = Some(entry_bb);
 
-        // Step 6: build the body of this lambda by "inlining it into itself"
+        // Step 4: build the body of this lambda by "inlining it into itself"
         let retv = self.build_lambda_inline(lambda, &parameter_values[..]);
 
-        // Step 7: if successful, build a return instruction
+        // Step 5: if successful, build a return instruction
         let retv_build = match retv {
             Ok(retv) => match retv {
+                // Closures are built with stack-allocated environments, so cannot be returned
+                Val::Closure(_) => Err(Error::NotImplemented("Returning closures from functions")),
                 Val::Value(v) => {
                     self.builder.build_return(Some(&v));
                     Ok(())
                 }
-                Val::Function(f) => unimplemented!(
-                    "Higher order functions not yet implemented, returned {:?}",
-                    f
-                ),
-                v @ Val::Unit | v @ Val::Contr => panic!(
-                    "Impossible representation {:?} for compiled function result",
-                    v
-                ),
+                // Functions are returned by pointer, as given by their result representation
+                Val::Function(f) => {
+                    self.builder
+                        .build_return(Some(&f.as_global_value().as_pointer_value()));
+                    Ok(())
+                }
+                // A contradictory result can never be returned, e.g. when a refinement is statically false
+                Val::Contr => {
+                    self.builder.build_unreachable();
+                    Ok(())
+                }
+                // A function with a value representation cannot have a propositional result
+                Val::Unit => Err(Error::InvalidFuncRepr),
             },
             Err(err) => Err(err),
         };
 
-        // Step 8: Cleanup: reset current, locals, head, and region
+        // Step 6: Cleanup: reset current, locals, and head
 
-        // Debug assertions: note that `head` and `locals` are allowed to change
-        debug_assert_eq!(&self.region, region);
-        debug_assert_eq!(self.curr, Some(result_fn));
+        // Invariant checks: note that `head` and `locals` are allowed to change
+        let state = self.check_function_state(region, result_fn);
 
         // Resets
         self.curr = old_curr;
@@ -417,12 +827,62 @@ impl<'ctx> Codegen<'ctx> {
             self.builder.position_at_end(head);
         }
         self.locals = old_locals;
-        self.region = old_region;
 
-        // Step 9: Return, handling errors
-        // Bubble up retv errors here;
-        retv_build?;
-        // Otherwise, return successfully constructed function
-        Ok(Val::Function(result_fn))
+        state.and(retv_build)
+    }
+
+    /// Check that the current region and function are `region` and `f`, as after building the body of `f`
+    ///
+    /// Violations are caught by debug assertions, or, if the `strict` feature is enabled, reported as
+    /// `Error::InternalError` in all builds, so that corrupted state causes a clean failure rather than miscompilation.
+    pub fn check_function_state(
+        &self,
+        region: &Region,
+        f: FunctionValue<'ctx>,
+    ) -> Result<(), Error> {
+        let consistent = self.region == *region && self.curr == Some(f);
+        if cfg!(feature = "strict") {
+            if !consistent {
+                return Err(Error::InternalError(
+                    "Inconsistent region or current function after building a function body",
+                ));
+            }
+        } else {
+            debug_assert_eq!(&self.region, region);
+            debug_assert_eq!(self.curr, Some(f));
+        }
+        Ok(())
+    }
+}
+
+/// Get the index of the parameter a lambda returns unchanged, if it is an identity combinator
+fn identity_param(l: &Lambda) -> Option<usize> {
+    let region = l.get_ty().def_region();
+    (0..region.len()).find(|&ix| {
+        region
+            .clone()
+            .param(ix)
+            .map(|param| ValId::from(param) == *l.result())
+            .unwrap_or(false)
+    })
+}
+
+/// Get the returned value of a function whose body consists only of returning a constant
+fn constant_return(f: FunctionValue) -> Option<BasicValueEnum> {
+    if f.count_basic_blocks() != 1 {
+        return None;
+    }
+    let ret = f.get_first_basic_block()?.get_first_instruction()?;
+    if ret.get_opcode() != InstructionOpcode::Return {
+        return None;
+    }
+    match ret.get_operand(0)? {
+        Either::Left(v) => match v {
+            BasicValueEnum::IntValue(i) if i.is_const() => Some(v),
+            BasicValueEnum::FloatValue(f) if f.is_const() => Some(v),
+            BasicValueEnum::PointerValue(p) if p.is_const() => Some(v),
+            _ => None,
+        },
+        Either::Right(_) => None,
     }
 }
@@ -0,0 +1,121 @@
+/*!
+C header generation for shimmed `rain` functions
+*/
+use super::*;
+use inkwell::types::{BasicTypeEnum, StructType};
+use std::fmt::Write;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Render a C header declaring the FFI shims of the given functions, each given by its symbol name and the
+    /// representation of the function it wraps
+    ///
+    /// Signatures follow the ABI of `build_shim`: struct and array parameters are passed by pointer, and struct and
+    /// array results are written through an additional trailing pointer parameter, with the shim returning `0` as an
+    /// `int32_t`. Array results are written through a pointer to their first element. Each struct is declared as
+    /// `struct <name>_<parameter index>`, or `struct <name>_ret` for results. Integers are declared as signed if
+    /// `signed_shim_ints` is set.
+    ///
+    /// Shims built with `build_shim_in_place` which write their result back through a parameter have no trailing
+    /// pointer parameter.
+    pub fn emit_c_header(&self, defs: &[(&str, &FunctionRepr)]) -> String {
+        let mut structs = String::new();
+        let mut decls = String::new();
+        for (name, repr) in defs.iter().copied() {
+            let mut params: Vec<String> = Vec::new();
+            let param_tys = repr.repr.get_param_types();
+            let in_place = self.in_place_shims.contains(name)
+                && param_tys
+                    .iter()
+                    .any(|ty| Some(*ty) == repr.repr.get_return_type());
+            for (i, ty) in param_tys.into_iter().enumerate() {
+                let c_ty = match ty {
+                    BasicTypeEnum::StructType(s) => {
+                        let struct_name = format!("{}_{}", name, i);
+                        render_c_struct(&mut structs, &struct_name, s, self.signed_shim_ints);
+                        format!("struct {} *", struct_name)
+                    }
+                    ty => match c_type_name(ty, self.signed_shim_ints) {
+                        pointer if pointer.ends_with('*') => pointer.to_string(),
+                        c_ty => format!("{} ", c_ty),
+                    },
+                };
+                params.push(format!("{}arg{}", c_ty, i));
+            }
+            let ret = match repr.repr.get_return_type() {
+                Some(BasicTypeEnum::StructType(_)) | Some(BasicTypeEnum::ArrayType(_))
+                    if in_place =>
+                {
+                    "int32_t"
+                }
+                Some(BasicTypeEnum::StructType(s)) => {
+                    let struct_name = format!("{}_ret", name);
+                    render_c_struct(&mut structs, &struct_name, s, self.signed_shim_ints);
+                    params.push(format!("struct {} *out", struct_name));
+                    "int32_t"
+                }
+                Some(BasicTypeEnum::ArrayType(a)) => {
+                    // C functions cannot return arrays, so the out pointer points to the first element
+                    let element = match a.get_element_type() {
+                        BasicTypeEnum::StructType(s) => {
+                            let struct_name = format!("{}_ret", name);
+                            render_c_struct(&mut structs, &struct_name, s, self.signed_shim_ints);
+                            format!("struct {}", struct_name)
+                        }
+                        ty => c_type_name(ty, self.signed_shim_ints).to_string(),
+                    };
+                    params.push(format!("{} *out", element));
+                    "int32_t"
+                }
+                Some(ty) => c_type_name(ty, self.signed_shim_ints),
+                None => "void",
+            };
+            if params.is_empty() {
+                params.push("void".into());
+            }
+            writeln!(decls, "{} {}({});", ret, name, params.join(", "))
+                .expect("Writing to a string cannot fail");
+        }
+        format!(
+            "#include <stdbool.h>\n#include <stdint.h>\n\n{}{}",
+            structs, decls
+        )
+    }
+}
+
+/// Get the name of the C type corresponding to a non-struct LLVM type, with integers signed if `signed` is set
+fn c_type_name(ty: BasicTypeEnum, signed: bool) -> &'static str {
+    match ty {
+        BasicTypeEnum::IntType(i) => match (i.get_bit_width(), signed) {
+            (1, _) => "bool",
+            (2..=8, false) => "uint8_t",
+            (2..=8, true) => "int8_t",
+            (9..=16, false) => "uint16_t",
+            (9..=16, true) => "int16_t",
+            (17..=32, false) => "uint32_t",
+            (17..=32, true) => "int32_t",
+            (33..=64, false) => "uint64_t",
+            (33..=64, true) => "int64_t",
+            (_, false) => "unsigned __int128",
+            (_, true) => "__int128",
+        },
+        BasicTypeEnum::FloatType(_) => "double",
+        _ => "void *",
+    }
+}
+
+/// Render the C definition of a struct, and of any nested structs, into `out`
+fn render_c_struct(out: &mut String, name: &str, s: StructType, signed: bool) {
+    let mut fields = String::new();
+    for (i, ty) in s.get_field_types().into_iter().enumerate() {
+        match ty {
+            BasicTypeEnum::StructType(nested) => {
+                let nested_name = format!("{}_f{}", name, i);
+                render_c_struct(out, &nested_name, nested, signed);
+                writeln!(fields, "    struct {} f{};", nested_name, i)
+            }
+            ty => writeln!(fields, "    {} f{};", c_type_name(ty, signed), i),
+        }
+        .expect("Writing to a string cannot fail");
+    }
+    writeln!(out, "struct {} {{\n{}}};\n", name, fields).expect("Writing to a string cannot fail");
+}
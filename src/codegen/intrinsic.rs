@@ -0,0 +1,56 @@
+/*!
+Code generation for calls to LLVM intrinsics
+*/
+use super::*;
+use inkwell::types::{BasicType, BasicTypeEnum};
+use inkwell::values::BasicValueEnum;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Build a call to an LLVM intrinsic, or any other external function, with a given name
+    ///
+    /// The function is declared on the module with a type computed from `ret` and the arguments if it does not
+    /// already exist. Propositional arguments are skipped, and a propositional `ret` yields a `void` function.
+    pub fn build_intrinsic(
+        &mut self,
+        name: &str,
+        ret: Repr<'ctx>,
+        args: &[Val<'ctx>],
+    ) -> Result<Val<'ctx>, Error> {
+        let mut this_args: Vec<BasicValueEnum<'ctx>> = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg {
+                Val::Value(v) => this_args.push(*v),
+                Val::Function(f) => this_args.push(f.as_global_value().as_pointer_value().into()),
+                Val::Closure(c) => this_args.push((*c).into()),
+                Val::Unit => {}
+                Val::Contr => return Ok(Val::Contr),
+            }
+        }
+        let intrinsic = match self.module.get_function(name) {
+            Some(intrinsic) => intrinsic,
+            None => {
+                let arg_tys: Vec<BasicTypeEnum<'ctx>> =
+                    this_args.iter().map(|arg| arg.get_type()).collect();
+                let ty = match ret {
+                    Repr::Type(t) => t.fn_type(&arg_tys[..], false),
+                    Repr::Product(p) => p.repr.fn_type(&arg_tys[..], false),
+                    Repr::Prop => self.context.void_type().fn_type(&arg_tys[..], false),
+                    Repr::Empty => return Ok(Val::Contr),
+                    Repr::Function(_) => {
+                        return Err(Error::NotImplemented("Intrinsics returning functions"))
+                    }
+                };
+                self.module.add_function(name, ty, None)
+            }
+        };
+        match self
+            .builder
+            .build_call(intrinsic, &this_args[..], "intrinsic")
+            .try_as_basic_value()
+            .left()
+        {
+            Some(b) => Ok(b.into()),
+            None => Ok(Val::Unit),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+/*!
+JIT compilation of `rain` functions
+*/
+use super::*;
+use inkwell::execution_engine::{FunctionLookupError, JitFunction, UnsafeFunctionPointer};
+use inkwell::OptimizationLevel;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Get the execution engine for this codegen context, creating it if it does not exist yet
+    ///
+    /// The engine takes ownership of the module, which can still be accessed and extended through this context.
+    /// Creating the engine fails if the module is already owned by another execution engine.
+    pub fn execution_engine(&mut self) -> Result<&ExecutionEngine<'ctx>, Error> {
+        if self.execution_engine.is_none() {
+            let engine = self
+                .module
+                .create_jit_execution_engine(OptimizationLevel::None)
+                .map_err(|err| Error::JitError(err.to_string()))?;
+            self.execution_engine = Some(engine);
+        }
+        Ok(self
+            .execution_engine
+            .as_ref()
+            .expect("Execution engine was just created"))
+    }
+
+    /// Build a given value, and JIT compile it to a function of type `F` using this context's execution engine
+    ///
+    /// # Safety
+    /// `F` must match the LLVM signature of the compiled function, as for `ExecutionEngine::get_function`.
+    pub unsafe fn jit_function<F: UnsafeFunctionPointer>(
+        &mut self,
+        v: &ValId,
+    ) -> Result<JitFunction<'ctx, F>, Error> {
+        let name = match self.build(v)? {
+            Val::Function(f) => f.get_name().to_string_lossy().into_owned(),
+            _ => return Err(Error::InvalidFuncRepr),
+        };
+        self.execution_engine()?;
+        let engine = self
+            .execution_engine
+            .as_ref()
+            .expect("Execution engine was just created");
+        match engine.get_function(&name) {
+            Err(FunctionLookupError::FunctionNotFound) => {}
+            result => return result.map_err(|err| Error::JitError(format!("{:?}", err))),
+        }
+        // Functions added after the module was last compiled are only picked up once it is handed to the engine again
+        engine
+            .remove_module(&self.module)
+            .map_err(|err| Error::JitError(format!("{:?}", err)))?;
+        engine
+            .add_module(&self.module)
+            .map_err(|_| Error::JitError("Failed to re-add module".into()))?;
+        engine
+            .get_function(&name)
+            .map_err(|err| Error::JitError(format!("{:?}", err)))
+    }
+}
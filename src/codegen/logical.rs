@@ -14,10 +14,28 @@ impl<'ctx> Codegen<'ctx> {
         self.context.bool_type().const_int(b as u64, false)
     }
 
+    /// Build a refinement, i.e. an assumption that a boolean condition holds
+    ///
+    /// A statically false refinement is a failed proof obligation, and hence compiles to `Val::Contr`, while a
+    /// statically true refinement compiles to `Val::Unit`. Otherwise, the condition is passed to `llvm.assume` in the
+    /// current basic block, so that later code may be optimized under it.
+    pub fn build_refinement(&mut self, cond: IntValue<'ctx>) -> Result<Val<'ctx>, Error> {
+        match cond.get_zero_extended_constant() {
+            Some(0) => Ok(Val::Contr),
+            Some(_) => Ok(Val::Unit),
+            None => self.build_intrinsic("llvm.assume", Repr::Prop, &[Val::Value(cond.into())]),
+        }
+    }
+
     /// Build a constant logical `rain` function
-    pub fn build_logical(&mut self, l: &Logical) -> FunctionValue<'ctx> {
+    pub fn build_logical(&mut self, l: &Logical) -> Result<FunctionValue<'ctx>, Error> {
         if let Some(b) = l.get_const() {
-            return self.build_constant(&LOGICAL_OP_TYS[l.arity() as usize - 1], &b.into());
+            return match self.build_constant(&LOGICAL_OP_TYS[l.arity() as usize - 1], &b.into())? {
+                Val::Function(f) => Ok(f),
+                _ => Err(Error::InternalError(
+                    "Constant logical operations compile to functions",
+                )),
+            };
         }
         match l.arity() {
             1 => match l.data() {
@@ -46,6 +64,13 @@ impl<'ctx> Codegen<'ctx> {
         if let Some(c) = l.get_const() {
             return Ok(self.build_bool(c).into());
         }
+        // Short-circuit on a constant first argument, avoiding building the rest of the expression if possible
+        if let ValueEnum::Bool(b) = args[0].as_enum() {
+            return match l.apply(*b) {
+                Either::Left(c) => Ok(self.build_bool(c).into()),
+                Either::Right(rest) => self.build_logical_expr(rest, &args[1..]),
+            };
+        }
         // Direct construction of non-constant operations
         match l_arity {
             0 => panic!("Zero arity logical operations ({}) are invalid!", l),
@@ -63,6 +88,15 @@ impl<'ctx> Codegen<'ctx> {
             }
             // Binary operations
             2 => {
+                // Peepholes for syntactically equal arguments
+                if args[0] == args[1] {
+                    if l == logical::And || l == logical::Or {
+                        return self.build(&args[0]);
+                    }
+                    if l == logical::Xor {
+                        return Ok(self.build_bool(false).into());
+                    }
+                }
                 if l == logical::And {
                     let lhs: IntValue = self.build(&args[0])?.try_into().expect("A boolean value");
                     let rhs: IntValue = self.build(&args[1])?.try_into().expect("A boolean value");
@@ -78,6 +112,29 @@ impl<'ctx> Codegen<'ctx> {
                     let rhs: IntValue = self.build(&args[1])?.try_into().expect("A boolean value");
                     return Ok(self.builder.build_xor(lhs, rhs, "pxor").into());
                 }
+                if let Some(op) = DerivedOp::classify(l) {
+                    let lhs: IntValue = self.build(&args[0])?.try_into().expect("A boolean value");
+                    let rhs: IntValue = self.build(&args[1])?.try_into().expect("A boolean value");
+                    let result = match op {
+                        DerivedOp::Nand => {
+                            let and = self.builder.build_and(lhs, rhs, "pand");
+                            self.builder.build_not(and, "pnand")
+                        }
+                        DerivedOp::Nor => {
+                            let or = self.builder.build_or(lhs, rhs, "por");
+                            self.builder.build_not(or, "pnor")
+                        }
+                        DerivedOp::Xnor => {
+                            let xor = self.builder.build_xor(lhs, rhs, "pxor");
+                            self.builder.build_not(xor, "pxnor")
+                        }
+                        DerivedOp::Implies => {
+                            let not_lhs = self.builder.build_not(lhs, "nlhs");
+                            self.builder.build_or(not_lhs, rhs, "pimplies")
+                        }
+                    };
+                    return Ok(result.into());
+                }
                 // Go to general strategy: split and evaluate
             }
             _ => {} // Go to general strategy: split and evaluate
@@ -113,3 +170,34 @@ impl<'ctx> Codegen<'ctx> {
         Ok(self.builder.build_or(is_high, is_low, "psplit").into())
     }
 }
+
+/// A binary logical operation lowered directly in terms of a primitive LLVM operation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum DerivedOp {
+    /// `not(and(a, b))`
+    Nand,
+    /// `not(or(a, b))`
+    Nor,
+    /// `not(xor(a, b))`
+    Xnor,
+    /// `or(not(a), b)`
+    Implies,
+}
+
+impl DerivedOp {
+    /// Classify a binary logical operation by the unary operations it reduces to when its first argument is fixed
+    fn classify(l: Logical) -> Option<DerivedOp> {
+        if l.arity() != 2 {
+            return None;
+        }
+        let high = l.apply(true).right()?;
+        let low = l.apply(false).right()?;
+        match (high.get_const(), low.get_const()) {
+            (None, Some(true)) if high == logical::Not => Some(DerivedOp::Nand),
+            (None, Some(true)) if high == logical::Id => Some(DerivedOp::Implies),
+            (Some(false), None) if low == logical::Not => Some(DerivedOp::Nor),
+            (None, None) if high == logical::Id && low == logical::Not => Some(DerivedOp::Xnor),
+            _ => None,
+        }
+    }
+}
@@ -3,23 +3,85 @@ The data-structures necessary for `rain` code generation
 */
 use super::repr::*;
 use crate::error::Error;
-use fxhash::FxHashMap as HashMap;
+use crate::lowering::{BitsLowering, LogicalLowering, PrimitiveLowering};
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use hayami_im_rc::{SymbolMap, SymbolTable};
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
+#[cfg(feature = "jit")]
+use inkwell::execution_engine::ExecutionEngine;
 use inkwell::module::Module;
+use inkwell::targets::{TargetData, TargetTriple};
+use inkwell::types::BasicTypeEnum;
 use inkwell::values::FunctionValue;
+use rain_ir::primitive::finite::Finite;
 use rain_ir::region::{Region, Regional};
 use rain_ir::value::{TypeId, ValId, ValueEnum};
+use std::cell::RefCell;
+use std::rc::Rc;
 
+mod alias;
+mod alloca;
 mod bits;
+mod bytes;
+mod closure;
+mod ctor;
 mod finite;
 mod function;
+mod header;
+mod intrinsic;
+#[cfg(feature = "jit")]
+mod jit;
 mod logical;
+mod pointer;
 mod shim;
+mod target;
 mod ternary;
 mod tuple;
+mod variant;
+
+/// The maximum width of an LLVM integer type
+pub const MAX_INT_BITS: u32 = (1 << 24) - 1;
+
+/// A target profile, configuring code generation for the constraints of a family of targets
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TargetProfile {
+    /// The host target, with no additional constraints
+    Native,
+    /// 32-bit WebAssembly, which has no native integers wider than 64 bits
+    Wasm32,
+}
+
+impl Default for TargetProfile {
+    fn default() -> TargetProfile {
+        TargetProfile::Native
+    }
+}
+
+impl TargetProfile {
+    /// The maximum width of integer types representable under this profile
+    pub fn max_int_width(self) -> u32 {
+        match self {
+            TargetProfile::Native => MAX_INT_BITS,
+            TargetProfile::Wasm32 => 64,
+        }
+    }
+    /// The target triple of this profile, if any
+    pub fn triple(self) -> Option<&'static str> {
+        match self {
+            TargetProfile::Native => None,
+            TargetProfile::Wasm32 => Some("wasm32-unknown-unknown"),
+        }
+    }
+    /// The data layout of this profile, if any
+    pub fn data_layout(self) -> Option<&'static str> {
+        match self {
+            TargetProfile::Native => None,
+            TargetProfile::Wasm32 => Some("e-m:e-p:32:32-i64:64-n32:64-S128"),
+        }
+    }
+}
 
 /**
 A `rain` code generation context for a given module.
@@ -45,8 +107,63 @@ pub struct Codegen<'ctx> {
     region: Region,
     /// Type representations
     reprs: HashMap<TypeId, Repr<'ctx>>,
+    /// Type representations shared with other `Codegen`s using the same LLVM context, if any
+    shared_reprs: Option<Rc<RefCell<HashMap<TypeId, Repr<'ctx>>>>>,
+    /// Values currently being built, used to detect cycles
+    building: HashSet<ValId>,
+    /// Types currently being represented, used to detect cycles
+    representing: HashSet<TypeId>,
     /// Function name counter.
     counter: usize,
+    /// Lookup table name counter, kept apart from the function name counter so tables never shift function names
+    table_counter: usize,
+    /// The prefix prepended to generated symbol names
+    name_prefix: String,
+    /// Symbol names overriding generated names for given top-level values
+    names: HashMap<ValId, String>,
+    /// Names of the LLVM struct types representing given product types
+    record_names: HashMap<TypeId, String>,
+    /// Whether to seed incrementally built aggregates with `zeroinitializer` rather than `undef`
+    zero_init_aggregates: bool,
+    /// Whether to emit `void` LLVM functions for lambdas with propositional results, rather than no function at all
+    emit_unit_functions: bool,
+    /// Whether to bracket stack slots with `llvm.lifetime.start` and `llvm.lifetime.end`
+    emit_lifetime_markers: bool,
+    /// Whether to represent integer types with their exact bit width, rather than rounding up to a standard width
+    exact_int_widths: bool,
+    /// Whether to represent `#bool` through the general finite type path, rather than special-casing it
+    bool_as_finite: bool,
+    /// Whether to emit lambdas as `linkonce_odr` functions in a comdat keyed by a stable hash of the lambda
+    dedup_lambdas: bool,
+    /// Whether to represent finite types with the fewest bits able to hold all their indices
+    packed_finite: bool,
+    /// Whether to represent finite types by the target's pointer-sized integer type, where wide enough
+    ptr_sized_indices: bool,
+    /// Whether narrow integers are treated as signed at FFI boundaries, rather than unsigned
+    signed_shim_ints: bool,
+    /// Whether to inline every application, rather than ever emitting a call
+    flatten: bool,
+    /// Whether booleans are stored in products as bytes, as in the C ABI, rather than as `i1`
+    byte_bool_fields: bool,
+    /// Whether nullary lambdas with constant bodies are compiled to their constant value, rather than a function
+    fold_constant_thunks: bool,
+    /// The target profile being compiled for
+    target_profile: TargetProfile,
+    /// The triple and data layout of the module before they were overridden by a target profile, if they were
+    native_target: Option<(String, String)>,
+    /// The CPU to attach to generated functions and target machines, if any
+    target_cpu: Option<String>,
+    /// The CPU features, e.g. `+avx2`, to attach to generated functions and target machines, if any
+    target_features: Option<String>,
+    /// The functions registered to run when the module is loaded, with their priorities
+    global_ctors: Vec<(FunctionValue<'ctx>, u32)>,
+    /// The names of the FFI shims which write their results back through one of their parameters
+    in_place_shims: HashSet<String>,
+    /// The registered lowerings of primitive functions, in order of registration
+    lowerings: Vec<Rc<dyn PrimitiveLowering<'ctx>>>,
+    /// The execution engine used to JIT compiled functions, created lazily since it takes ownership of the module
+    #[cfg(feature = "jit")]
+    execution_engine: Option<ExecutionEngine<'ctx>>,
     /// The LLVM module to which these values are being added
     module: Module<'ctx>,
     /// The IR builder for this codegen context
@@ -66,13 +183,57 @@ impl<'ctx> Codegen<'ctx> {
             head: None,
             region: Region::NULL,
             reprs: HashMap::default(),
+            shared_reprs: None,
+            building: HashSet::default(),
+            representing: HashSet::default(),
             counter: 0,
+            table_counter: 0,
+            name_prefix: String::new(),
+            names: HashMap::default(),
+            record_names: HashMap::default(),
+            zero_init_aggregates: false,
+            emit_unit_functions: false,
+            emit_lifetime_markers: false,
+            exact_int_widths: false,
+            bool_as_finite: false,
+            dedup_lambdas: false,
+            packed_finite: false,
+            ptr_sized_indices: false,
+            signed_shim_ints: false,
+            flatten: false,
+            byte_bool_fields: false,
+            fold_constant_thunks: false,
+            target_profile: TargetProfile::Native,
+            native_target: None,
+            target_cpu: None,
+            target_features: None,
+            global_ctors: Vec::new(),
+            in_place_shims: HashSet::default(),
+            lowerings: vec![Rc::new(LogicalLowering), Rc::new(BitsLowering)],
+            #[cfg(feature = "jit")]
+            execution_engine: None,
             module,
             builder: context.create_builder(),
             context,
         }
     }
 
+    /// Create a new code-generation context bound to a given LLVM `context` and `module`, sharing a cache of type
+    /// representations with any other code-generation contexts created with the same cache
+    ///
+    /// Since LLVM types are global to their context, all code-generation contexts sharing a cache must use the same
+    /// `context`. Changing a setting affecting type representations, e.g. `set_packed_finite`, detaches a code
+    /// generation context from the shared cache.
+    pub fn new_with_shared_reprs(
+        context: &'ctx Context,
+        module: Module<'ctx>,
+        reprs: Rc<RefCell<HashMap<TypeId, Repr<'ctx>>>>,
+    ) -> Codegen<'ctx> {
+        let mut codegen = Codegen::new(context, module);
+        codegen.shared_reprs = Some(reprs);
+        codegen
+    }
+
     /// Get the global compiled `rain` values
     ///
     /// See the documentation for the `globals` private member of `Codegen` for more information.
@@ -81,6 +242,51 @@ impl<'ctx> Codegen<'ctx> {
         &self.globals
     }
 
+    /// Get the compiled value for a given `rain` value, if it has already been compiled in the current context
+    ///
+    /// Values of depth zero are looked up in the global values first, falling back to the current local symbol table,
+    /// whereas other values are only looked up in the current local symbol table, if any.
+    pub fn get_compiled(&self, v: &ValId) -> Option<&Val<'ctx>> {
+        if v.depth() == 0 {
+            self.globals.get(v).or_else(|| self.locals.as_ref()?.get(v))
+        } else {
+            self.locals.as_ref()?.get(v)
+        }
+    }
+
+    /// Get a map from each compiled top-level `rain` function to its emitted symbol name
+    ///
+    /// This is useful, e.g., for generating linker scripts or C headers after compiling a batch of values.
+    pub fn symbol_map(&self) -> HashMap<ValId, String> {
+        self.globals
+            .iter()
+            .filter_map(|(v, val)| match val {
+                Val::Function(f) => Some((v.clone(), f.get_name().to_string_lossy().into_owned())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get the LLVM module to which values are being added
+    #[inline]
+    pub fn module(&self) -> &Module<'ctx> {
+        &self.module
+    }
+
+    /// Strip all debug information from the LLVM module, e.g. to produce a minimal release module
+    ///
+    /// This removes debug metadata attached to instructions and functions, as well as the `llvm.dbg.*` named
+    /// metadata, and returns whether anything was stripped.
+    pub fn strip_metadata(&self) -> bool {
+        self.module.strip_debug_info()
+    }
+
+    /// Get the IR builder for this codegen context
+    #[inline]
+    pub fn builder(&self) -> &Builder<'ctx> {
+        &self.builder
+    }
+
     /// Get the compiled representations in this context
     ///
     /// See the documentation for the `reprs` private member of `Codegen` for more information.
@@ -88,31 +294,486 @@ impl<'ctx> Codegen<'ctx> {
     pub fn reprs(&self) -> &HashMap<TypeId, Repr<'ctx>> {
         &self.reprs
     }
+    /// Get the cache of type representations shared with other code-generation contexts, if any
+    #[inline]
+    pub fn shared_reprs(&self) -> Option<&Rc<RefCell<HashMap<TypeId, Repr<'ctx>>>>> {
+        self.shared_reprs.as_ref()
+    }
+    /// Clear the cached type representations, detaching from any shared cache
+    ///
+    /// This is called whenever a setting affecting type representations changes, since the cached representations
+    /// were computed under the old setting.
+    fn clear_reprs(&mut self) {
+        self.reprs.clear();
+        self.shared_reprs = None;
+    }
+    /// Whether incrementally built aggregates are seeded with `zeroinitializer` rather than `undef`
+    #[inline]
+    pub fn zero_init_aggregates(&self) -> bool {
+        self.zero_init_aggregates
+    }
+
+    /// Set whether incrementally built aggregates are seeded with `zeroinitializer` rather than `undef`
+    ///
+    /// Defaults to `false`, i.e. `undef`, which gives the optimizer the most freedom.
+    #[inline]
+    pub fn set_zero_init_aggregates(&mut self, zero_init_aggregates: bool) {
+        self.zero_init_aggregates = zero_init_aggregates
+    }
+
+    /// Whether `void` LLVM functions are emitted for lambdas with propositional results
+    #[inline]
+    pub fn emit_unit_functions(&self) -> bool {
+        self.emit_unit_functions
+    }
+
+    /// Set whether `void` LLVM functions are emitted for lambdas with propositional results
+    ///
+    /// Defaults to `false`, in which case such lambdas compile to `Val::Unit` and no symbol is emitted.
+    #[inline]
+    pub fn set_emit_unit_functions(&mut self, emit_unit_functions: bool) {
+        self.emit_unit_functions = emit_unit_functions
+    }
+
+    /// Whether stack slots are bracketed with lifetime intrinsics
+    #[inline]
+    pub fn emit_lifetime_markers(&self) -> bool {
+        self.emit_lifetime_markers
+    }
+
+    /// Set whether stack slots are bracketed with lifetime intrinsics, allowing the optimizer to reuse them
+    #[inline]
+    pub fn set_emit_lifetime_markers(&mut self, emit_lifetime_markers: bool) {
+        self.emit_lifetime_markers = emit_lifetime_markers
+    }
+
+    /// Whether integer types are represented with their exact bit width
+    #[inline]
+    pub fn exact_int_widths(&self) -> bool {
+        self.exact_int_widths
+    }
+
+    /// Set whether integer types are represented with their exact bit width, e.g. `i24` for `#bits(24)`
+    ///
+    /// Defaults to `false`, in which case widths are rounded up to `i8`, `i16`, `i32`, `i64` or `i128`. Since this
+    /// changes type representations, this clears the representation cache.
+    pub fn set_exact_int_widths(&mut self, exact_int_widths: bool) {
+        if self.exact_int_widths != exact_int_widths {
+            self.clear_reprs();
+        }
+        self.exact_int_widths = exact_int_widths
+    }
+
+    /// Whether `#bool` is represented through the general finite type path
+    #[inline]
+    pub fn bool_as_finite(&self) -> bool {
+        self.bool_as_finite
+    }
+
+    /// Set whether `#bool` is represented through the general finite type path, i.e. as `#finite(2)`
+    ///
+    /// Both paths yield `i1`, but with this flag set the representation of `#bool` is looked up in, and stored in,
+    /// the representation cache like any other type. Defaults to `false`.
+    #[inline]
+    pub fn set_bool_as_finite(&mut self, bool_as_finite: bool) {
+        self.bool_as_finite = bool_as_finite
+    }
+
+    /// Whether lambdas are emitted as deduplicable `linkonce_odr` functions
+    #[inline]
+    pub fn dedup_lambdas(&self) -> bool {
+        self.dedup_lambdas
+    }
+
+    /// Set whether lambdas are emitted as deduplicable `linkonce_odr` functions
+    ///
+    /// When set, each lambda is named by a stable hash of its definition and placed in a comdat group of the same
+    /// name, allowing the linker to merge copies of the same lambda compiled into different object files. Defaults
+    /// to `false`.
+    #[inline]
+    pub fn set_dedup_lambdas(&mut self, dedup_lambdas: bool) {
+        self.dedup_lambdas = dedup_lambdas
+    }
+
+    /// Whether finite types are represented with the fewest bits able to hold all their indices
+    #[inline]
+    pub fn packed_finite(&self) -> bool {
+        self.packed_finite
+    }
+
+    /// Set whether finite types are represented with the fewest bits able to hold all their indices, e.g. `i3` for
+    /// `#finite(6)`
+    ///
+    /// Defaults to `false`, in which case widths are rounded up to `i8`, `i16`, `i32`, `i64` or `i128`. Booleans are
+    /// always represented as `i1`. Since this changes type representations, this clears the representation cache.
+    pub fn set_packed_finite(&mut self, packed_finite: bool) {
+        if self.packed_finite != packed_finite {
+            self.clear_reprs();
+        }
+        self.packed_finite = packed_finite
+    }
+
+    /// Whether finite types are represented by the target's pointer-sized integer type
+    #[inline]
+    pub fn ptr_sized_indices(&self) -> bool {
+        self.ptr_sized_indices
+    }
+
+    /// Set whether finite types are represented by the target's pointer-sized integer type, as given by the module's
+    /// data layout, e.g. to use indices directly as GEP operands
+    ///
+    /// Finite types with two or fewer elements, or too many elements to fit in a pointer-sized integer, keep their
+    /// usual representation. Since this changes type representations, this clears the representation cache.
+    pub fn set_ptr_sized_indices(&mut self, ptr_sized_indices: bool) {
+        if self.ptr_sized_indices != ptr_sized_indices {
+            self.clear_reprs();
+        }
+        self.ptr_sized_indices = ptr_sized_indices
+    }
+
+    /// Whether narrow integers are treated as signed at FFI boundaries
+    #[inline]
+    pub fn signed_shim_ints(&self) -> bool {
+        self.signed_shim_ints
+    }
+
+    /// Set whether narrow integers are treated as signed at FFI boundaries, e.g. for shims of functions on bit
+    /// vectors holding signed values
+    ///
+    /// This determines whether FFI shims sign or zero extend narrow integer parameters and results, and whether C
+    /// headers declare them as `intN_t` or `uintN_t`. Defaults to `false`, since finite types are unsigned.
+    #[inline]
+    pub fn set_signed_shim_ints(&mut self, signed_shim_ints: bool) {
+        self.signed_shim_ints = signed_shim_ints
+    }
+
+    /// Whether every application is inlined, rather than ever emitting a call
+    #[inline]
+    pub fn flatten(&self) -> bool {
+        self.flatten
+    }
+
+    /// Set whether every application is inlined, rather than ever emitting a call
+    ///
+    /// This makes each compiled function a single monolithic function, which is useful for measuring pure
+    /// computation cost. Applications which cannot be inlined, e.g. of function parameters, return
+    /// `Error::NotImplemented` rather than being compiled to calls. Defaults to `false`.
+    #[inline]
+    pub fn set_flatten(&mut self, flatten: bool) {
+        self.flatten = flatten
+    }
+
+    /// Whether booleans are stored in products as bytes, rather than as `i1`
+    #[inline]
+    pub fn byte_bool_fields(&self) -> bool {
+        self.byte_bool_fields
+    }
+
+    /// Set whether booleans are stored in products as bytes, as in the C ABI, rather than as `i1`
+    ///
+    /// Booleans are widened with `zext` when stored into a product, and truncated back to `i1` when projected out
+    /// of it. Defaults to `false`. Since this changes type representations, this clears the representation cache.
+    pub fn set_byte_bool_fields(&mut self, byte_bool_fields: bool) {
+        if self.byte_bool_fields != byte_bool_fields {
+            self.clear_reprs();
+        }
+        self.byte_bool_fields = byte_bool_fields
+    }
+
+    /// Whether nullary lambdas with constant bodies are compiled to their constant value
+    #[inline]
+    pub fn fold_constant_thunks(&self) -> bool {
+        self.fold_constant_thunks
+    }
+
+    /// Set whether nullary lambdas with constant bodies are compiled to their constant value, rather than a function
+    ///
+    /// If set, a thunk whose body compiles to an LLVM constant, e.g. `|| #add #bits(8)[2] #bits(8)[3]`, builds to
+    /// that constant, and no function is left in the module. Defaults to `false`.
+    #[inline]
+    pub fn set_fold_constant_thunks(&mut self, fold_constant_thunks: bool) {
+        self.fold_constant_thunks = fold_constant_thunks
+    }
+
+    /// Get the target profile being compiled for
+    #[inline]
+    pub fn target_profile(&self) -> TargetProfile {
+        self.target_profile
+    }
+
+    /// Set the target profile being compiled for, configuring the module's triple and data layout accordingly
+    ///
+    /// Types whose representation is too wide for the profile, e.g. `i128` on `TargetProfile::Wasm32`, have no
+    /// representation, and hence `Error::UnrepresentableType` is returned for them. Switching back to a profile
+    /// without its own triple, e.g. `TargetProfile::Native`, restores the module's original triple and data layout.
+    /// Since this changes type representations, this clears the representation cache.
+    pub fn set_target_profile(&mut self, target_profile: TargetProfile) {
+        match target_profile.triple() {
+            Some(triple) => {
+                if self.native_target.is_none() {
+                    let module = &self.module;
+                    let native_triple = module.get_triple().as_str().to_string_lossy().into_owned();
+                    let native_data_layout = module
+                        .get_data_layout()
+                        .as_str()
+                        .to_string_lossy()
+                        .into_owned();
+                    self.native_target = Some((native_triple, native_data_layout));
+                }
+                self.module.set_triple(&TargetTriple::create(triple));
+                if let Some(data_layout) = target_profile.data_layout() {
+                    self.module
+                        .set_data_layout(&TargetData::create(data_layout).get_data_layout());
+                }
+            }
+            None => {
+                if let Some((triple, data_layout)) = self.native_target.take() {
+                    self.module.set_triple(&TargetTriple::create(&triple));
+                    self.module
+                        .set_data_layout(&TargetData::create(&data_layout).get_data_layout());
+                }
+            }
+        }
+        if self.target_profile != target_profile {
+            self.clear_reprs();
+        }
+        self.target_profile = target_profile
+    }
+
+    /// Get the CPU attached to generated functions and target machines, if any
+    #[inline]
+    pub fn target_cpu(&self) -> Option<&str> {
+        self.target_cpu.as_deref()
+    }
+
+    /// Set the CPU attached to generated functions and target machines, e.g. `skylake`
+    ///
+    /// This only affects functions generated after it is set.
+    #[inline]
+    pub fn set_target_cpu(&mut self, target_cpu: Option<String>) {
+        self.target_cpu = target_cpu
+    }
+
+    /// Get the CPU features attached to generated functions and target machines, if any
+    #[inline]
+    pub fn target_features(&self) -> Option<&str> {
+        self.target_features.as_deref()
+    }
+
+    /// Set the CPU features attached to generated functions and target machines, as a comma-separated list such as
+    /// `+avx2,+fma`
+    ///
+    /// This only affects functions generated after it is set.
+    #[inline]
+    pub fn set_target_features(&mut self, target_features: Option<String>) {
+        self.target_features = target_features
+    }
+
+    /// Get the functions registered to run when the module is loaded, with their priorities, in order of registration
+    #[inline]
+    pub fn global_ctors(&self) -> &[(FunctionValue<'ctx>, u32)] {
+        &self.global_ctors
+    }
+
+    /// Register a lowering for primitive functions, taking precedence over all previously registered lowerings
+    ///
+    /// The lowerings for logical operations and bit vector arithmetic are registered by default.
+    pub fn register_lowering(&mut self, lowering: Rc<dyn PrimitiveLowering<'ctx>>) {
+        self.lowerings.push(lowering)
+    }
+
+    /// Get the prefix prepended to generated symbol names
+    #[inline]
+    pub fn name_prefix(&self) -> &str {
+        &self.name_prefix
+    }
+
+    /// Set the prefix prepended to generated symbol names, e.g. to avoid collisions between `rain` modules
+    #[inline]
+    pub fn set_name_prefix(&mut self, name_prefix: String) {
+        self.name_prefix = name_prefix
+    }
+
+    /// Get the symbol names overriding generated names for given top-level values
+    #[inline]
+    pub fn names(&self) -> &HashMap<ValId, String> {
+        &self.names
+    }
+
+    /// Set the symbol name of the function emitted for a given top-level value, e.g. a lambda, overriding the
+    /// generated name
+    ///
+    /// Names set this way are used as-is, without `name_prefix`. This only affects values which have not yet been
+    /// built.
+    #[inline]
+    pub fn set_name(&mut self, v: &ValId, name: String) {
+        self.names.insert(v.clone(), name);
+    }
+
+    /// Get the names of the LLVM struct types representing given product types
+    #[inline]
+    pub fn record_names(&self) -> &HashMap<TypeId, String> {
+        &self.record_names
+    }
+
+    /// Set the name of the LLVM struct type representing a given product type, which is otherwise represented by an
+    /// anonymous literal struct type
+    ///
+    /// This only affects types whose representation has not yet been computed.
+    #[inline]
+    pub fn set_record_name(&mut self, ty: &TypeId, name: String) {
+        self.record_names.insert(ty.clone(), name);
+    }
+
+    /// Generate a fresh symbol name of a given kind, e.g. `__lambda`, incrementing the name counter
+    fn fresh_name(&mut self, kind: &str) -> String {
+        let name = format!("{}{}_{}", self.name_prefix, kind, self.counter);
+        self.counter += 1;
+        name
+    }
+
     /// Get the representation for a given type, if any
     pub fn repr(&mut self, t: &TypeId) -> Result<Repr<'ctx>, Error> {
         // Special cases
         if let ValueEnum::BoolTy(_) = t.as_enum() {
-            return Ok(Repr::Type(self.context.bool_type().into()));
+            if !self.bool_as_finite {
+                return Ok(Repr::Type(self.context.bool_type().into()));
+            }
         }
         // Cached case
         if let Some(repr) = self.reprs.get(t) {
             return Ok(repr.clone());
         }
-        // General case
-        let r = match t.as_enum() {
-            ValueEnum::Finite(f) => self.repr_finite(f),
-            ValueEnum::Product(p) => self.repr_product(p)?,
-            ValueEnum::BoolTy(_) => unreachable!(),
-            _ => unimplemented!("Representation for rain type {} is not implemented", t),
-        };
+        let shared = self
+            .shared_reprs
+            .as_ref()
+            .and_then(|shared| shared.borrow().get(t).cloned());
+        if let Some(repr) = shared {
+            self.reprs.insert(t.clone(), repr.clone());
+            return Ok(repr);
+        }
+        // General case, guarding against cycles in malformed value graphs, which would otherwise recurse forever
+        if !self.representing.insert(t.clone()) {
+            return Err(Error::InternalError("cyclic value graph"));
+        }
+        let r = self.repr_uncached(t);
+        self.representing.remove(t);
+        let r = r?;
+        if let Some(shared) = self.shared_reprs.as_ref() {
+            shared.borrow_mut().insert(t.clone(), r.clone());
+        }
         let old = self.reprs.insert(t.clone(), r.clone());
         // We just checked above that the type has no representation!
         // TODO: think about this: perhaps compiling the type has led to it getting a representation...
-        // consider an in-progress compilation map to avoid infinite loops...
-        // No wait, this should be prevented by the DAG-property of the `rain` graph...
         debug_assert_eq!(old, None);
         Ok(r)
     }
+    /// Compute the representation for a given type, ignoring the cache
+    fn repr_uncached(&mut self, t: &TypeId) -> Result<Repr<'ctx>, Error> {
+        let r = match t.as_enum() {
+            ValueEnum::Finite(f) => self.repr_finite(f)?,
+            ValueEnum::BitsTy(b) => self.repr_bitsty(b)?,
+            ValueEnum::Product(p) => match self.record_names.get(t).cloned() {
+                Some(name) => self.repr_record(p, &name)?,
+                None => self.repr_product(p)?,
+            },
+            ValueEnum::Pi(p) => self.build_function_repr(p)?,
+            ValueEnum::BoolTy(_) => self.repr_finite(&Finite(2))?,
+            _ => return Err(Error::UnrepresentableType),
+        };
+        Ok(r)
+    }
+    /// Check that a representation is no wider than the target profile allows, returning
+    /// `Error::UnrepresentableType` otherwise
+    fn check_int_width(&self, r: Repr<'ctx>) -> Result<Repr<'ctx>, Error> {
+        if let Repr::Type(BasicTypeEnum::IntType(i)) = r {
+            if i.get_bit_width() > self.target_profile.max_int_width() {
+                return Err(Error::UnrepresentableType);
+            }
+        }
+        Ok(r)
+    }
+    /// Build a given value under a provided table of local values
+    ///
+    /// This allows compiling open terms by binding their free variables to known values. The previous local values
+    /// are restored afterwards.
+    pub fn build_with_locals(
+        &mut self,
+        v: &ValId,
+        locals: SymbolTable<ValId, Val<'ctx>>,
+    ) -> Result<Val<'ctx>, Error> {
+        let old_locals = self.locals.replace(locals);
+        let result = self.build(v);
+        self.locals = old_locals;
+        result
+    }
+    /// Build a given value in a new layer of the local symbol table, if any, which is discarded afterwards
+    ///
+    /// This is used to build values in basic blocks which need not dominate later code, e.g. the branches of a ternary
+    /// node, so that local values computed there are not reused outside of them.
+    pub fn build_scoped(&mut self, v: &ValId) -> Result<Val<'ctx>, Error> {
+        self.with_scope(|codegen| codegen.build(v))
+    }
+    /// Run a code generation operation in a new layer of the local symbol table, if any, which is discarded afterwards
+    pub fn with_scope<T>(
+        &mut self,
+        op: impl FnOnce(&mut Codegen<'ctx>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let scoped = match self.locals.as_mut() {
+            Some(locals) => {
+                locals.push();
+                true
+            }
+            None => false,
+        };
+        let result = op(self);
+        if scoped {
+            self.locals
+                .as_mut()
+                .expect("Nested builds restore the symbol table")
+                .pop();
+        }
+        result
+    }
+    /// Build a given value under a provided table of local values, appending any instructions to the end of the last
+    /// basic block of an existing LLVM function
+    ///
+    /// Returns `Error::NoCurrentBlock` if `f` has no basic blocks. The previous function, head and local values are
+    /// restored afterwards, and the builder is left at the end of the block where the value was computed.
+    pub fn build_in_function(
+        &mut self,
+        f: FunctionValue<'ctx>,
+        v: &ValId,
+        locals: SymbolTable<ValId, Val<'ctx>>,
+    ) -> Result<Val<'ctx>, Error> {
+        self.with_function(f, |codegen| codegen.build_with_locals(v, locals))
+    }
+    /// Run a code generation operation, appending any instructions to the end of the last basic block of an existing
+    /// LLVM function
+    ///
+    /// Returns `Error::NoCurrentBlock` if `f` has no basic blocks. The previous function and head are restored
+    /// afterwards, and the builder is left at the end of the block where the operation finished.
+    pub fn with_function<T>(
+        &mut self,
+        f: FunctionValue<'ctx>,
+        op: impl FnOnce(&mut Codegen<'ctx>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let block = f.get_last_basic_block().ok_or(Error::NoCurrentBlock)?;
+        let old_curr = self.curr.replace(f);
+        let old_head = self.head.replace(block);
+        self.builder.position_at_end(block);
+        let result = op(self);
+        self.curr = old_curr;
+        self.head = old_head;
+        result
+    }
+    /// Build a given value, and return the textual LLVM IR of the module
+    ///
+    /// This is mainly useful for golden testing of generated code.
+    pub fn compile_to_string(&mut self, v: &ValId) -> Result<String, Error> {
+        self.build(v)?;
+        Ok(self.module.print_to_string().to_string())
+    }
     /// Build a given value
     pub fn build(&mut self, v: &ValId) -> Result<Val<'ctx>, Error> {
         let depth = v.depth();
@@ -121,6 +782,10 @@ impl<'ctx> Codegen<'ctx> {
             if let Some(val) = self.globals().get(v) {
                 return Ok(val.clone());
             }
+            // Constants without a global definition may be bound to the results of computations in the current function
+            if let Some(val) = self.locals.as_ref().and_then(|locals| locals.get(v)) {
+                return Ok(val.clone());
+            }
         } else if let Some(this_table) = self.locals.as_ref() {
             if let Some(val) = this_table.get(v) {
                 return Ok(val.clone());
@@ -129,28 +794,42 @@ impl<'ctx> Codegen<'ctx> {
             panic!("A symbol table should be already pushed when compiling a value in a function");
         }
 
+        // Guard against cycles in malformed value graphs, which would otherwise recurse forever
+        if !self.building.insert(v.clone()) {
+            return Err(Error::InternalError("cyclic value graph"));
+        }
+        let val = self.build_uncached(v);
+        self.building.remove(v);
+        let val = val?;
+
+        // Constants which were built from values local to the current function must not leak into other functions
+        if depth == 0 && (val.is_const() || self.locals.is_none()) {
+            self.globals.insert(v.clone(), val.clone());
+        } else if let Some(this_table) = self.locals.as_mut() {
+            this_table.insert(v.clone(), val.clone());
+        } else {
+            panic!("A symbol table should be already pushed when compiling a value in a function");
+        }
+        Ok(val)
+    }
+    /// Build a given value, ignoring the cache
+    fn build_uncached(&mut self, v: &ValId) -> Result<Val<'ctx>, Error> {
         let val = match v.as_enum() {
             ValueEnum::Bool(b) => self.build_bool(*b).into(),
             ValueEnum::Lambda(l) => self.build_lambda(l)?,
-            ValueEnum::Logical(l) => self.build_logical(l).into(),
+            ValueEnum::Logical(l) => self.build_logical(l)?.into(),
             ValueEnum::Sexpr(s) => self.build_sexpr(s)?,
             ValueEnum::Tuple(t) => self.build_tuple(t)?,
             ValueEnum::Product(p) => self.build_product(p)?,
-            ValueEnum::Parameter(_) => panic!("Unregistered parameter {}, depth = {}!", v, depth),
-            ValueEnum::Finite(f) => self.build_finite(f),
-            ValueEnum::Index(i) => self.build_index(i),
+            ValueEnum::Parameter(_) => {
+                panic!("Unregistered parameter {}, depth = {}!", v, v.depth())
+            }
+            ValueEnum::Finite(f) => self.build_finite(f)?,
+            ValueEnum::Index(i) => self.build_index(i)?,
             ValueEnum::Ternary(t) => self.build_ternary(t)?,
-            ValueEnum::Bits(t) => self.build_bits(t),
+            ValueEnum::Bits(t) => self.build_bits(t)?,
             _ => unimplemented!("Building value {}", v),
         };
-
-        if depth == 0 {
-            self.globals.insert(v.clone(), val.clone());
-        } else if let Some(this_table) = self.locals.as_mut() {
-            this_table.insert(v.clone(), val.clone());
-        } else {
-            panic!("A symbol table should be already pushed when compiling a value in a function");
-        }
         Ok(val)
     }
 }
@@ -0,0 +1,26 @@
+/*!
+Pointer types for compiled `rain` values
+*/
+use super::*;
+use inkwell::types::{BasicType, FunctionType, PointerType};
+use inkwell::AddressSpace;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Get the type of pointers to values of a given type in a given address space
+    ///
+    /// All pointer types emitted during code generation are created through this method, so that switching to opaque
+    /// pointers, where every pointer in an address space has the same type `ptr`, only requires changing this method.
+    pub fn ptr_to<T: BasicType<'ctx>>(&self, ty: T, space: AddressSpace) -> PointerType<'ctx> {
+        ty.ptr_type(space)
+    }
+
+    /// Get the type of pointers to functions of a given type
+    pub fn fn_ptr_to(&self, ty: FunctionType<'ctx>) -> PointerType<'ctx> {
+        ty.ptr_type(AddressSpace::Generic)
+    }
+
+    /// Get the type of untyped pointers, i.e. `i8*`, or `ptr` under opaque pointers
+    pub fn byte_ptr(&self) -> PointerType<'ctx> {
+        self.ptr_to(self.context.i8_type(), AddressSpace::Generic)
+    }
+}
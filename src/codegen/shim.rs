@@ -1,28 +1,83 @@
 use super::*;
+use inkwell::attributes::{Attribute, AttributeLoc};
 use inkwell::module::Linkage;
 use inkwell::types::{BasicType, BasicTypeEnum};
 use inkwell::values::BasicValueEnum;
 use inkwell::AddressSpace;
 
 impl<'ctx> Codegen<'ctx> {
+    /// Get the attribute with which an integer of a given type is extended when passed across the FFI boundary, if
+    /// any
+    ///
+    /// Integers narrower than 32 bits are sign extended if `signed_shim_ints` is set and zero extended otherwise,
+    /// with booleans always being zero extended.
+    fn int_extension_attribute(&self, ty: BasicTypeEnum<'ctx>) -> Option<Attribute> {
+        let width = match ty {
+            BasicTypeEnum::IntType(i) => i.get_bit_width(),
+            _ => return None,
+        };
+        let kind = if width >= 32 {
+            return None;
+        } else if width > 1 && self.signed_shim_ints {
+            "signext"
+        } else {
+            "zeroext"
+        };
+        Some(
+            self.context
+                .create_enum_attribute(Attribute::get_named_enum_kind_id(kind), 0),
+        )
+    }
+
     /// Implement FFI shim
+    /// Struct and array arguments are passed by pointer.
     /// The result function will have a additional argument of type pointer
-    /// to the original return type of f if the return type of f is a struct.
+    /// to the original return type of f if the return type of f is a struct or an array.
     /// In that case, return result will be written to the pointer
     pub fn build_shim(
         &mut self,
         f: FunctionValue<'ctx>,
         name: &str,
         linkage: Option<Linkage>,
+    ) -> FunctionValue {
+        self.build_shim_with(f, name, linkage, false)
+    }
+
+    /// Implement an FFI shim for an in-place transform, which writes its result back through one of its parameters
+    ///
+    /// If `f` takes a struct of its return type as a parameter, no additional argument is added, and the result is
+    /// instead written back through the pointer passed for the first such parameter, avoiding a copy. Otherwise, this
+    /// behaves like `build_shim`. The shim is recorded as in-place, so that `emit_c_header` declares it accordingly.
+    pub fn build_shim_in_place(
+        &mut self,
+        f: FunctionValue<'ctx>,
+        name: &str,
+        linkage: Option<Linkage>,
+    ) -> FunctionValue {
+        self.in_place_shims.insert(name.to_string());
+        self.build_shim_with(f, name, linkage, true)
+    }
+
+    /// Implement an FFI shim, writing struct results back through a matching parameter if `in_place` is set
+    fn build_shim_with(
+        &mut self,
+        f: FunctionValue<'ctx>,
+        name: &str,
+        linkage: Option<Linkage>,
+        in_place: bool,
     ) -> FunctionValue {
         let f_type = f.get_type();
         let f_args_type = f_type.get_param_types();
         let mut shim_args_type: Vec<BasicTypeEnum<'ctx>> = Vec::new();
-        for this_type in f_args_type {
+        for this_type in f_args_type.iter().copied() {
             match this_type {
                 BasicTypeEnum::StructType(s) => {
                     // TODO: Address may need to be changed
-                    shim_args_type.push(s.ptr_type(AddressSpace::Global).into());
+                    shim_args_type.push(self.ptr_to(s, AddressSpace::Global).into());
+                }
+                BasicTypeEnum::ArrayType(a) => {
+                    // Arrays are passed by pointer, like structs
+                    shim_args_type.push(self.ptr_to(a, AddressSpace::Global).into());
                 }
                 BasicTypeEnum::IntType(i) => shim_args_type.push(i.into()),
                 BasicTypeEnum::PointerType(p) => shim_args_type.push(p.into()),
@@ -30,13 +85,24 @@ impl<'ctx> Codegen<'ctx> {
             }
         }
         let mut is_return_converted = false;
+        let mut out_ix = None;
         let ret_type: BasicTypeEnum<'ctx> = match f_type.get_return_type() {
             Some(t) => {
                 match t {
-                    BasicTypeEnum::StructType(s) => {
-                        // TODO: Address may need to be changed
-                        shim_args_type.push(s.ptr_type(AddressSpace::Global).into());
-                        is_return_converted = true;
+                    BasicTypeEnum::StructType(_) | BasicTypeEnum::ArrayType(_) => {
+                        let in_place_ix = if in_place {
+                            f_args_type.iter().position(|ty| *ty == t)
+                        } else {
+                            None
+                        };
+                        if let Some(ix) = in_place_ix {
+                            out_ix = Some(ix as u32);
+                        } else {
+                            // TODO: Address may need to be changed
+                            shim_args_type.push(self.ptr_to(t, AddressSpace::Global).into());
+                            out_ix = Some(shim_args_type.len() as u32 - 1);
+                            is_return_converted = true;
+                        }
                         self.context.i32_type().into()
                     }
                     BasicTypeEnum::IntType(i) => i.into(),
@@ -47,7 +113,18 @@ impl<'ctx> Codegen<'ctx> {
             None => unimplemented!("Void return function not implemented"),
         };
         let wrapper_f_type = ret_type.fn_type(&shim_args_type[..], false);
-        let wrapper_f = self.module.add_function(name, wrapper_f_type, linkage);
+        let wrapper_f = self.define_function(name, wrapper_f_type, linkage);
+        // Narrow integers are extended at the FFI boundary according to their signedness
+        for (ix, ty) in shim_args_type.iter().enumerate() {
+            if let Some(ext) = self.int_extension_attribute(*ty) {
+                wrapper_f.add_attribute(AttributeLoc::Param(ix as u32), ext);
+            }
+        }
+        if let Some(ext) = self.int_extension_attribute(ret_type) {
+            if out_ix.is_none() {
+                wrapper_f.add_attribute(AttributeLoc::Return, ext);
+            }
+        }
         let this_block = self.context.append_basic_block(wrapper_f, "entry");
         self.builder.position_at_end(this_block);
         let args = if is_return_converted {
@@ -78,9 +155,8 @@ impl<'ctx> Codegen<'ctx> {
             .left()
         {
             Some(v) => {
-                if is_return_converted {
-                    let _wrapper_params_here = wrapper_f.get_params();
-                    match wrapper_f.get_params().pop() {
+                if let Some(out_ix) = out_ix {
+                    match wrapper_f.get_nth_param(out_ix) {
                         Some(p) => match p {
                             BasicValueEnum::PointerValue(p) => {
                                 self.builder.build_store(p, v);
@@ -89,7 +165,7 @@ impl<'ctx> Codegen<'ctx> {
                                 ));
                             }
                             _ => panic!(
-                                "Last element of a Shim of a function with 
+                                "Output argument of a Shim of a function with 
                         a struct type return should have pointertype"
                             ),
                         },
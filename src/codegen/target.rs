@@ -0,0 +1,68 @@
+/*!
+Target machines and target-specific function attributes
+*/
+use super::*;
+use inkwell::attributes::AttributeLoc;
+use inkwell::module::Linkage;
+use inkwell::targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine};
+use inkwell::types::FunctionType;
+use inkwell::OptimizationLevel;
+
+impl<'ctx> Codegen<'ctx> {
+    /// Define a new function in the current module, with this context's target CPU and features attached
+    ///
+    /// All functions with bodies emitted during code generation are created through this method, whereas declarations
+    /// of external functions such as intrinsics are not.
+    pub fn define_function(
+        &self,
+        name: &str,
+        ty: FunctionType<'ctx>,
+        linkage: Option<Linkage>,
+    ) -> FunctionValue<'ctx> {
+        let f = self.module.add_function(name, ty, linkage);
+        self.add_target_attributes(f);
+        f
+    }
+
+    /// Attach the `target-cpu` and `target-features` attributes of this context, if set, to a function
+    pub fn add_target_attributes(&self, f: FunctionValue<'ctx>) {
+        if let Some(cpu) = &self.target_cpu {
+            let attribute = self.context.create_string_attribute("target-cpu", cpu);
+            f.add_attribute(AttributeLoc::Function, attribute);
+        }
+        if let Some(features) = &self.target_features {
+            let attribute = self
+                .context
+                .create_string_attribute("target-features", features);
+            f.add_attribute(AttributeLoc::Function, attribute);
+        }
+    }
+
+    /// Create a target machine for the current module's target triple, or the host if none is set, with this context's
+    /// target CPU and features
+    ///
+    /// Returns `Error::TargetError` if the target is unknown or does not support the given CPU.
+    pub fn create_target_machine(&self, level: OptimizationLevel) -> Result<TargetMachine, Error> {
+        let triple = self.module.get_triple();
+        let triple = if triple.as_str().to_bytes().is_empty() {
+            Target::initialize_native(&InitializationConfig::default())
+                .map_err(Error::TargetError)?;
+            TargetMachine::get_default_triple()
+        } else {
+            Target::initialize_all(&InitializationConfig::default());
+            triple
+        };
+        let target =
+            Target::from_triple(&triple).map_err(|err| Error::TargetError(err.to_string()))?;
+        target
+            .create_target_machine(
+                &triple,
+                self.target_cpu.as_deref().unwrap_or(""),
+                self.target_features.as_deref().unwrap_or(""),
+                level,
+                RelocMode::Default,
+                CodeModel::Default,
+            )
+            .ok_or_else(|| Error::TargetError(format!("Invalid target machine for {:?}", triple)))
+    }
+}
@@ -4,15 +4,185 @@ Code generation for rain gammas
 use super::*;
 use either::Either;
 use inkwell::module::Linkage;
-use inkwell::values::{BasicValue, IntValue};
+use inkwell::types::{BasicType, BasicTypeEnum};
+use inkwell::values::{BasicValue, BasicValueEnum, IntValue};
 use rain_ir::control::ternary::Ternary;
 use rain_ir::region::Regional;
 use rain_ir::typing::Typed;
+use rain_ir::value::Value;
+use std::rc::Rc;
 
 /// The default linkage of lambda values
 pub const DEFAULT_GAMMA_LINKAGE: Option<Linkage> = None;
 
 impl<'ctx> Codegen<'ctx> {
+    /// Check that a branch value has the LLVM type expected of the result of a ternary node
+    ///
+    /// Feeding a mismatched value into a phi node makes LLVM fail with an opaque assertion, so this returns
+    /// `Error::InternalError` instead.
+    pub fn check_branch_repr(
+        result_repr: BasicTypeEnum<'ctx>,
+        branch: BasicValueEnum<'ctx>,
+    ) -> Result<(), Error> {
+        if branch.get_type() == result_repr {
+            Ok(())
+        } else {
+            Err(Error::InternalError(
+                "Ternary branch value does not match the result representation",
+            ))
+        }
+    }
+
+    /// Get the representation of the result of a ternary node
+    ///
+    /// Dependently typed ternary nodes, i.e. those with branches of different types, are supported as long as both
+    /// branch types have the same representation, e.g. products lowering to the same LLVM struct type.
+    pub fn ternary_result_repr(&mut self, ternary: &Ternary) -> Result<Repr<'ctx>, Error> {
+        let high_ty = ternary.high().ty();
+        let low_ty = ternary.low().ty();
+        if high_ty == low_ty {
+            return self.repr(high_ty.as_var());
+        }
+        let high_repr = self.repr(high_ty.as_var())?;
+        let low_repr = self.repr(low_ty.as_var())?;
+        match (high_repr, low_repr) {
+            (Repr::Product(high), Repr::Product(low)) if high.repr == low.repr => {
+                Ok(Repr::Product(high))
+            }
+            (high_repr, low_repr) if high_repr == low_repr => Ok(high_repr),
+            _ => Err(Error::NotImplemented(
+                "Dependently typed ternary nodes with different branch representations",
+            )),
+        }
+    }
+
+    /// Get the representation of a ternary node as a function of its boolean selector
+    fn ternary_repr(&mut self, ternary: &Ternary) -> Result<Repr<'ctx>, Error> {
+        if ternary.low().ty() == ternary.high().ty() {
+            return self.build_function_repr(ternary.get_ty());
+        }
+        // Dependently typed ternary nodes have a dependent function type, so we compute their representation directly
+        let result_repr: BasicTypeEnum<'ctx> = match self.ternary_result_repr(ternary)? {
+            Repr::Type(t) => t,
+            Repr::Product(p) => p.repr.into(),
+            Repr::Function(f) => self.fn_ptr_to(f.repr).into(),
+            Repr::Prop | Repr::Empty => return Ok(Repr::Prop),
+        };
+        let mut mapping = IxMap::with_capacity(1);
+        mapping.push_ix(0);
+        let repr = result_repr.fn_type(&[self.context.bool_type().into()], false);
+        Ok(Repr::Function(Rc::new(FunctionRepr { mapping, repr })))
+    }
+
+    /// Build a selection between two values given as `(cond, high, low)`, without emitting any branches
+    ///
+    /// Unlike a ternary node, both `high` and `low` are evaluated unless `cond` is a constant, in which case only the
+    /// selected value is built and returned directly. Applications of a select function registered with
+    /// `SelectLowering` are compiled with this method.
+    pub fn build_select_app(&mut self, args: &[ValId]) -> Result<Val<'ctx>, Error> {
+        let (cond, high, low) = match args {
+            [cond, high, low] => (cond, high, low),
+            _ => return Err(Error::NotImplemented("Partial application of selection")),
+        };
+        let cond = match self.build(cond)? {
+            Val::Value(BasicValueEnum::IntValue(cond)) => cond,
+            Val::Contr => return Ok(Val::Contr),
+            _ => {
+                return Err(Error::InternalError(
+                    "Selection condition must be a boolean",
+                ))
+            }
+        };
+        if let Some(c) = cond.get_zero_extended_constant() {
+            return self.build(if c != 0 { high } else { low });
+        }
+        let high_val = self.build(high)?;
+        let low_val = self.build(low)?;
+        self.build_select(cond, high_val, low_val)
+    }
+
+    /// Build a boolean if-then-else given as `(cond, high, low)`, where `high` and `low` are values of the same type
+    ///
+    /// If both branches are scalars which are literals or have already been compiled, so that evaluating them is free,
+    /// they are selected between with `build_select`. Otherwise, each branch is built in its own basic block, so that
+    /// only the selected branch is evaluated. Constant conditions are folded.
+    pub fn build_ite_app(&mut self, args: &[ValId]) -> Result<Val<'ctx>, Error> {
+        let (cond, high, low) = match args {
+            [cond, high, low] => (cond, high, low),
+            _ => return Err(Error::NotImplemented("Partial application of if-then-else")),
+        };
+        let cond = match self.build(cond)? {
+            Val::Value(BasicValueEnum::IntValue(cond)) => cond,
+            Val::Contr => return Ok(Val::Contr),
+            _ => {
+                return Err(Error::InternalError(
+                    "If-then-else condition must be a boolean",
+                ))
+            }
+        };
+        if let Some(c) = cond.get_zero_extended_constant() {
+            return self.build(if c != 0 { high } else { low });
+        }
+        let result_repr = self.repr(high.ty().as_var())?;
+        match result_repr {
+            Repr::Prop => return Ok(Val::Unit),
+            Repr::Empty => return Ok(Val::Contr),
+            Repr::Type(_) if self.is_evaluated(high) && self.is_evaluated(low) => {
+                let high_val = self.build(high)?;
+                let low_val = self.build(low)?;
+                return self.build_select(cond, high_val, low_val);
+            }
+            _ => {}
+        }
+        self.build_branches_inline(result_repr, cond, high, low, false)
+    }
+
+    /// Whether a value is a literal or has already been compiled in the current context
+    fn is_evaluated(&self, v: &ValId) -> bool {
+        match v.as_enum() {
+            ValueEnum::Bool(_) | ValueEnum::Index(_) | ValueEnum::Bits(_) => true,
+            _ => self.get_compiled(v).is_some(),
+        }
+    }
+
+    /// Build a selection between two compiled values in the current basic block, folding constant conditions
+    pub fn build_select(
+        &mut self,
+        cond: IntValue<'ctx>,
+        high: Val<'ctx>,
+        low: Val<'ctx>,
+    ) -> Result<Val<'ctx>, Error> {
+        if let Some(c) = cond.get_zero_extended_constant() {
+            return Ok(if c != 0 { high } else { low });
+        }
+        let (high, low) = match (high, low) {
+            (Val::Contr, _) | (_, Val::Contr) => return Ok(Val::Contr),
+            (Val::Unit, Val::Unit) => return Ok(Val::Unit),
+            (Val::Closure(high), Val::Closure(low)) => {
+                Self::check_branch_repr(high.get_type().into(), low.into())?;
+                let selected = self.builder.build_select(cond, high, low, "__select");
+                return Ok(Val::Closure(selected.into_struct_value()));
+            }
+            (high, low) => (Self::select_operand(high)?, Self::select_operand(low)?),
+        };
+        Self::check_branch_repr(high.get_type(), low)?;
+        Ok(self
+            .builder
+            .build_select(cond, high, low, "__select")
+            .into())
+    }
+
+    /// Get the LLVM value to be selected between for a compiled value, with functions selected by pointer
+    fn select_operand(v: Val<'ctx>) -> Result<BasicValueEnum<'ctx>, Error> {
+        match v {
+            Val::Value(v) => Ok(v),
+            Val::Function(f) => Ok(f.as_global_value().as_pointer_value().into()),
+            _ => Err(Error::InternalError(
+                "Selection between values with different representations",
+            )),
+        }
+    }
+
     /// Build an inline ternary node, switching on a given LLVM boolean
     ///
     /// # Preconditions
@@ -23,25 +193,50 @@ impl<'ctx> Codegen<'ctx> {
         &mut self,
         ternary: &Ternary,
         switch_bool: IntValue,
+    ) -> Result<Val<'ctx>, Error> {
+        self.build_ternary_inline_with(ternary, switch_bool, false)
+    }
+
+    /// Build an inline ternary node, switching on a given LLVM boolean, which selects the low branch if `inverted`
+    pub fn build_ternary_inline_with(
+        &mut self,
+        ternary: &Ternary,
+        switch_bool: IntValue,
+        inverted: bool,
+    ) -> Result<Val<'ctx>, Error> {
+        let result_repr = self.ternary_result_repr(ternary)?;
+        self.build_branches_inline(
+            result_repr,
+            switch_bool,
+            &ternary.high(),
+            &ternary.low(),
+            inverted,
+        )
+    }
+
+    /// Build a conditional branch on a given LLVM boolean, building `high` and `low` in their own basic blocks and
+    /// merging their values with a phi node of a given representation
+    ///
+    /// Selects `low` when `switch_bool` is true if `inverted` is set. Has the same preconditions as
+    /// `build_ternary_inline`.
+    pub fn build_branches_inline(
+        &mut self,
+        result_repr: Repr<'ctx>,
+        switch_bool: IntValue,
+        high: &ValId,
+        low: &ValId,
+        inverted: bool,
     ) -> Result<Val<'ctx>, Error> {
         // Step 0: get the current function and representation, failing early if unavailable
         let curr = self.curr.ok_or(Error::NoCurrentFunction)?;
-        let high_ty = ternary.high().ty();
-        let low_ty = ternary.low().ty();
-        let result_repr = if high_ty == low_ty {
-            self.repr(high_ty.as_var())?
-        } else {
-            unimplemented!(
-                "Dependently typed ternary nodes: high_ty = {:?} != low_ty = {:?}, ternary = {:#?}",
-                high_ty,
-                low_ty,
-                ternary
-            );
-        };
-        let result_repr = if let Repr::Type(ty) = result_repr {
-            ty
-        } else {
-            unimplemented!("Non basic representation {:?}", result_repr)
+        let result_repr: BasicTypeEnum<'ctx> = match result_repr {
+            Repr::Type(ty) => ty,
+            // Selection between functions is done via a phi over function pointers
+            Repr::Function(f) => self.fn_ptr_to(f.repr).into(),
+            Repr::Product(p) => p.repr.into(),
+            // Branches with propositional or empty results carry no values, so no control flow is needed
+            Repr::Prop => return Ok(Val::Unit),
+            Repr::Empty => return Ok(Val::Contr),
         };
 
         // Step 1: create branches, build conditional branch
@@ -49,38 +244,54 @@ impl<'ctx> Codegen<'ctx> {
         let low_br = self.context.append_basic_block(curr, "low");
         let result_br = self.context.append_basic_block(curr, "ternary_result");
 
-        self.builder
-            .build_conditional_branch(switch_bool, high_br, low_br);
+        if inverted {
+            self.builder
+                .build_conditional_branch(switch_bool, low_br, high_br);
+        } else {
+            self.builder
+                .build_conditional_branch(switch_bool, high_br, low_br);
+        }
 
-        // Step 2: compile values into high/low branches
+        // Step 2: compile values into high/low branches, each in its own scope, since neither branch dominates the
+        // other or the result branch
         // Step 2.a: high branch
         self.head = Some(high_br);
         self.builder.position_at_end(high_br);
-        let high_val = match self.build(&ternary.high())? {
+        let high_val = match self.build_scoped(high)? {
             Val::Value(v) => v,
+            Val::Function(f) => f.as_global_value().as_pointer_value().into(),
             v => unimplemented!(
                 "Non LLVM branch values not yet implemented: got high branch {:?}",
                 v
             ),
         };
+        // The high branch may itself have branched, so the phi must refer to the block it ends in
+        let high_br = self.head.unwrap_or(high_br);
         self.builder.build_unconditional_branch(result_br);
 
         // Step 2.b: low branch
         self.head = Some(low_br);
         self.builder.position_at_end(low_br);
-        let low_val = match self.build(&ternary.low())? {
+        let low_val = match self.build_scoped(low)? {
             Val::Value(v) => v,
+            Val::Function(f) => f.as_global_value().as_pointer_value().into(),
             v => unimplemented!(
                 "Non LLVM branch values not yet implemented: got low branch {:#?}",
                 v
             ),
         };
+        let low_br = self.head.unwrap_or(low_br);
         self.builder.build_unconditional_branch(result_br);
 
         // Step 3: compile phi result into result branch
         // Note we stay in the result branch at the end, since further instructions should be placed there
         self.head = Some(result_br);
         self.builder.position_at_end(result_br);
+        Self::check_branch_repr(result_repr, high_val)?;
+        Self::check_branch_repr(result_repr, low_val)?;
+        if high_val == low_val {
+            return Ok(Val::Value(high_val));
+        }
         let phi_val = self.builder.build_phi(result_repr, "tern");
         phi_val.add_incoming(&[(&high_val, high_br), (&low_val, low_br)]);
 
@@ -90,10 +301,6 @@ impl<'ctx> Codegen<'ctx> {
 
     /// Build a ternary node
     pub fn build_ternary(&mut self, ternary: &Ternary) -> Result<Val<'ctx>, Error> {
-        // TODO: Factor this out into a helper function later
-        if ternary.low().ty() != ternary.high().ty() {
-            unimplemented!("Dependent ternary nodes are not implemented");
-        }
         // Step 1: Cache and initialize region
         let old_region = if ternary.depth() != 0 {
             unimplemented!(
@@ -108,10 +315,10 @@ impl<'ctx> Codegen<'ctx> {
 
         // Step 2: construct prototype, construct function, handle edge cases
         //TODO: general get_repr
-        let prototype_or_return = match self.build_function_repr(ternary.get_ty()) {
+        let prototype_or_return = match self.ternary_repr(ternary) {
             Ok(Repr::Function(prototype)) => Either::Left(prototype),
             Ok(Repr::Prop) => Either::Right(Ok(Val::Unit)),
-            Ok(r) => panic!("Invalid function representation: {:?}", r),
+            Ok(_) => Either::Right(Err(Error::InvalidFuncRepr)),
             Err(err) => Either::Right(Err(err)),
         };
 
@@ -125,12 +332,11 @@ impl<'ctx> Codegen<'ctx> {
             }
         };
 
-        let result_fn = self.module.add_function(
-            &format!("__tern_{}", self.counter),
-            prototype.repr,
-            DEFAULT_GAMMA_LINKAGE,
-        );
-        self.counter += 1;
+        let name = match self.names.get(&ternary.clone().into_val()).cloned() {
+            Some(name) => name,
+            None => self.fresh_name("__tern"),
+        };
+        let result_fn = self.define_function(&name, prototype.repr, DEFAULT_GAMMA_LINKAGE);
 
         // Step 3: add an entry basic block, registering it, and setting the builder position
         let entry_bb = self.context.append_basic_block(result_fn, "entry");
@@ -158,9 +364,8 @@ impl<'ctx> Codegen<'ctx> {
         };
 
         // Step 7: Cleanup: reset current, locals, head, and region, and propagate errors if necessary
-        // Debug assertions: note that `head` and `locals` are allowed to change
-        debug_assert_eq!(self.region.region(), ternary.region());
-        debug_assert_eq!(self.curr, Some(result_fn));
+        // Invariant checks: note that `head` and `locals` are allowed to change
+        let state = self.check_function_state(&ternary.region().clone_region(), result_fn);
 
         // Resets:
         self.curr = old_curr;
@@ -171,6 +376,7 @@ impl<'ctx> Codegen<'ctx> {
         self.locals = old_locals;
         self.region = old_region;
 
+        state?;
         ternary_result?;
 
         // Otherwise, return successfully constructed function
@@ -3,8 +3,9 @@ Code generation for `rain` tuples and product types
 */
 
 use super::*;
-use inkwell::types::BasicTypeEnum;
-use inkwell::values::BasicValueEnum;
+use inkwell::types::{BasicType, BasicTypeEnum, StructType};
+use inkwell::values::{AsValueRef, BasicValueEnum, StructValue};
+use llvm_sys::core::LLVMIsConstant;
 use rain_ir::typing::Typed;
 use rain_ir::value::tuple::{Product, Tuple};
 use std::rc::Rc;
@@ -12,19 +13,54 @@ use std::rc::Rc;
 impl<'ctx> Codegen<'ctx> {
     /// Get the representation for a product type
     pub fn repr_product(&mut self, p: &Product) -> Result<Repr<'ctx>, Error> {
+        self.repr_product_with(p, None)
+    }
+
+    /// Get the representation for a product type, as the named LLVM struct type set by `set_record_name` if any
+    ///
+    /// This agrees with `repr` on the product type, so that values built from a `Product` have the same LLVM type as
+    /// the parameters and results of functions taking or returning it.
+    pub fn repr_product_type(&mut self, p: &Product) -> Result<Repr<'ctx>, Error> {
+        let record = self
+            .record_names
+            .keys()
+            .find(|ty| match ty.as_enum() {
+                ValueEnum::Product(q) => q == p,
+                _ => false,
+            })
+            .cloned();
+        match record {
+            Some(ty) => self.repr(&ty),
+            None => self.repr_product(p),
+        }
+    }
+
+    /// Get the representation for a product type as a named LLVM struct type, e.g. `%Pair = type { i8, i16 }`
+    ///
+    /// If a struct type with this name already exists in the module, it is reused if it has the same body, and
+    /// `Error::SymbolExists` is returned otherwise.
+    pub fn repr_record(&mut self, p: &Product, name: &str) -> Result<Repr<'ctx>, Error> {
+        self.repr_product_with(p, Some(name))
+    }
+
+    /// Get the representation for a product type, as a named struct type if a name is given
+    fn repr_product_with(&mut self, p: &Product, name: Option<&str>) -> Result<Repr<'ctx>, Error> {
         let mut mapping = IxMap::new();
         let mut struct_index = 0;
         let mut repr_vec: Vec<BasicTypeEnum<'ctx>> = Vec::new();
-        let reprs = p.iter().map(|ty| self.repr(ty));
-        for repr in reprs {
-            let repr = repr?;
-            match repr {
+        for ty in p.iter() {
+            match self.repr(ty)? {
                 Repr::Type(ty) => {
-                    repr_vec.push(ty);
+                    repr_vec.push(self.field_repr(ty));
+                    mapping.push_ix(struct_index);
+                    struct_index += 1;
+                }
+                // Function members are stored as function pointers
+                Repr::Function(f) => {
+                    repr_vec.push(self.fn_ptr_to(f.repr).into());
                     mapping.push_ix(struct_index);
                     struct_index += 1;
                 }
-                Repr::Function(_) => unimplemented!("Functions in structure types!"),
                 Repr::Empty => return Ok(Repr::Empty),
                 Repr::Prop => mapping.push_prop(),
                 Repr::Product(p) => {
@@ -37,22 +73,251 @@ impl<'ctx> Codegen<'ctx> {
         if struct_index == 0 {
             Ok(Repr::Empty)
         } else {
-            let repr = self.context.struct_type(&repr_vec[..], false);
+            let repr = match name {
+                Some(name) => {
+                    let repr = match self.module.get_struct_type(name) {
+                        Some(existing) => existing,
+                        None => self.context.opaque_struct_type(name),
+                    };
+                    if repr.is_opaque() {
+                        repr.set_body(&repr_vec[..], false);
+                    } else if repr.get_field_types() != repr_vec {
+                        return Err(Error::SymbolExists(name.to_owned()));
+                    }
+                    repr
+                }
+                None => self.context.struct_type(&repr_vec[..], false),
+            };
             Ok(Repr::Product(Rc::new(ProductRepr { mapping, repr })))
         }
     }
 
-    /// Build a product in the current local context
-    pub fn build_product(&mut self, _p: &Product) -> Result<Val<'ctx>, Error> {
-        unimplemented!("Product type compilation")
+    /// Get the type of the product slot storing a member with a given representation
+    ///
+    /// This is the representation itself, except for booleans when `byte_bool_fields` is set, which are stored as
+    /// `i8`.
+    pub fn field_repr(&self, ty: BasicTypeEnum<'ctx>) -> BasicTypeEnum<'ctx> {
+        match ty {
+            BasicTypeEnum::IntType(i) if self.byte_bool_fields && i.get_bit_width() == 1 => {
+                self.context.i8_type().into()
+            }
+            ty => ty,
+        }
+    }
+
+    /// Widen a value to be stored in a product slot of a given type, if the slot is wider than the value
+    pub fn build_field_widening(
+        &mut self,
+        value: BasicValueEnum<'ctx>,
+        slot: BasicTypeEnum<'ctx>,
+    ) -> BasicValueEnum<'ctx> {
+        match (value, slot) {
+            (BasicValueEnum::IntValue(v), BasicTypeEnum::IntType(slot))
+                if v.get_type().get_bit_width() < slot.get_bit_width() =>
+            {
+                if v.is_const() {
+                    v.const_z_ext(slot).into()
+                } else {
+                    self.builder.build_int_z_extend(v, slot, "widen").into()
+                }
+            }
+            (value, _) => value,
+        }
+    }
+
+    /// Narrow a value projected out of a product slot to the representation of the member stored in it
+    pub fn build_field_narrowing(
+        &mut self,
+        element: Val<'ctx>,
+        member: BasicTypeEnum<'ctx>,
+    ) -> Val<'ctx> {
+        match (element, member) {
+            (Val::Value(BasicValueEnum::IntValue(v)), BasicTypeEnum::IntType(member))
+                if v.get_type().get_bit_width() > member.get_bit_width() =>
+            {
+                if v.is_const() {
+                    Val::Value(v.const_truncate(member).into())
+                } else {
+                    Val::Value(self.builder.build_int_truncate(v, member, "narrow").into())
+                }
+            }
+            (element, _) => element,
+        }
+    }
+
+    /// Get the initial value of an aggregate of a given type which is built member by member
+    ///
+    /// This is `zeroinitializer` if `zero_init_aggregates` is set, and `undef` otherwise.
+    pub fn aggregate_seed(&self, ty: StructType<'ctx>) -> StructValue<'ctx> {
+        if self.zero_init_aggregates {
+            ty.const_zero()
+        } else {
+            ty.get_undef()
+        }
+    }
+
+    /// Build the projection of the element at a given representation index out of a compiled product
+    ///
+    /// Register products are projected with `extractvalue`, whereas memory-backed products, i.e. pointers to
+    /// structs, are projected with an inbounds GEP followed by a load.
+    pub fn build_projection(
+        &mut self,
+        aggregate: Val<'ctx>,
+        repr_ix: u32,
+    ) -> Result<Val<'ctx>, Error> {
+        let element = match aggregate {
+            Val::Value(BasicValueEnum::StructValue(s)) => self
+                .builder
+                .build_extract_value(s, repr_ix, "idx")
+                .expect("Internal error: valid index guaranteed by IR construction"),
+            Val::Value(BasicValueEnum::PointerValue(p)) => {
+                let element_ptr = self
+                    .builder
+                    .build_struct_gep(p, repr_ix, "idx_ptr")
+                    .map_err(|_| {
+                        Error::InternalError("Invalid index into memory-backed product")
+                    })?;
+                self.builder.build_load(element_ptr, "idx")
+            }
+            Val::Contr => return Ok(Val::Contr),
+            _ => {
+                return Err(Error::InternalError(
+                    "Projection out of a value which is not a struct or a pointer to one",
+                ))
+            }
+        };
+        Ok(Val::Value(element))
+    }
+
+    /// Build the projection of the `ix`th element of a product type as a first-class function
+    ///
+    /// This corresponds to a projection which has been applied to an index, but not yet to a value of the product type.
+    /// Projections onto propositional elements, and projections out of empty products, compile to `Val::Unit`, as for
+    /// other functions with propositional representations.
+    pub fn build_projection_fn(&mut self, p: &Product, ix: usize) -> Result<Val<'ctx>, Error> {
+        let repr = match self.repr_product_type(p)? {
+            Repr::Product(repr) => repr,
+            Repr::Empty | Repr::Prop => return Ok(Val::Unit),
+            Repr::Type(_) | Repr::Function(_) => {
+                return Err(Error::InternalError(
+                    "Product types are represented by structs",
+                ))
+            }
+        };
+        let repr_ix = match repr.mapping.get(ix) {
+            Some(repr_ix) => repr_ix,
+            None => return Ok(Val::Unit),
+        };
+        let field_ty = repr
+            .repr
+            .get_field_type_at_index(repr_ix)
+            .ok_or(Error::InternalError("Product mapping out of bounds"))?;
+        let member_ty = match p.iter().nth(ix).map(|ty| self.repr(ty)).transpose()? {
+            Some(Repr::Type(member_ty)) => member_ty,
+            _ => field_ty,
+        };
+        let name = self.fresh_name("__proj");
+        let proj_fn =
+            self.define_function(&name, member_ty.fn_type(&[repr.repr.into()], false), None);
+        let entry_bb = self.context.append_basic_block(proj_fn, "entry");
+        self.builder.position_at_end(entry_bb);
+        let param = proj_fn
+            .get_nth_param(0)
+            .expect("Projection has one parameter")
+            .into_struct_value();
+        let element = self
+            .builder
+            .build_extract_value(param, repr_ix, "idx")
+            .expect("Internal error: valid index guaranteed by IR construction");
+        let element = match self.build_field_narrowing(Val::Value(element), member_ty) {
+            Val::Value(element) => element,
+            _ => unreachable!("Narrowing preserves values"),
+        };
+        self.builder.build_return(Some(&element));
+        if let Some(head) = self.head {
+            self.builder.position_at_end(head);
+        }
+        Ok(Val::Function(proj_fn))
+    }
+
+    /// Build a constant of a product type from the compiled constants for each of its members
+    ///
+    /// `members` must contain one value per member of `p`, including propositional members, which are skipped.
+    /// Returns `Error::NotConst` if any representable member is not an LLVM constant.
+    pub fn build_const_product(
+        &mut self,
+        p: &Product,
+        members: &[Val<'ctx>],
+    ) -> Result<Val<'ctx>, Error> {
+        let repr = match self.repr_product_type(p)? {
+            Repr::Product(repr) => repr,
+            Repr::Prop => return Ok(Val::Unit),
+            Repr::Empty => return Ok(Val::Contr),
+            Repr::Type(_) | Repr::Function(_) => {
+                return Err(Error::InternalError(
+                    "Product types are represented by structs",
+                ))
+            }
+        };
+        if members.len() != repr.mapping.len() {
+            return Err(Error::InternalError(
+                "Wrong number of members for constant product",
+            ));
+        }
+        let mut values: Vec<Option<BasicValueEnum<'ctx>>> =
+            vec![None; repr.repr.count_fields() as usize];
+        for (mapped, member) in repr.mapping.iter().zip(members) {
+            let pos = match mapped {
+                ReprIx::Val(pos) => pos as usize,
+                ReprIx::Prop => continue,
+            };
+            match member {
+                Val::Value(v) if is_const_value(v) => {
+                    let slot = repr
+                        .repr
+                        .get_field_type_at_index(pos)
+                        .ok_or(Error::InternalError("Product mapping out of bounds"))?;
+                    values[pos] = Some(self.build_field_widening(*v, slot))
+                }
+                Val::Contr => return Ok(Val::Contr),
+                _ => return Err(Error::NotConst),
+            }
+        }
+        let values: Vec<BasicValueEnum<'ctx>> = values
+            .into_iter()
+            .map(|v| {
+                v.ok_or(Error::InternalError(
+                    "Product mapping does not cover all fields",
+                ))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Val::Value(repr.repr.const_named_struct(&values[..]).into()))
+    }
+
+    /// Compile a product type into an LLVM value, e.g. for passing it to a dependent function
+    ///
+    /// A product type is encoded by the size of its representation in bytes, as an `i64` constant. Products with no
+    /// non-propositional members, including uninhabited products, compile to `Val::Unit`.
+    pub fn build_product(&mut self, p: &Product) -> Result<Val<'ctx>, Error> {
+        match self.repr_product_type(p)? {
+            Repr::Product(repr) => repr
+                .repr
+                .size_of()
+                .map(Val::from)
+                .ok_or(Error::UnrepresentableType),
+            Repr::Prop | Repr::Empty => Ok(Val::Unit),
+            _ => Err(Error::InternalError(
+                "Product types are represented by structs",
+            )),
+        }
     }
 
     /// Build a tuple in the current local context
     pub fn build_tuple(&mut self, t: &Tuple) -> Result<Val<'ctx>, Error> {
-        let p_enum = t.ty().as_enum();
-        match p_enum {
-            ValueEnum::Product(product) => {
-                let repr = match self.repr_product(product)? {
+        let ty = t.ty().clone_ty();
+        match ty.as_enum() {
+            ValueEnum::Product(_) => {
+                let repr = match self.repr(&ty)? {
                     Repr::Product(tmp) => tmp,
                     Repr::Prop => return Ok(Val::Unit),
                     Repr::Empty => return Ok(Val::Contr),
@@ -64,21 +329,45 @@ impl<'ctx> Codegen<'ctx> {
                         return Err(Error::NotImplemented("Type in tuple not supported yet"))
                     }
                 };
-                let mut values: Vec<BasicValueEnum<'ctx>> = Vec::new();
+                // Each non-propositional member is placed in the struct slot it is mapped to
+                let mut slots: Vec<Option<BasicValueEnum<'ctx>>> =
+                    vec![None; repr.repr.count_fields() as usize];
                 for (i, mapped) in repr.mapping.iter().enumerate() {
-                    if let ReprIx::Val(_mapped_pos) = mapped {
-                        //TODO: stop assuming mapped positions increase monotonically!
+                    if let ReprIx::Val(pos) = mapped {
                         let this_result = self.build(&t[i])?;
                         // Note: This assumes that each type has unique representation
                         let value: BasicValueEnum<'ctx> = match this_result {
                             Val::Value(v) => v,
-                            Val::Function(_) => unimplemented!("Function tuple members"),
+                            Val::Function(f) => f.as_global_value().as_pointer_value().into(),
+                            Val::Closure(_) => {
+                                return Err(Error::NotImplemented("Closures as tuple members"))
+                            }
                             l => panic!("Invalid tuple member {:?}", l),
                         };
-                        values.push(value);
+                        let slot = repr
+                            .repr
+                            .get_field_type_at_index(pos)
+                            .ok_or(Error::InternalError("Tuple member mapped out of bounds"))?;
+                        slots[pos as usize] = Some(self.build_field_widening(value, slot));
                     }
                 }
-                Ok(Val::Value(repr.repr.const_named_struct(&values[..]).into()))
+                let values: Vec<BasicValueEnum<'ctx>> = slots
+                    .into_iter()
+                    .collect::<Option<_>>()
+                    .ok_or(Error::InternalError("Product slot without a tuple member"))?;
+                if values.iter().all(is_const_value) {
+                    return Ok(Val::Value(repr.repr.const_named_struct(&values[..]).into()));
+                }
+                // Non-constant members: build the struct member by member in the current basic block
+                let mut aggregate = self.aggregate_seed(repr.repr);
+                for (pos, value) in values.into_iter().enumerate() {
+                    aggregate = self
+                        .builder
+                        .build_insert_value(aggregate, value, pos as u32, "tuple")
+                        .expect("Internal error: valid index guaranteed by representation")
+                        .into_struct_value();
+                }
+                Ok(Val::Value(aggregate.into()))
             }
             ty => panic!(
                 "Expected tuple {} to have a product type, but type {} returned instead",
@@ -87,3 +376,18 @@ impl<'ctx> Codegen<'ctx> {
         }
     }
 }
+
+/// Whether a compiled value is known to be an LLVM constant
+///
+/// Aggregates, e.g. nested tuples, are constant if they were built as constants rather than by `insertvalue`.
+fn is_const_value(v: &BasicValueEnum) -> bool {
+    match v {
+        BasicValueEnum::IntValue(i) => i.is_const(),
+        BasicValueEnum::FloatValue(f) => f.is_const(),
+        BasicValueEnum::PointerValue(p) => p.is_const(),
+        BasicValueEnum::ArrayValue(a) => a.is_const(),
+        // SAFETY: `s` is a valid LLVM value
+        BasicValueEnum::StructValue(s) => unsafe { LLVMIsConstant(s.as_value_ref()) != 0 },
+        _ => false,
+    }
+}
@@ -0,0 +1,154 @@
+/*!
+Code generation for matches on tagged variants
+*/
+use super::*;
+use inkwell::types::BasicTypeEnum;
+use inkwell::values::{BasicValue, BasicValueEnum, IntValue};
+
+impl<'ctx> Codegen<'ctx> {
+    /// Get the tag and payload of a compiled variant
+    ///
+    /// A variant is represented either by its tag alone, as an integer, or by a struct whose first field is its tag and
+    /// whose second field, if any, is its payload. Tags are the index of the variant in declaration order.
+    pub fn variant_parts(
+        &mut self,
+        scrutinee: Val<'ctx>,
+    ) -> Result<(IntValue<'ctx>, Option<BasicValueEnum<'ctx>>), Error> {
+        let scrutinee = match scrutinee {
+            Val::Value(BasicValueEnum::IntValue(tag)) => return Ok((tag, None)),
+            Val::Value(BasicValueEnum::StructValue(s)) => s,
+            _ => {
+                return Err(Error::InternalError(
+                    "Match scrutinee is not a tagged variant",
+                ))
+            }
+        };
+        let tag = match self.builder.build_extract_value(scrutinee, 0, "tag") {
+            Some(BasicValueEnum::IntValue(tag)) => tag,
+            _ => {
+                return Err(Error::InternalError(
+                    "Match scrutinee does not begin with an integer tag",
+                ))
+            }
+        };
+        let payload = if scrutinee.get_type().count_fields() > 1 {
+            self.builder.build_extract_value(scrutinee, 1, "payload")
+        } else {
+            None
+        };
+        Ok((tag, payload))
+    }
+
+    /// Build a match on a compiled variant, switching on its tag
+    ///
+    /// Each arm is a pair of a tag and the value to build when the scrutinee has that tag. If an arm's value is a
+    /// unary lambda and the scrutinee has a payload, the lambda is applied to the payload, binding it in that arm.
+    /// Tags without an arm are assumed unreachable. Constant tags are folded into the matching arm. Each arm is built in
+    /// its own layer of the local symbol table, since its block does not dominate the code after the match. If every
+    /// reachable arm is propositional, so is the match, which returns `Val::Unit`.
+    pub fn build_match(
+        &mut self,
+        scrutinee: Val<'ctx>,
+        arms: &[(usize, ValId)],
+    ) -> Result<Val<'ctx>, Error> {
+        // Step 0: handle edge cases, failing early if not within a function
+        if scrutinee == Val::Contr {
+            return Ok(Val::Contr);
+        }
+        let curr = self.curr.ok_or(Error::NoCurrentFunction)?;
+        let (tag, payload) = self.variant_parts(scrutinee)?;
+        if let Some(c) = tag.get_zero_extended_constant() {
+            return match arms.iter().find(|(arm_tag, _)| *arm_tag as u64 == c) {
+                Some((_, arm)) => self.build_arm(arm, payload),
+                None => Ok(Val::Contr),
+            };
+        }
+
+        // Step 1: create a block per arm, along with the unreachable default and the result block
+        let default_br = self.context.append_basic_block(curr, "no_match");
+        let result_br = self.context.append_basic_block(curr, "match_result");
+        let tag_ty = tag.get_type();
+        let arm_brs: Vec<_> = arms
+            .iter()
+            .map(|(arm_tag, _)| {
+                (
+                    tag_ty.const_int(*arm_tag as u64, false),
+                    self.context.append_basic_block(curr, "arm"),
+                )
+            })
+            .collect();
+        self.builder.build_switch(tag, default_br, &arm_brs[..]);
+        self.builder.position_at_end(default_br);
+        self.builder.build_unreachable();
+
+        // Step 2: compile each arm in its own block, recording the block it ends in
+        let mut incoming: Vec<(BasicValueEnum<'ctx>, BasicBlock<'ctx>)> =
+            Vec::with_capacity(arms.len());
+        let mut unit_arm = false;
+        for ((_, arm), (_, arm_br)) in arms.iter().zip(arm_brs.iter()) {
+            self.head = Some(*arm_br);
+            self.builder.position_at_end(*arm_br);
+            let arm_val = match self.with_scope(|codegen| codegen.build_arm(arm, payload))? {
+                Val::Value(v) => v,
+                Val::Function(f) => f.as_global_value().as_pointer_value().into(),
+                Val::Contr => {
+                    self.builder.build_unreachable();
+                    continue;
+                }
+                Val::Closure(_) => {
+                    return Err(Error::NotImplemented("Matches with closure results"))
+                }
+                Val::Unit => {
+                    unit_arm = true;
+                    self.builder.build_unconditional_branch(result_br);
+                    continue;
+                }
+            };
+            let arm_br = self.head.unwrap_or(*arm_br);
+            self.builder.build_unconditional_branch(result_br);
+            incoming.push((arm_val, arm_br));
+        }
+
+        // Step 3: compile the phi result into the result block, staying there for further instructions
+        self.head = Some(result_br);
+        self.builder.position_at_end(result_br);
+        if unit_arm {
+            return if incoming.is_empty() {
+                Ok(Val::Unit)
+            } else {
+                Err(Error::InternalError(
+                    "Propositional match arm in a match with a non-propositional result",
+                ))
+            };
+        }
+        let result_repr: BasicTypeEnum<'ctx> = match incoming.first() {
+            Some((v, _)) => v.get_type(),
+            None => {
+                self.builder.build_unreachable();
+                return Ok(Val::Contr);
+            }
+        };
+        for (v, _) in incoming.iter() {
+            Self::check_branch_repr(result_repr, *v)?;
+        }
+        let phi_val = self.builder.build_phi(result_repr, "match");
+        for (v, br) in incoming.iter() {
+            phi_val.add_incoming(&[(v as &dyn BasicValue<'ctx>, *br)]);
+        }
+        Ok(Val::Value(phi_val.as_basic_value()))
+    }
+
+    /// Build an arm of a match, applying it to the payload of the scrutinee if it is a unary lambda
+    fn build_arm(
+        &mut self,
+        arm: &ValId,
+        payload: Option<BasicValueEnum<'ctx>>,
+    ) -> Result<Val<'ctx>, Error> {
+        match (arm.as_enum(), payload) {
+            (ValueEnum::Lambda(l), Some(payload)) if l.def_region().len() == 1 => {
+                self.build_lambda_inline(l, &[Val::Value(payload)])
+            }
+            _ => self.build(arm),
+        }
+    }
+}
@@ -10,6 +10,8 @@ pub enum Error {
     NotConst,
     /// Attempted to create a non-constant value of an irrepresentable type
     Irrepresentable,
+    /// Attempted to get the representation of a type which has no LLVM representation
+    UnrepresentableType,
     /// Invalid function representation
     InvalidFuncRepr,
     /// No current function set
@@ -18,6 +20,14 @@ pub enum Error {
     NoCurrentBlock,
     /// An internal error
     InternalError(&'static str),
+    /// Attempted to define a symbol which is already defined in the module
+    SymbolExists(String),
+    /// An error creating or querying a JIT execution engine
+    JitError(String),
+    /// A representation does not lower to the expected LLVM type, described by the representation found
+    ReprMismatch(String),
+    /// An error creating a target machine
+    TargetError(String),
     /// Not implemented
     NotImplemented(&'static str),
     /// A `rain` value error
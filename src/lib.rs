@@ -5,6 +5,7 @@
 #[warn(clippy::all)]
 pub mod codegen;
 pub mod error;
+pub mod lowering;
 pub mod repr;
 
 pub use inkwell::context::Context;
@@ -0,0 +1,144 @@
+/*!
+Pluggable lowering of primitive `rain` functions
+*/
+use crate::codegen::Codegen;
+use crate::error::Error;
+use crate::repr::Val;
+use rain_ir::value::{ValId, ValueEnum};
+use std::fmt::Debug;
+
+/**
+A lowering of applications of some set of primitive `rain` functions into LLVM
+
+`Codegen::build_app` tries each registered lowering in turn, most recently registered first, before falling back to
+the general application logic. The first lowering to return `Some` result wins.
+*/
+pub trait PrimitiveLowering<'ctx>: Debug {
+    /// Lower the application of `f` to `args` in the current basic block, or return `None` if `f` is not handled
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>>;
+}
+
+/// The lowering of logical operations
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LogicalLowering;
+
+impl<'ctx> PrimitiveLowering<'ctx> for LogicalLowering {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        match f.as_enum() {
+            ValueEnum::Logical(l) => Some(cg.build_logical_expr(*l, args)),
+            _ => None,
+        }
+    }
+}
+
+/// The lowering of bit vector arithmetic
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BitsLowering;
+
+impl<'ctx> PrimitiveLowering<'ctx> for BitsLowering {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        match f.as_enum() {
+            f_enum @ ValueEnum::BinOp(_) | f_enum @ ValueEnum::Neg(_) => {
+                Some(cg.build_bits_app(f_enum, args))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A bit vector operation interpreting its operand as a two's complement signed integer
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SignedBitsOp {
+    /// The absolute value, wrapping for the minimum value
+    Abs,
+    /// The sign, i.e. all ones for negative values and zero otherwise
+    Sign,
+}
+
+/**
+The lowering of applications of a given function as a signed bit vector operation, taking a bits type and an operand
+
+`rain` has no signed bit vector primitives, so frontends which define them can register this lowering to compile their
+applications with `Codegen::build_signed_bits_app`.
+*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignedBitsLowering(pub ValId, pub SignedBitsOp);
+
+impl<'ctx> PrimitiveLowering<'ctx> for SignedBitsLowering {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        if *f == self.0 {
+            Some(cg.build_signed_bits_app(self.1, args))
+        } else {
+            None
+        }
+    }
+}
+
+/**
+The lowering of applications of a given function as a boolean if-then-else, taking a condition and two branches
+
+`rain` has no if-then-else primitive distinct from ternary nodes, so frontends which define one, e.g. as a lambda, can
+register this lowering to compile its applications with `Codegen::build_ite_app` rather than inlining its definition.
+*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IteLowering(pub ValId);
+
+impl<'ctx> PrimitiveLowering<'ctx> for IteLowering {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        if *f == self.0 {
+            Some(cg.build_ite_app(args))
+        } else {
+            None
+        }
+    }
+}
+
+/**
+The lowering of applications of a given function as a branchless selection, taking a condition and two values
+
+`rain` has no select primitive distinct from ternary nodes, so frontends which define one can register this lowering to
+compile its applications with `Codegen::build_select_app`, evaluating both values and emitting a `select` rather than
+branching.
+*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SelectLowering(pub ValId);
+
+impl<'ctx> PrimitiveLowering<'ctx> for SelectLowering {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        if *f == self.0 {
+            Some(cg.build_select_app(args))
+        } else {
+            None
+        }
+    }
+}
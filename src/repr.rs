@@ -2,8 +2,12 @@
 LLVM representations for rain types and values
 */
 
+use crate::codegen::Codegen;
+use crate::error::Error;
 use inkwell::types::{BasicTypeEnum, FunctionType, StructType};
-use inkwell::values::{BasicValueEnum, FunctionValue, IntValue};
+use inkwell::values::{AsValueRef, BasicValueEnum, FunctionValue, IntValue, StructValue};
+use inkwell::AddressSpace;
+use llvm_sys::core::LLVMIsConstant;
 use std::convert::TryFrom;
 use std::ops::Deref;
 use std::rc::Rc;
@@ -49,6 +53,58 @@ pub enum Repr<'ctx> {
     Empty,
 }
 
+impl<'ctx> Repr<'ctx> {
+    /// Get the LLVM type values of this representation are lowered to, if any
+    ///
+    /// Functions are lowered to pointers to them, while `Repr::Prop` and `Repr::Empty` have no LLVM type.
+    #[inline]
+    pub fn basic_type(&self) -> Option<BasicTypeEnum<'ctx>> {
+        match self {
+            Repr::Type(t) => Some(*t),
+            Repr::Function(f) => Some(f.repr.ptr_type(AddressSpace::Generic).into()),
+            Repr::Product(p) => Some(p.repr.into()),
+            Repr::Prop | Repr::Empty => None,
+        }
+    }
+
+    /// Get the LLVM type values of this representation are lowered to by a given code-generation context, if any
+    ///
+    /// As for `basic_type`, except that functions are lowered to the pointer type given by `Codegen::fn_ptr_to`.
+    #[inline]
+    pub fn basic_type_in(&self, codegen: &Codegen<'ctx>) -> Option<BasicTypeEnum<'ctx>> {
+        match self {
+            Repr::Function(f) => Some(codegen.fn_ptr_to(f.repr).into()),
+            _ => self.basic_type(),
+        }
+    }
+
+    /// Check that this representation lowers to a given LLVM type, e.g. a known C struct layout at an FFI boundary
+    ///
+    /// Returns `Error::ReprMismatch` otherwise.
+    pub fn assert_type(&self, expected: BasicTypeEnum<'ctx>) -> Result<(), Error> {
+        if self.basic_type() == Some(expected) {
+            Ok(())
+        } else {
+            Err(Error::ReprMismatch(format!("{:?}", self)))
+        }
+    }
+
+    /// Check that this representation lowers to a given LLVM type in a given code-generation context
+    ///
+    /// As for `assert_type`, except that function types are checked against `Codegen::fn_ptr_to`.
+    pub fn assert_type_in(
+        &self,
+        codegen: &Codegen<'ctx>,
+        expected: BasicTypeEnum<'ctx>,
+    ) -> Result<(), Error> {
+        if self.basic_type_in(codegen) == Some(expected) {
+            Ok(())
+        } else {
+            Err(Error::ReprMismatch(format!("{:?}", self)))
+        }
+    }
+}
+
 /**
 An LLVM representation of a value for a `rain` type
 */
@@ -58,12 +114,49 @@ pub enum Val<'ctx> {
     Value(BasicValueEnum<'ctx>),
     /// As a function
     Function(FunctionValue<'ctx>),
+    /// As a closure, i.e. a pair of a function pointer and a pointer to its environment
+    Closure(StructValue<'ctx>),
     /// A value of the unit type, indicating a no-op`
     Unit,
     /// A contradiction, indicating undefined behaviour
     Contr,
 }
 
+impl<'ctx> Val<'ctx> {
+    /// Get the LLVM type of this value, if any
+    ///
+    /// Functions have the type of a pointer to them, while `Val::Unit` and `Val::Contr` have no LLVM type.
+    #[inline]
+    pub fn basic_type(&self) -> Option<BasicTypeEnum<'ctx>> {
+        match self {
+            Val::Value(v) => Some(v.get_type()),
+            Val::Function(f) => Some(f.as_global_value().as_pointer_value().get_type().into()),
+            Val::Closure(c) => Some(c.get_type().into()),
+            Val::Unit | Val::Contr => None,
+        }
+    }
+
+    /// Whether this value is known to be constant, and hence may be used in any function
+    ///
+    /// Functions and `Val::Unit` are always constant, whereas closures, which point to the stack, and `Val::Contr` are
+    /// not.
+    #[inline]
+    pub fn is_const(&self) -> bool {
+        match self {
+            Val::Value(BasicValueEnum::IntValue(i)) => i.is_const(),
+            Val::Value(BasicValueEnum::FloatValue(f)) => f.is_const(),
+            Val::Value(BasicValueEnum::PointerValue(p)) => p.is_const(),
+            Val::Value(BasicValueEnum::ArrayValue(a)) => a.is_const(),
+            // SAFETY: `s` is a valid LLVM value
+            Val::Value(BasicValueEnum::StructValue(s)) => unsafe {
+                LLVMIsConstant(s.as_value_ref()) != 0
+            },
+            Val::Value(_) | Val::Closure(_) | Val::Contr => false,
+            Val::Function(_) | Val::Unit => true,
+        }
+    }
+}
+
 impl<'ctx> From<BasicValueEnum<'ctx>> for Val<'ctx> {
     #[inline]
     fn from(b: BasicValueEnum<'ctx>) -> Val<'ctx> {
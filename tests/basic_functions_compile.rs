@@ -1,14 +1,28 @@
+use hayami_im_rc::{SymbolMap, SymbolTable};
+use inkwell::attributes::AttributeLoc;
 use inkwell::context::Context;
 use inkwell::execution_engine::JitFunction;
-use inkwell::values::{FunctionValue, IntValue};
-use inkwell::OptimizationLevel;
+use inkwell::module::Linkage;
+use inkwell::targets::TargetData;
+use inkwell::values::{BasicValueEnum, FunctionValue, InstructionOpcode, IntValue};
+use inkwell::{AddressSpace, IntPredicate, OptimizationLevel};
 use rain_builder::Builder;
 use rain_ir::control::ternary::Ternary;
 use rain_ir::primitive::bits::{BinOp, BitsTy, Neg};
-use rain_ir::value::{ValId, Value};
-use rain_llvm::codegen::Codegen;
-use rain_llvm::repr::Val;
+use rain_ir::primitive::finite::Finite;
+use rain_ir::primitive::logical;
+use rain_ir::typing::Typed;
+use rain_ir::value::expr::Sexpr;
+use rain_ir::value::{ValId, Value, ValueEnum};
+use rain_llvm::codegen::{Codegen, TargetProfile};
+use rain_llvm::error::Error;
+use rain_llvm::lowering::{
+    IteLowering, PrimitiveLowering, SelectLowering, SignedBitsLowering, SignedBitsOp,
+};
+use rain_llvm::repr::{FunctionRepr, IxMap, Repr, ReprIx, Val};
+use std::cell::RefCell;
 use std::convert::{Into, TryInto};
+use std::rc::Rc;
 
 #[test]
 fn boolean_identity_compiles() {
@@ -510,7 +524,7 @@ fn bits_idx() {
         assert!(app_result.is_const());
         assert_eq!(app_result.get_zero_extended_constant(), Some(1));
     }
-    
+
     {
         let t1 = BitsTy(8).data(3).unwrap();
         let (rest, ix) = builder
@@ -534,3 +548,5655 @@ fn bits_idx() {
         assert_eq!(app_result.get_zero_extended_constant(), Some(0));
     }
 }
+
+#[test]
+fn failed_lambda_leaves_no_function() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("failed_lambda");
+    let mut codegen = Codegen::new(&context, module);
+
+    // The body applies a function with a dependent return type, which is not yet supported
+    let (rest, bad) = builder
+        .parse_expr("|x: #bool| (|T: #type y: T| y) #bool x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    assert!(codegen.build(&bad).is_err());
+    assert_eq!(codegen.module().get_function("__lambda_0"), None);
+    assert_eq!(codegen.module().get_first_function(), None);
+}
+
+#[test]
+fn xor_with_self_is_false() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("xor_self");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, xor_self) = builder
+        .parse_expr("|x: #bool| (#xor x x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let f: FunctionValue = codegen
+        .build(&xor_self)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("ret i1 false"), "Unexpected IR: {}", ir);
+    assert!(!ir.contains("xor"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn table_select_on_finite_index() {
+    let context = Context::create();
+    let module = context.create_module("table_select");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let entries: Vec<ValId> = [7, 42, 13, 255]
+        .iter()
+        .map(|x| BitsTy(8).data(*x).unwrap().into())
+        .collect();
+
+    // Constant indices select directly
+    let two = context.i8_type().const_int(2, false);
+    let selected: IntValue = codegen
+        .build_table_select(two.into(), &entries[..])
+        .expect("Valid selection")
+        .try_into()
+        .expect("Integer value");
+    assert_eq!(selected.get_zero_extended_constant(), Some(13));
+
+    // Runtime indices go through a lookup table
+    let i8_type = context.i8_type();
+    let f =
+        codegen
+            .module()
+            .add_function("select", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let ix = f.get_nth_param(0).unwrap();
+    let selected: IntValue = codegen
+        .build_table_select(ix.into(), &entries[..])
+        .expect("Valid selection")
+        .try_into()
+        .expect("Integer value");
+    codegen.builder().build_return(Some(&selected));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("select") }.expect("Valid IR generated");
+
+    // Run
+    for (ix, expected) in [7, 42, 13, 255].iter().enumerate() {
+        unsafe {
+            assert_eq!(jit_f.call(ix as u8), *expected);
+        }
+    }
+}
+
+#[test]
+fn table_select_on_wide_index() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("table_select_wide");
+    let mut codegen = Codegen::new(&context, module);
+
+    let entries: Vec<ValId> = [7, 42, 13]
+        .iter()
+        .map(|x| BitsTy(8).data(*x).unwrap().into())
+        .collect();
+
+    // A 128-bit index cannot be used as a GEP index without losing bits
+    let i128_type = context.i128_type();
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "select_wide",
+        i8_type.fn_type(&[i128_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let ix = f.get_nth_param(0).unwrap();
+    match codegen.build_table_select(ix.into(), &entries[..]) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected a wide index to be unsupported, got {:?}", r),
+    }
+    assert!(codegen.module().get_global("__table_0").is_none());
+
+    // A 64-bit index is used as-is
+    let i64_type = context.i64_type();
+    let f = codegen.module().add_function(
+        "select_narrow",
+        i8_type.fn_type(&[i64_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let ix = f.get_nth_param(0).unwrap();
+    let selected: IntValue = codegen
+        .build_table_select(ix.into(), &entries[..])
+        .expect("Valid selection")
+        .try_into()
+        .expect("Integer value");
+    codegen.builder().build_return(Some(&selected));
+    assert!(f.verify(true));
+
+    // Tables are named by their own counter, so they do not shift the names of lambdas
+    assert!(codegen.module().get_global("__table_0").is_some());
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+    assert_eq!(f_name, "__lambda_0");
+}
+
+#[test]
+fn mismatched_ternary_branch_is_reported() {
+    let context = Context::create();
+    let bool_ty = context.bool_type();
+    let i8_ty = context.i8_type();
+    assert!(Codegen::check_branch_repr(bool_ty.into(), bool_ty.const_int(1, false).into()).is_ok());
+    match Codegen::check_branch_repr(bool_ty.into(), i8_ty.const_int(1, false).into()) {
+        Err(Error::InternalError(_)) => {}
+        r => panic!("Expected an internal error, got {:?}", r),
+    }
+}
+
+#[test]
+fn ternary_branches_do_not_share_local_values() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("ternary_branch_scopes");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, params) = builder
+        .parse_expr("|c: #bool x: #bool y: #bool| c")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match params.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let region = lambda.get_ty().def_region().clone();
+    let c = ValId::from(region.clone().param(0).expect("Lambda has a parameter"));
+    let x = ValId::from(region.clone().param(1).expect("Lambda has two parameters"));
+    let y = ValId::from(region.param(2).expect("Lambda has three parameters"));
+
+    // `#not x` is first built in the high branch, and needed again in the low branch and after the branches join
+    let not_x: ValId = Sexpr::try_new(vec![logical::Not.into(), x.clone()].into())
+        .expect("Valid application")
+        .into();
+    let low: ValId = Sexpr::try_new(vec![logical::And.into(), not_x.clone(), y.clone()].into())
+        .expect("Valid application")
+        .into();
+    let t: ValId = Ternary::conditional(not_x.clone(), low).unwrap().into();
+    let branch: ValId = Sexpr::try_new(vec![t, c.clone()].into())
+        .expect("Valid application")
+        .into();
+    let app: ValId = Sexpr::try_new(vec![logical::And.into(), branch, not_x].into())
+        .expect("Valid application")
+        .into();
+
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "ternary_branch_scopes",
+        bool_type.fn_type(
+            &[bool_type.into(), bool_type.into(), bool_type.into()],
+            false,
+        ),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    for (ix, param) in [&c, &x, &y].iter().enumerate() {
+        locals.insert(
+            (*param).clone(),
+            Val::Value(f.get_nth_param(ix as u32).unwrap()),
+        );
+    }
+    let result: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("ternary_branch_scopes") }
+            .expect("Valid IR generated");
+
+    // Run
+    for &c in &[true, false] {
+        for &x in &[true, false] {
+            for &y in &[true, false] {
+                let branch = if c { !x } else { !x && y };
+                unsafe {
+                    assert_eq!(jit_f.call(c, x, y), branch && !x);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn propositional_ternary_result_needs_no_branches() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("propositional_ternary");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder.parse_expr("|c: #bool| c").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match id.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let c = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let (rest, unit) = builder.parse_expr("#ix(1)[0]").expect("Valid index");
+    assert_eq!(rest, "");
+    let t: ValId = Ternary::conditional(unit.clone(), unit).unwrap().into();
+    let app: ValId = Sexpr::try_new(vec![t, c.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let f = codegen.module().add_function(
+        "propositional_ternary",
+        context
+            .void_type()
+            .fn_type(&[context.bool_type().into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(c, Val::Value(f.get_nth_param(0).unwrap()));
+    assert_eq!(
+        codegen
+            .build_in_function(f, &app, locals)
+            .expect("Compilation works"),
+        Val::Unit
+    );
+    assert_eq!(f.count_basic_blocks(), 1);
+}
+
+#[test]
+fn empty_ternary_result_needs_no_branches() {
+    let context = Context::create();
+    let module = context.create_module("empty_ternary");
+    let mut codegen = Codegen::new(&context, module);
+
+    let f = codegen.module().add_function(
+        "empty_ternary",
+        context
+            .void_type()
+            .fn_type(&[context.bool_type().into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    // An empty result is unreachable, whichever branch is taken
+    let cond = f.get_nth_param(0).unwrap().into_int_value();
+    let result = codegen
+        .with_function(f, |codegen| {
+            codegen.build_branches_inline(Repr::Empty, cond, &true.into(), &false.into(), false)
+        })
+        .expect("Compilation works");
+    assert_eq!(result, Val::Contr);
+    assert_eq!(f.count_basic_blocks(), 1);
+}
+
+#[test]
+fn mismatched_ternary_branch_is_reported_through_phi() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("mismatched_ternary");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder.parse_expr("|c: #bool| c").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match id.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let c = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let (rest, high) = builder.parse_expr("#ix(6)[4]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, low) = builder.parse_expr("#ix(6)[2]").expect("Valid index");
+    assert_eq!(rest, "");
+    let t: ValId = Ternary::conditional(high.clone(), low).unwrap().into();
+    let app: ValId = Sexpr::try_new(vec![t, c.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    // Bind the high branch to an `i32`, whereas the ternary's result, and its low branch, are represented by an `i8`
+    let i32_type = context.i32_type();
+    let f = codegen.module().add_function(
+        "mismatched_ternary",
+        context
+            .i8_type()
+            .fn_type(&[context.bool_type().into(), i32_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(c, Val::Value(f.get_nth_param(0).unwrap()));
+    locals.insert(high, Val::Value(f.get_nth_param(1).unwrap()));
+    match codegen.build_in_function(f, &app, locals) {
+        Err(Error::InternalError(_)) => {}
+        r => panic!("Expected a mismatched branch to be reported, got {:?}", r),
+    }
+
+    // The mismatch is caught before any phi node is built
+    let ir = f.print_to_string().to_string();
+    assert!(!ir.contains("phi"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn logical_short_circuits_on_constant_first_argument() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("short_circuit");
+    let mut codegen = Codegen::new(&context, module);
+
+    // Compiling this value would emit a function, so we can check whether it was built
+    let (rest, expensive) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let and_result: IntValue = codegen
+        .build_logical_expr(logical::And, &[false.into(), expensive.clone()])
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean value");
+    assert_eq!(and_result.get_zero_extended_constant(), Some(0));
+
+    let or_result: IntValue = codegen
+        .build_logical_expr(logical::Or, &[true.into(), expensive])
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean value");
+    assert_eq!(or_result.get_zero_extended_constant(), Some(1));
+
+    assert_eq!(codegen.module().get_first_function(), None);
+}
+
+#[test]
+fn lambda_body_into_predeclared_function() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("predeclared");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, nand) = builder
+        .parse_expr("|x: #bool y: #bool| (#not (#and x y))")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match nand.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+
+    // Forward declaration
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "my_nand",
+        bool_type.fn_type(&[bool_type.into(), bool_type.into()], false),
+        None,
+    );
+    codegen
+        .build_lambda_body_into(lambda, f)
+        .expect("Compilation works");
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("my_nand") }.expect("Valid IR generated");
+
+    // Run
+    for x in [true, false].iter().copied() {
+        for y in [true, false].iter().copied() {
+            unsafe {
+                assert_eq!(jit_f.call(x, y), !(x && y));
+            }
+        }
+    }
+}
+
+#[test]
+fn compiled_values_can_be_retrieved() {
+    let context = Context::create();
+    let module = context.create_module("get_compiled");
+    let mut codegen = Codegen::new(&context, module);
+
+    let t: ValId = BitsTy(8).data(42).unwrap().into();
+    assert_eq!(codegen.get_compiled(&t), None);
+    let built = codegen.build(&t).expect("Compilation works");
+    assert_eq!(codegen.get_compiled(&t), Some(&built));
+}
+
+#[test]
+fn bool_represented_indices_compile() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("bool_index");
+    let mut codegen = Codegen::new(&context, module);
+
+    for (ix, expr) in ["#ix(2)[0]", "#ix(2)[1]"].iter().enumerate() {
+        let (rest, ix_val) = builder.parse_expr(expr).expect("Valid Index Instance");
+        assert_eq!(rest, "");
+        let val: IntValue = codegen
+            .build(&ix_val)
+            .expect("Valid value")
+            .try_into()
+            .expect("Integer value");
+        assert_eq!(val.get_type().get_bit_width(), 1);
+        assert_eq!(val.get_zero_extended_constant(), Some(ix as u64));
+    }
+
+    // Out-of-range indices are rejected before they ever reach code generation
+    assert!(builder.parse_expr("#ix(2)[2]").is_err());
+}
+
+#[test]
+fn aggregate_seed_follows_flag() {
+    let context = Context::create();
+    let module = context.create_module("aggregate_seed");
+    let mut codegen = Codegen::new(&context, module);
+    let struct_ty = context.struct_type(
+        &[context.i8_type().into(), context.i16_type().into()],
+        false,
+    );
+
+    assert!(!codegen.zero_init_aggregates());
+    let seed = codegen.aggregate_seed(struct_ty);
+    assert!(seed.is_undef());
+
+    codegen.set_zero_init_aggregates(true);
+    let seed = codegen.aggregate_seed(struct_ty);
+    assert!(!seed.is_undef());
+    assert!(seed
+        .print_to_string()
+        .to_string()
+        .contains("zeroinitializer"));
+}
+
+#[test]
+fn unit_functions_are_emitted_when_requested() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("unit_function");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_emit_unit_functions(true);
+
+    let (rest, unit_fn) = builder
+        .parse_expr("|x: #bool| #ix(1)[0]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    codegen.set_name(&unit_fn, "unit_fn".to_string());
+
+    let f: FunctionValue = codegen
+        .build(&unit_fn)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.get_type().get_return_type().is_none());
+    assert!(f.verify(true));
+
+    // Unit functions are named like any other lambda
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+    assert_eq!(f_name, "unit_fn");
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool)> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        jit_f.call(true);
+        jit_f.call(false);
+    }
+}
+
+#[test]
+fn pointer_backed_projection_uses_gep() {
+    let context = Context::create();
+    let module = context.create_module("pointer_projection");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let i16_type = context.i16_type();
+    let struct_ty = context.struct_type(&[context.i8_type().into(), i16_type.into()], false);
+    let f = codegen.module().add_function(
+        "second",
+        i16_type.fn_type(&[struct_ty.ptr_type(AddressSpace::Generic).into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let ptr = f.get_nth_param(0).unwrap();
+    let second: IntValue = codegen
+        .build_projection(ptr.into(), 1)
+        .expect("Valid projection")
+        .try_into()
+        .expect("Integer value");
+    codegen.builder().build_return(Some(&second));
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(
+        ir.contains("getelementptr inbounds"),
+        "Unexpected IR: {}",
+        ir
+    );
+
+    #[repr(C)]
+    struct Pair {
+        first: i8,
+        second: i16,
+    }
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(*const Pair) -> i16> =
+        unsafe { execution_engine.get_function("second") }.expect("Valid IR generated");
+
+    // Run
+    let pair = Pair {
+        first: 3,
+        second: 517,
+    };
+    assert_eq!(pair.first, 3);
+    unsafe {
+        assert_eq!(jit_f.call(&pair), 517);
+    }
+}
+
+#[test]
+fn projection_out_of_non_product_is_reported() {
+    let context = Context::create();
+    let module = context.create_module("non_product_projection");
+    let mut codegen = Codegen::new(&context, module);
+    let byte = context.i8_type().const_int(3, false);
+    match codegen.build_projection(Val::Value(byte.into()), 0) {
+        Err(Error::InternalError(_)) => {}
+        r => panic!("Expected an internal error, got {:?}", r),
+    }
+}
+
+#[test]
+fn byte_strings_compile_to_global_arrays() {
+    let context = Context::create();
+    let module = context.create_module("byte_string");
+    let mut codegen = Codegen::new(&context, module);
+
+    let ptr = match codegen.build_byte_string(b"hello") {
+        Val::Value(BasicValueEnum::PointerValue(p)) => p,
+        v => panic!("Expected a pointer, got {:?}", v),
+    };
+    assert_eq!(
+        ptr.get_type(),
+        context.i8_type().ptr_type(AddressSpace::Generic)
+    );
+    let global = codegen
+        .module()
+        .get_first_global()
+        .expect("A global was emitted");
+    assert!(global.is_constant());
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(ir.contains("c\"hello\""), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn lambda_inline_at_global_level() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("lambda_inline");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match not.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+
+    for x in [true, false].iter().copied() {
+        let param: Val = codegen.build_bool(x).into();
+        let result: IntValue = codegen
+            .build_lambda_inline(lambda, &[param])
+            .expect("Compilation works")
+            .try_into()
+            .expect("A boolean value");
+        assert_eq!(result.get_zero_extended_constant(), Some(!x as u64));
+    }
+    // Inlining emits no functions
+    assert_eq!(codegen.module().get_first_function(), None);
+}
+
+#[test]
+fn lambda_inline_reuses_local_table() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("lambda_inline_in_place");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let t: ValId = true.into();
+    let f: ValId = false.into();
+    // `((|x: #bool| (#not x)) #true)` is inlined at the global level, where a local table is already present
+    let app: ValId = Sexpr::try_new(vec![not, t.clone()].into())
+        .expect("Valid application")
+        .into();
+    // `#false` is looked up in the same table after the inlined lambda's parameters have been popped off of it
+    let or: ValId = Sexpr::try_new(vec![logical::Or.into(), app, f.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let bool_type = context.bool_type();
+    let g = codegen.module().add_function(
+        "not_or",
+        bool_type.fn_type(&[bool_type.into(), bool_type.into()], false),
+        None,
+    );
+    context.append_basic_block(g, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(t, Val::Value(g.get_nth_param(0).unwrap()));
+    locals.insert(f, Val::Value(g.get_nth_param(1).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(g, &or, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean value");
+    assert!(!result.is_const());
+    codegen.builder().build_return(Some(&result));
+    assert!(g.verify(true));
+
+    // Inlining emits no functions
+    let ir = codegen.module().print_to_string().to_string();
+    assert_eq!(ir.matches("define").count(), 1, "Unexpected IR: {}", ir);
+    assert!(!ir.contains("call"), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("not_or") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true, false), false);
+        assert_eq!(jit_f.call(false, false), true);
+        assert_eq!(jit_f.call(true, true), true);
+    }
+}
+
+#[test]
+fn bits_mod() {
+    let context = Context::create();
+    let module = context.create_module("bits_mod");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let t1 = BitsTy(8).data(7).unwrap();
+    let t2 = BitsTy(8).data(3).unwrap();
+    let mod_struct = BinOp::Mod.into_var();
+    let arg_vec: Vec<ValId> = vec![BitsTy(8).into_val(), t1.into(), t2.into()];
+    let app_result: IntValue = codegen
+        .build_app(mod_struct.as_val(), &arg_vec[..])
+        .unwrap()
+        .try_into()
+        .expect("Result of building Mod should be an int");
+    assert!(app_result.is_const());
+    assert_eq!(app_result.get_zero_extended_constant(), Some(1));
+
+    // Constant zero divisor
+    let zero = BitsTy(8).data(0).unwrap();
+    let arg_vec: Vec<ValId> = vec![BitsTy(8).into_val(), t1.into(), zero.into()];
+    assert_eq!(
+        codegen
+            .build_app(mod_struct.as_val(), &arg_vec[..])
+            .unwrap(),
+        Val::Contr
+    );
+
+    // Runtime values
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "umod",
+        i8_type.fn_type(&[i8_type.into(), i8_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let lhs = f.get_nth_param(0).unwrap().into_int_value();
+    let rhs = f.get_nth_param(1).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_bits_binop(BinOp::Mod, lhs, rhs)
+        .unwrap()
+        .try_into()
+        .expect("An int");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8, u8) -> u8> =
+        unsafe { execution_engine.get_function("umod") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(7, 3), 1);
+        assert_eq!(jit_f.call(200, 7), 4);
+    }
+}
+
+#[test]
+fn ternary_selects_between_functions() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("function_ternary");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, and) = builder
+        .parse_expr("|x: #bool y: #bool| (#and x y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, or) = builder
+        .parse_expr("|x: #bool y: #bool| (#or x y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let t = Ternary::conditional(and, or).unwrap();
+    let f: FunctionValue = codegen
+        .build(&t.into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles functions");
+    assert!(f.verify(true));
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    // Jit
+    type BinaryLogical = unsafe extern "C" fn(bool, bool) -> bool;
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> BinaryLogical> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    for x in [true, false].iter().copied() {
+        for y in [true, false].iter().copied() {
+            unsafe {
+                assert_eq!(jit_f.call(false)(x, y), x && y);
+                assert_eq!(jit_f.call(true)(x, y), x || y);
+            }
+        }
+    }
+}
+
+#[test]
+fn generated_names_carry_prefix() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("name_prefix");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_name_prefix("mymod".to_string());
+    assert_eq!(codegen.name_prefix(), "mymod");
+
+    let (rest, id) = builder.parse_expr("|x: #bool| x").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&id)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(f.get_name().to_str(), Ok("mymod__lambda_0"));
+
+    let t = Ternary::conditional(false.into(), true.into()).unwrap();
+    let f: FunctionValue = codegen
+        .build(&t.into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles functions");
+    assert_eq!(f.get_name().to_str(), Ok("mymod__tern_1"));
+}
+
+#[test]
+fn partially_applied_projection_compiles() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("projection_fn");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, product) = builder
+        .parse_expr("#product[#finite(73) #finite(1025)]")
+        .expect("Valid product");
+    assert_eq!(rest, "");
+    let product = match product.as_enum() {
+        ValueEnum::Product(p) => p,
+        _ => panic!("Expected a product"),
+    };
+
+    let f: FunctionValue = codegen
+        .build_projection_fn(product, 1)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let f_shim = codegen.build_shim(f, "proj_shim", None);
+    let f_shim_name = f_shim
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    #[repr(C)]
+    struct Pair {
+        first: i8,
+        second: i16,
+    }
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(*mut Pair) -> i16> =
+        unsafe { execution_engine.get_function(f_shim_name) }.expect("Valid IR generated");
+
+    // Run
+    for second in 0..10 {
+        let mut pair = Pair { first: 3, second };
+        assert_eq!(pair.first, 3);
+        unsafe {
+            assert_eq!(jit_f.call(&mut pair), second);
+        }
+    }
+}
+
+#[test]
+fn unrepresentable_parameter_is_an_error() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("unrepresentable");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, f) = builder
+        .parse_expr("|x: #type| #true")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    match codegen.build(&f) {
+        Err(Error::UnrepresentableType) => {}
+        r => panic!("Expected an unrepresentable type error, got {:?}", r),
+    }
+    assert_eq!(codegen.module().get_first_function(), None);
+}
+
+#[test]
+fn index_and_bits_coercions() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("coercions");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, ix) = builder
+        .parse_expr("#ix(6)[4]")
+        .expect("Valid Index Instance");
+    assert_eq!(rest, "");
+    let ix_val = codegen.build(&ix).expect("Valid value");
+    let bits: IntValue = codegen
+        .build_index_to_bits(ix_val, &BitsTy(8))
+        .expect("Valid coercion")
+        .try_into()
+        .expect("Integer value");
+    assert_eq!(bits.get_type().get_bit_width(), 8);
+    assert_eq!(bits.get_zero_extended_constant(), Some(4));
+
+    let wide = context.i32_type().const_int(10, false);
+    let back: IntValue = codegen
+        .build_bits_to_index(wide, &Finite(6))
+        .expect("Valid coercion")
+        .try_into()
+        .expect("Integer value");
+    assert_eq!(back.get_type().get_bit_width(), 8);
+    assert_eq!(back.get_zero_extended_constant(), Some(4));
+}
+
+#[test]
+fn boolean_identity_golden_ir() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("identity_bool");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, bool_id) = builder.parse_expr("|x: #bool| x").expect("Valid function");
+    assert_eq!(rest, "");
+
+    let ir = codegen
+        .compile_to_string(&bool_id)
+        .expect("Compilation works");
+    assert_eq!(
+        ir,
+        "; ModuleID = 'identity_bool'\n\
+         source_filename = \"identity_bool\"\n\
+         \n\
+         define i1 @__lambda_0(i1 %0) {\n\
+         entry:\n  \
+         ret i1 %0\n\
+         }\n"
+    );
+}
+
+#[test]
+fn thunk_compiles() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("thunk");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, thunk) = builder.parse_expr("|| #true").expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let f: FunctionValue = codegen
+        .build(&thunk)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(f.count_params(), 0);
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn() -> bool> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(), true);
+    }
+}
+
+#[test]
+fn stack_slots_have_lifetime_markers() {
+    let context = Context::create();
+    let module = context.create_module("lifetimes");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_emit_lifetime_markers(true);
+
+    let i8_type = context.i8_type();
+    let f = codegen
+        .module()
+        .add_function("slot", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let slot = codegen
+        .build_stack_slot(i8_type.into())
+        .expect("In a function");
+    codegen
+        .builder()
+        .build_store(slot, f.get_nth_param(0).unwrap());
+    let result = codegen.builder().build_load(slot, "result");
+    codegen.end_stack_slot(slot);
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    let start = ir
+        .find("llvm.lifetime.start")
+        .expect("Lifetime start emitted");
+    let end = ir.find("llvm.lifetime.end").expect("Lifetime end emitted");
+    assert!(ir.find("alloca").expect("Alloca emitted") < start);
+    assert!(start < end);
+}
+
+#[test]
+fn open_terms_compile_under_provided_locals() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("open_term");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match not.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let x = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+
+    let mut locals = SymbolTable::default();
+    locals.insert(x.clone(), codegen.build_bool(true).into());
+    let result: IntValue = codegen
+        .build_with_locals(&x, locals.clone())
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean value");
+    assert_eq!(result.get_zero_extended_constant(), Some(1));
+
+    let result: IntValue = codegen
+        .build_with_locals(lambda.result(), locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean value");
+    assert_eq!(result.get_zero_extended_constant(), Some(0));
+}
+
+#[test]
+fn ternary_with_identical_branches_has_no_phi() {
+    let context = Context::create();
+    let module = context.create_module("identical_branches");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let t = Ternary::conditional(true.into(), true.into()).unwrap();
+    let f: FunctionValue = codegen
+        .build(&t.into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles functions");
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(!ir.contains("phi"), "Unexpected IR: {}", ir);
+
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(b: bool) -> bool> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(false), true);
+        assert_eq!(jit_f.call(true), true);
+    }
+}
+
+#[test]
+fn exact_width_bits_representation() {
+    let context = Context::create();
+    let module = context.create_module("exact_widths");
+    let mut codegen = Codegen::new(&context, module);
+
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(24))
+            .expect("Valid representation"),
+        Repr::Type(context.i32_type().into())
+    );
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(14))
+            .expect("Valid representation"),
+        Repr::Type(context.i16_type().into())
+    );
+    codegen.set_exact_int_widths(true);
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(14))
+            .expect("Valid representation"),
+        Repr::Type(context.custom_width_int_type(14).into())
+    );
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(24))
+            .expect("Valid representation"),
+        Repr::Type(context.custom_width_int_type(24).into())
+    );
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(64))
+            .expect("Valid representation"),
+        Repr::Type(context.i64_type().into())
+    );
+
+    let i: IntValue = codegen
+        .build(&BitsTy(24).data(8848).unwrap().into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles values");
+    assert_eq!(i.get_type().get_bit_width(), 24);
+}
+
+#[test]
+fn bits_sub() {
+    let context = Context::create();
+    let module = context.create_module("bits");
+    let mut codegen = Codegen::new(&context, module);
+
+    let t1 = BitsTy(8).data(5).unwrap();
+    let t2 = BitsTy(8).data(7).unwrap();
+
+    let sub_struct = BinOp::Sub.into_var();
+
+    let arg_vec: Vec<ValId> = vec![BitsTy(8).into_val(), t1.into(), t2.into()];
+    let app_result: IntValue = codegen
+        .build_app(sub_struct.as_val(), &arg_vec[..])
+        .unwrap()
+        .try_into()
+        .expect("Result of building Sub should be an int");
+    assert_eq!(app_result.get_type().get_bit_width(), 8);
+    assert_eq!(app_result.get_zero_extended_constant(), Some(254));
+    assert!(app_result.is_const());
+
+    // Partial application is reported as an error
+    let arg_vec: Vec<ValId> = vec![BitsTy(8).into_val(), t1.into()];
+    assert!(codegen
+        .build_app(sub_struct.as_val(), &arg_vec[..])
+        .is_err());
+}
+
+#[test]
+fn bits_sub_without_wrapping() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("bits_sub");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let five: ValId = BitsTy(8).data(5).unwrap().into();
+    let two: ValId = BitsTy(8).data(2).unwrap().into();
+    let sub_struct = BinOp::Sub.into_var();
+
+    // Constant folding
+    let arg_vec: Vec<ValId> = vec![BitsTy(8).into_val(), five.clone(), two.clone()];
+    let app_result: IntValue = codegen
+        .build_app(sub_struct.as_val(), &arg_vec[..])
+        .unwrap()
+        .try_into()
+        .expect("Result of building Sub should be an int");
+    assert_eq!(app_result.get_type().get_bit_width(), 8);
+    assert_eq!(app_result.get_zero_extended_constant(), Some(3));
+    assert!(app_result.is_const());
+
+    // Non-bits operands are reported rather than panicking
+    let (rest, id) = builder.parse_expr("|x: #bool| x").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let arg_vec: Vec<ValId> = vec![BitsTy(8).into_val(), five.clone(), id];
+    match codegen.build_app(sub_struct.as_val(), &arg_vec[..]) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected a non-bits operand to be reported, got {:?}", r),
+    }
+
+    // Runtime values, binding operands which have no global definition yet to the parameters of a function
+    let lhs: ValId = BitsTy(8).data(11).unwrap().into();
+    let rhs: ValId = BitsTy(8).data(13).unwrap().into();
+    let sub: ValId = Sexpr::try_new(
+        vec![
+            sub_struct.as_val().clone(),
+            BitsTy(8).into_val(),
+            lhs.clone(),
+            rhs.clone(),
+        ]
+        .into(),
+    )
+    .expect("Valid application")
+    .into();
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "sub",
+        i8_type.fn_type(&[i8_type.into(), i8_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(lhs, Val::Value(f.get_nth_param(0).unwrap()));
+    locals.insert(rhs, Val::Value(f.get_nth_param(1).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(f, &sub, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert!(!result.is_const());
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8, u8) -> u8> =
+        unsafe { execution_engine.get_function("sub") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(5, 2), 3);
+        assert_eq!(jit_f.call(200, 58), 142);
+        assert_eq!(jit_f.call(1, 3), 254);
+    }
+}
+
+#[test]
+fn nested_constant_tuples_are_constant() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("nested_constant_tuple");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, nested) = builder
+        .parse_expr("[[#ix(3)[1] #ix(5)[2]] #ix(7)[3]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let nested = codegen.build(&nested).expect("Compilation works");
+    assert!(nested.is_const());
+
+    // A constant tuple can be used directly as the return value of a thunk, without any `insertvalue`s
+    let (rest, thunk) = builder
+        .parse_expr("|| [[#ix(3)[1] #ix(5)[2]] #ix(7)[3]]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&thunk)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(!ir.contains("insertvalue"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn shared_tuple_members_are_built_once() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("shared_members");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, pair) = builder
+        .parse_expr("|y: #bool| [(#not y) (#not y)]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let f: FunctionValue = codegen
+        .build(&pair)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert_eq!(ir.matches("xor").count(), 1, "Unexpected IR: {}", ir);
+    assert_eq!(
+        ir.matches("insertvalue").count(),
+        2,
+        "Unexpected IR: {}",
+        ir
+    );
+}
+
+#[test]
+fn intrinsics_can_be_called_by_name() {
+    let context = Context::create();
+    let module = context.create_module("intrinsic");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let i32_type = context.i32_type();
+    let f = codegen
+        .module()
+        .add_function("popcount", i32_type.fn_type(&[], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = i32_type.const_int(0b10110, false);
+    let result: IntValue = codegen
+        .build_intrinsic("llvm.ctpop.i32", Repr::Type(i32_type.into()), &[arg.into()])
+        .expect("Valid intrinsic call")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+    assert!(codegen.module().get_function("llvm.ctpop.i32").is_some());
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn() -> u32> =
+        unsafe { execution_engine.get_function("popcount") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(), 3);
+    }
+}
+
+#[test]
+fn bit_counting() {
+    let context = Context::create();
+    let module = context.create_module("bit_counting");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let i8_type = context.i8_type();
+    let x = i8_type.const_int(0b10110, false);
+    let fold = |v: Val| -> Option<u64> {
+        let v: IntValue = v.try_into().expect("An integer");
+        v.get_zero_extended_constant()
+    };
+    assert_eq!(fold(codegen.build_popcount(x, 8).unwrap()), Some(3));
+    assert_eq!(fold(codegen.build_ctlz(x, 8).unwrap()), Some(3));
+    assert_eq!(fold(codegen.build_cttz(x, 8).unwrap()), Some(1));
+    let zero = i8_type.const_int(0, false);
+    assert_eq!(fold(codegen.build_ctlz(zero, 8).unwrap()), Some(8));
+    assert_eq!(fold(codegen.build_cttz(zero, 8).unwrap()), Some(8));
+
+    // Runtime values
+    let f =
+        codegen
+            .module()
+            .add_function("popcount", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = f.get_nth_param(0).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_popcount(arg, 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("popcount") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0b10110), 3);
+        assert_eq!(jit_f.call(0xFF), 8);
+    }
+}
+
+#[test]
+fn bit_counting_narrower_than_representation() {
+    let context = Context::create();
+    let module = context.create_module("bit_counting_narrow");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding: a `#bits(12)` is stored in an `i16`, but its padding is not counted
+    let i16_type = context.i16_type();
+    let x = i16_type.const_int(0x0F0, false);
+    let fold = |v: Val| -> Option<u64> {
+        let v: IntValue = v.try_into().expect("An integer");
+        v.get_zero_extended_constant()
+    };
+    assert_eq!(fold(codegen.build_ctlz(x, 12).unwrap()), Some(4));
+    assert_eq!(fold(codegen.build_cttz(x, 12).unwrap()), Some(4));
+    let zero = i16_type.const_int(0, false);
+    assert_eq!(fold(codegen.build_ctlz(zero, 12).unwrap()), Some(12));
+    assert_eq!(fold(codegen.build_cttz(zero, 12).unwrap()), Some(12));
+
+    // Runtime values
+    let fn_ty = i16_type.fn_type(&[i16_type.into()], false);
+    let ctlz = codegen.module().add_function("ctlz12", fn_ty, None);
+    let entry = context.append_basic_block(ctlz, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = ctlz.get_nth_param(0).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_ctlz(arg, 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(ctlz.verify(true));
+    let cttz = codegen.module().add_function("cttz12", fn_ty, None);
+    let entry = context.append_basic_block(cttz, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = cttz.get_nth_param(0).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_cttz(arg, 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(cttz.verify(true));
+
+    // Jit
+    let jit_ctlz: JitFunction<unsafe extern "C" fn(u16) -> u16> =
+        unsafe { execution_engine.get_function("ctlz12") }.expect("Valid IR generated");
+    let jit_cttz: JitFunction<unsafe extern "C" fn(u16) -> u16> =
+        unsafe { execution_engine.get_function("cttz12") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_ctlz.call(0x0F0), 4);
+        assert_eq!(jit_ctlz.call(0x800), 0);
+        assert_eq!(jit_ctlz.call(0), 12);
+        assert_eq!(jit_cttz.call(0x0F0), 4);
+        assert_eq!(jit_cttz.call(0x800), 11);
+        assert_eq!(jit_cttz.call(0), 12);
+    }
+}
+
+#[test]
+fn bits_rotate() {
+    let context = Context::create();
+    let module = context.create_module("rotate");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let i8_type = context.i8_type();
+    let x = i8_type.const_int(0b1000_0001, false);
+    let one = context.i32_type().const_int(1, false);
+    let rotated: IntValue = codegen
+        .build_rotate_left(x, one, 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(rotated.get_zero_extended_constant(), Some(0b0000_0011));
+    let rotated: IntValue = codegen
+        .build_rotate_right(x, one, 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(rotated.get_zero_extended_constant(), Some(0b1100_0000));
+
+    // Runtime values
+    let f = codegen
+        .module()
+        .add_function("rotl", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = f.get_nth_param(0).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_rotate_left(arg, one, 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("rotl") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0b1000_0001), 0b0000_0011);
+        assert_eq!(jit_f.call(0b0100_0000), 0b1000_0000);
+    }
+}
+
+#[test]
+fn bits_rotate_narrower_than_representation() {
+    let context = Context::create();
+    let module = context.create_module("rotate_narrow");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding: a `#bits(12)` is stored in an `i16`, but rotates within its low 12 bits
+    let i16_type = context.i16_type();
+    let x = i16_type.const_int(0x801, false);
+    let one = context.i32_type().const_int(1, false);
+    let rotated: IntValue = codegen
+        .build_rotate_left(x, one, 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(rotated.get_zero_extended_constant(), Some(0x003));
+    let rotated: IntValue = codegen
+        .build_rotate_right(x, one, 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(rotated.get_zero_extended_constant(), Some(0xC00));
+
+    // Bit vectors wider than their representation are rejected
+    assert!(codegen.build_rotate_left(x, one, 17).is_err());
+
+    // Runtime values
+    let f = codegen.module().add_function(
+        "rotl12",
+        i16_type.fn_type(&[i16_type.into(), i16_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = f.get_nth_param(0).unwrap().into_int_value();
+    let amount = f.get_nth_param(1).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_rotate_left(arg, amount, 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u16, u16) -> u16> =
+        unsafe { execution_engine.get_function("rotl12") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0x801, 1), 0x003);
+        assert_eq!(jit_f.call(0x801, 13), 0x003);
+        assert_eq!(jit_f.call(0x801, 0), 0x801);
+        assert_eq!(jit_f.call(0x123, 4), 0x231);
+    }
+}
+
+#[test]
+fn bits_minmax() {
+    let context = Context::create();
+    let module = context.create_module("minmax");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let i8_type = context.i8_type();
+    let five = i8_type.const_int(5, false);
+    let nine = i8_type.const_int(9, false);
+    let umax: IntValue = codegen
+        .build_minmax(five, nine, false, true)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert!(umax.is_const());
+    assert_eq!(umax.get_zero_extended_constant(), Some(9));
+
+    // Runtime values
+    let f = codegen.module().add_function(
+        "smin",
+        i8_type.fn_type(&[i8_type.into(), i8_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let lhs = f.get_nth_param(0).unwrap().into_int_value();
+    let rhs = f.get_nth_param(1).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_minmax(lhs, rhs, true, false)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(i8, i8) -> i8> =
+        unsafe { execution_engine.get_function("smin") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(5, 9), 5);
+        assert_eq!(jit_f.call(-3, 2), -3);
+    }
+}
+
+#[test]
+fn symbol_map_contains_compiled_functions() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("symbol_map");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder.parse_expr("|x: #bool| x").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    codegen.build(&id).expect("Compilation works");
+    codegen.build(&not).expect("Compilation works");
+
+    let symbols = codegen.symbol_map();
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols[&id], "__lambda_0");
+    assert_eq!(symbols[&not], "__lambda_1");
+}
+
+#[test]
+fn product_with_empty_member_is_empty() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("empty_product");
+    let mut codegen = Codegen::new(&context, module);
+
+    for expr in [
+        "#product[#finite(0) #finite(3) #finite(5)]",
+        "#product[#finite(3) #finite(0) #finite(5)]",
+        "#product[#finite(3) #finite(5) #finite(0)]",
+    ]
+    .iter()
+    {
+        let (rest, product) = builder.parse_expr(expr).expect("Valid product");
+        assert_eq!(rest, "");
+        let product = match product.as_enum() {
+            ValueEnum::Product(p) => p,
+            _ => panic!("Expected a product"),
+        };
+        assert_eq!(
+            codegen.repr_product(product).expect("Valid representation"),
+            Repr::Empty
+        );
+    }
+}
+
+#[test]
+fn ternary_selects_between_compatible_tuples() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("compatible_tuples");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, high) = builder
+        .parse_expr("[#ix(3)[1] #ix(5)[2]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let (rest, low) = builder
+        .parse_expr("[#ix(4)[3] #ix(6)[4]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+
+    let t = Ternary::conditional(high, low).unwrap();
+    let f: FunctionValue = codegen
+        .build(&t.into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles functions");
+    assert!(f.verify(true));
+    let i8_ty = context.i8_type().into();
+    assert_eq!(
+        f.get_type().get_return_type(),
+        Some(context.struct_type(&[i8_ty, i8_ty], false).into())
+    );
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("phi"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn finite_index_successor_and_predecessor_wrap() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("index_step");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let (rest, ix) = builder
+        .parse_expr("#ix(6)[0]")
+        .expect("Valid Index Instance");
+    assert_eq!(rest, "");
+    let ix_val = codegen.build(&ix).expect("Valid value");
+    let pred: IntValue = codegen
+        .build_index_pred(ix_val, &Finite(6))
+        .expect("Valid predecessor")
+        .try_into()
+        .expect("Integer value");
+    assert_eq!(pred.get_zero_extended_constant(), Some(5));
+
+    // Runtime values, with a cardinality filling the whole representation
+    let i8_type = context.i8_type();
+    let f = codegen
+        .module()
+        .add_function("succ", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = f.get_nth_param(0).unwrap();
+    let result: IntValue = codegen
+        .build_index_succ(Val::Value(arg), &Finite(256))
+        .expect("Valid successor")
+        .try_into()
+        .expect("Integer value");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("succ") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0), 1);
+        assert_eq!(jit_f.call(254), 255);
+        assert_eq!(jit_f.call(255), 0);
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn cached_execution_engine_jits_multiple_lambdas() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("cached_engine");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder.parse_expr("|x: #bool| x").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let jit_id: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { codegen.jit_function(&id) }.expect("Valid IR generated");
+    unsafe {
+        assert_eq!(jit_id.call(true), true);
+        assert_eq!(jit_id.call(false), false);
+    }
+
+    // The second lambda is compiled after the module has already been handed to the engine
+    let jit_not: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { codegen.jit_function(&not) }.expect("Valid IR generated");
+    unsafe {
+        assert_eq!(jit_not.call(true), false);
+        assert_eq!(jit_not.call(false), true);
+        assert_eq!(jit_id.call(true), true);
+    }
+}
+
+#[test]
+fn bool_repr_through_finite_path_is_cached() {
+    let context = Context::create();
+    let module = context.create_module("bool_as_finite");
+    let mut codegen = Codegen::new(&context, module);
+
+    let b: ValId = true.into();
+    let bool_ty = b.as_enum().ty().clone_ty();
+    let i1 = Repr::Type(context.bool_type().into());
+
+    assert_eq!(codegen.repr(&bool_ty).expect("Valid representation"), i1);
+    assert!(codegen.reprs().get(&bool_ty).is_none());
+
+    codegen.set_bool_as_finite(true);
+    assert_eq!(codegen.repr(&bool_ty).expect("Valid representation"), i1);
+    assert_eq!(codegen.reprs().get(&bool_ty), Some(&i1));
+}
+
+#[test]
+fn constant_product_from_members() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("const_product");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, product) = builder
+        .parse_expr("#product[#finite(73) #finite(1025)]")
+        .expect("Valid product");
+    assert_eq!(rest, "");
+    let product = match product.as_enum() {
+        ValueEnum::Product(p) => p,
+        _ => panic!("Expected a product"),
+    };
+    let members = [
+        Val::Value(context.i8_type().const_int(3, false).into()),
+        Val::Value(context.i16_type().const_int(1000, false).into()),
+    ];
+    let value = match codegen
+        .build_const_product(product, &members)
+        .expect("Valid constant")
+    {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct value, got {:?}", v),
+    };
+    assert!(value.is_const());
+    assert_eq!(
+        value.get_type(),
+        context.struct_type(
+            &[context.i8_type().into(), context.i16_type().into()],
+            false
+        )
+    );
+    assert_eq!(
+        value.print_to_string().to_string(),
+        "{ i8, i16 } { i8 3, i16 1000 }"
+    );
+
+    // Non-constant members are rejected
+    let f = codegen.module().add_function(
+        "non_const",
+        context
+            .i8_type()
+            .fn_type(&[context.i8_type().into()], false),
+        None,
+    );
+    let members = [
+        Val::Value(f.get_nth_param(0).unwrap()),
+        Val::Value(context.i16_type().const_int(1000, false).into()),
+    ];
+    match codegen.build_const_product(product, &members) {
+        Err(Error::NotConst) => {}
+        r => panic!("Expected a non-constant error, got {:?}", r),
+    }
+}
+
+/// A lowering compiling logical negation as the identity, to check that registered lowerings take precedence
+#[derive(Debug)]
+struct NotAsIdentity;
+
+impl<'ctx> PrimitiveLowering<'ctx> for NotAsIdentity {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        match f.as_enum() {
+            ValueEnum::Logical(l) if *l == logical::Not => Some(cg.build(&args[0])),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn registered_primitive_lowering_is_used() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("custom_lowering");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+    codegen.register_lowering(Rc::new(NotAsIdentity));
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(b: bool) -> bool> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(false), false);
+        assert_eq!(jit_f.call(true), true);
+    }
+}
+
+#[test]
+fn fully_applied_lambda_is_inlined() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("let_binding");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, f) = builder
+        .parse_expr("|y: #bool| ((|x: #bool| (#not x)) y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&f)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert_eq!(ir.matches("define").count(), 1, "Unexpected IR: {}", ir);
+    assert!(!ir.contains("call"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("xor"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn strip_metadata_removes_debug_info() {
+    let context = Context::create();
+    let module = context.create_module("strip_metadata");
+    let node = context.metadata_node(&[context.i32_type().const_int(1, false).into()]);
+    module.add_global_metadata("llvm.dbg.cu", &node);
+    let codegen = Codegen::new(&context, module);
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(ir.contains("!llvm.dbg.cu"), "Unexpected IR: {}", ir);
+    assert!(codegen.strip_metadata());
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(!ir.contains("!llvm.dbg.cu"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn constant_values_build_at_global_level() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("global_constants");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, tuple) = builder
+        .parse_expr("[#ix(3)[1] #ix(5)[2]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    match codegen.build(&tuple).expect("Compilation works") {
+        Val::Value(BasicValueEnum::StructValue(s)) => assert!(s.is_const()),
+        v => panic!("Expected a constant struct, got {:?}", v),
+    }
+
+    let args: Vec<ValId> = vec![
+        BitsTy(8).into_val(),
+        BitsTy(8).data(1).unwrap().into(),
+        BitsTy(8).data(2).unwrap().into(),
+    ];
+    let sum: IntValue = codegen
+        .build_app(BinOp::Add.into_var().as_val(), &args[..])
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(sum.get_zero_extended_constant(), Some(3));
+
+    // No function or basic block is needed to build constants
+    assert_eq!(codegen.module().get_first_function(), None);
+}
+
+#[test]
+fn product_identity_returns_parameter_directly() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("product_identity");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(1025) #finite(3) #finite(5)]| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&id)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    assert!(!ir.contains("extractvalue"), "Unexpected IR: {}", ir);
+    assert!(!ir.contains("insertvalue"), "Unexpected IR: {}", ir);
+    assert!(
+        ir.contains("ret { i8, i16, i8, i8 } %0"),
+        "Unexpected IR: {}",
+        ir
+    );
+}
+
+#[test]
+fn in_place_shim_mutates_input() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("in_place_shim");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, swap) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(73)]| [(x #ix(2)[1]) (x #ix(2)[0])]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&swap)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let f_shim = codegen.build_shim_in_place(f, "swap_shim", None);
+    assert_eq!(f_shim.count_params(), 1);
+    assert!(f_shim.verify(true));
+
+    #[repr(C)]
+    struct Pair {
+        first: u8,
+        second: u8,
+    }
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(*mut Pair) -> i32> =
+        unsafe { execution_engine.get_function("swap_shim") }.expect("Valid IR generated");
+
+    // Run
+    let mut pair = Pair {
+        first: 3,
+        second: 5,
+    };
+    unsafe {
+        assert_eq!(jit_f.call(&mut pair), 0);
+    }
+    assert_eq!(pair.first, 5);
+    assert_eq!(pair.second, 3);
+}
+
+#[test]
+fn deduplicated_lambdas_are_linkonce_odr_in_comdat() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("dedup_lambdas");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_dedup_lambdas(true);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    assert_eq!(f.get_linkage(), Linkage::LinkOnceODR);
+    assert!(f.as_global_value().get_comdat().is_some());
+
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(
+        ir.contains(&format!("${} = comdat any", f_name)),
+        "Unexpected IR: {}",
+        ir
+    );
+
+    // The same lambda in another module gets the same symbol
+    let other_module = context.create_module("dedup_lambdas_other");
+    let mut other_codegen = Codegen::new(&context, other_module);
+    other_codegen.set_dedup_lambdas(true);
+    let g: FunctionValue = other_codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(g.get_name(), f.get_name());
+
+    // An existing function of the same name but another type is not reused
+    let clashing_module = context.create_module("dedup_lambdas_clash");
+    clashing_module.add_function(
+        f_name,
+        context
+            .i8_type()
+            .fn_type(&[context.i8_type().into()], false),
+        None,
+    );
+    let mut clashing_codegen = Codegen::new(&context, clashing_module);
+    clashing_codegen.set_dedup_lambdas(true);
+    match clashing_codegen.build(&not) {
+        Err(Error::SymbolExists(name)) => assert_eq!(name, f_name),
+        r => panic!("Expected a symbol clash, got {:?}", r),
+    }
+}
+
+#[test]
+fn mux_lambda_compiles_for_wasm32() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("mux_wasm32");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_target_profile(TargetProfile::Wasm32);
+
+    let mux_p =
+        "|select: #bool high: #bool low: #bool| (#or (#and select high) (#and (#not select) low))";
+    let (rest, mux) = builder.parse_expr(mux_p).expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&mux)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(
+        ir.contains("target triple = \"wasm32-unknown-unknown\""),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(!ir.contains("i128"), "Unexpected IR: {}", ir);
+
+    // Integers wider than 64 bits are not representable on wasm32
+    let wide: ValId = BitsTy(100).data(1).unwrap().into();
+    let wide_ty = wide.as_enum().ty().clone_ty();
+    match codegen.repr(&wide_ty) {
+        Err(Error::UnrepresentableType) => {}
+        r => panic!("Expected an unrepresentable type, got {:?}", r),
+    }
+}
+
+#[test]
+fn select_without_branches() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("select");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, a) = builder.parse_expr("#ix(6)[4]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, b) = builder.parse_expr("#ix(6)[2]").expect("Valid index");
+    assert_eq!(rest, "");
+
+    // Constant conditions select directly
+    let selected: IntValue = codegen
+        .build_select_app(&[true.into(), a.clone(), b.clone()])
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(selected.get_zero_extended_constant(), Some(4));
+    assert_eq!(codegen.module().get_first_function(), None);
+
+    // Runtime conditions emit a select, but no branch
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "select",
+        i8_type.fn_type(&[context.bool_type().into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let cond = f.get_nth_param(0).unwrap().into_int_value();
+    let high = codegen.build(&a).expect("Valid value");
+    let low = codegen.build(&b).expect("Valid value");
+    let result: IntValue = codegen
+        .build_select(cond, high, low)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("select"), "Unexpected IR: {}", ir);
+    assert!(!ir.contains("br "), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> u8> =
+        unsafe { execution_engine.get_function("select") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), 4);
+        assert_eq!(jit_f.call(false), 2);
+    }
+}
+
+#[test]
+fn select_lowering_emits_select() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("select_lowering");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // A frontend-defined select, which would otherwise be inlined as a formula without any `select`
+    let (rest, select) = builder
+        .parse_expr("|c: #bool a: #bool b: #bool| (#or (#and c a) (#and (#not c) b))")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    codegen.register_lowering(Rc::new(SelectLowering(select.clone())));
+
+    let (rest, params) = builder
+        .parse_expr("|c: #bool a: #bool b: #bool| c")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match params.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let region = lambda.get_ty().def_region().clone();
+    let c = ValId::from(region.clone().param(0).expect("Lambda has a parameter"));
+    let a = ValId::from(region.clone().param(1).expect("Lambda has two parameters"));
+    let b = ValId::from(region.param(2).expect("Lambda has three parameters"));
+    let and: ValId = Sexpr::try_new(vec![logical::And.into(), a.clone(), b.clone()].into())
+        .expect("Valid application")
+        .into();
+    let or: ValId = Sexpr::try_new(vec![logical::Or.into(), a.clone(), b.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "select_lowering",
+        bool_type.fn_type(
+            &[bool_type.into(), bool_type.into(), bool_type.into()],
+            false,
+        ),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    for (ix, param) in [&c, &a, &b].iter().enumerate() {
+        locals.insert(
+            (*param).clone(),
+            Val::Value(f.get_nth_param(ix as u32).unwrap()),
+        );
+    }
+
+    // A constant condition selects its value directly
+    let constant: ValId =
+        Sexpr::try_new(vec![select.clone(), true.into(), a.clone(), b.clone()].into())
+            .expect("Valid application")
+            .into();
+    let selected = codegen
+        .build_in_function(f, &constant, locals.clone())
+        .expect("Compilation works");
+    assert_eq!(selected, Val::Value(f.get_nth_param(1).unwrap()));
+
+    // A runtime condition evaluates both values and selects between them, without branching
+    let app: ValId = Sexpr::try_new(vec![select, c, and, or].into())
+        .expect("Valid application")
+        .into();
+    let result: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("select i1"), "Unexpected IR: {}", ir);
+    assert!(!ir.contains("br "), "Unexpected IR: {}", ir);
+    assert_eq!(f.count_basic_blocks(), 1);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("select_lowering") }.expect("Valid IR generated");
+
+    // Run
+    for &c in &[true, false] {
+        for &a in &[true, false] {
+            for &b in &[true, false] {
+                let expected = if c { a && b } else { a || b };
+                unsafe {
+                    assert_eq!(jit_f.call(c, a, b), expected);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn pointer_sized_index_representation() {
+    let context = Context::create();
+    let module = context.create_module("ptr_sized_indices");
+    module.set_data_layout(&TargetData::create("e-p:64:64").get_data_layout());
+    let mut codegen = Codegen::new(&context, module);
+
+    assert_eq!(
+        codegen
+            .repr_finite(&Finite(6))
+            .expect("Valid representation"),
+        Repr::Type(context.i8_type().into())
+    );
+    codegen.set_ptr_sized_indices(true);
+    assert_eq!(
+        codegen
+            .repr_finite(&Finite(6))
+            .expect("Valid representation"),
+        Repr::Type(context.i64_type().into())
+    );
+    // Booleans keep their usual representation
+    assert_eq!(
+        codegen
+            .repr_finite(&Finite(2))
+            .expect("Valid representation"),
+        Repr::Type(context.bool_type().into())
+    );
+}
+
+#[test]
+fn c_header_for_product_identity_shim() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("c_header");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(1025)]| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&id)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    codegen.build_shim(f, "shim", None);
+
+    let id_ty = id.as_enum().ty().clone_ty();
+    let repr = match codegen.repr(&id_ty).expect("Valid representation") {
+        Repr::Function(repr) => repr,
+        r => panic!("Expected a function representation, got {:?}", r),
+    };
+    let header = codegen.emit_c_header(&[("shim", &repr)]);
+    assert!(
+        header.contains("struct shim_0 {\n    uint8_t f0;\n    uint16_t f1;\n};"),
+        "Unexpected header: {}",
+        header
+    );
+    assert!(
+        header.contains("int32_t shim(struct shim_0 *arg0, struct shim_ret *out);"),
+        "Unexpected header: {}",
+        header
+    );
+}
+
+#[test]
+fn c_header_for_in_place_shim() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("c_header_in_place");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, swap) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(73)]| [(x #ix(2)[1]) (x #ix(2)[0])]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&swap)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    codegen.build_shim_in_place(f, "swap_shim", None);
+
+    let swap_ty = swap.as_enum().ty().clone_ty();
+    let repr = match codegen.repr(&swap_ty).expect("Valid representation") {
+        Repr::Function(repr) => repr,
+        r => panic!("Expected a function representation, got {:?}", r),
+    };
+    let header = codegen.emit_c_header(&[("swap_shim", &repr)]);
+    assert!(
+        header.contains("int32_t swap_shim(struct swap_shim_0 *arg0);"),
+        "Unexpected header: {}",
+        header
+    );
+    assert!(
+        !header.contains("swap_shim_ret"),
+        "Unexpected header: {}",
+        header
+    );
+}
+
+#[test]
+fn applied_ternary_is_inlined_into_current_function() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("inline_ternary");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder.parse_expr("|c: #bool| c").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match id.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let c = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let (rest, high) = builder.parse_expr("#ix(6)[4]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, low) = builder.parse_expr("#ix(6)[2]").expect("Valid index");
+    assert_eq!(rest, "");
+    let t: ValId = Ternary::conditional(high, low).unwrap().into();
+    let app: ValId = Sexpr::try_new(vec![t, c.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    // Build the application inside a function, as for a member of a tuple
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "inline_ternary",
+        i8_type.fn_type(&[context.bool_type().into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(c, Val::Value(f.get_nth_param(0).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // No separate function is emitted for the ternary
+    let ir = codegen.module().print_to_string().to_string();
+    assert_eq!(ir.matches("define").count(), 1, "Unexpected IR: {}", ir);
+    assert!(!ir.contains("call"), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> u8> =
+        unsafe { execution_engine.get_function("inline_ternary") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), 4);
+        assert_eq!(jit_f.call(false), 2);
+    }
+}
+
+#[test]
+fn signed_shim_ints_are_sign_extended() {
+    let context = Context::create();
+    let module = context.create_module("signed_shim");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_signed_shim_ints(true);
+
+    // A function returning the 8-bit vector with all bits set
+    let i8_type = context.i8_type();
+    let f = codegen
+        .module()
+        .add_function("all_ones", i8_type.fn_type(&[], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    codegen
+        .builder()
+        .build_return(Some(&i8_type.const_all_ones()));
+    assert!(f.verify(true));
+
+    let f_shim = codegen.build_shim(f, "all_ones_shim", None);
+    assert!(f_shim.verify(true));
+    let ir = f_shim.print_to_string().to_string();
+    assert!(ir.contains("signext i8"), "Unexpected IR: {}", ir);
+
+    // The result is sign extended as a C `int8_t` would be
+    let jit_f: JitFunction<unsafe extern "C" fn() -> i32> =
+        unsafe { execution_engine.get_function("all_ones_shim") }.expect("Valid IR generated");
+    unsafe {
+        assert_eq!(jit_f.call(), -1);
+    }
+}
+
+#[test]
+fn ternary_on_negation_swaps_branches() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("inverted_ternary");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|c: #bool| (#not c)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match not.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let c = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let (rest, high) = builder.parse_expr("#ix(6)[4]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, low) = builder.parse_expr("#ix(6)[2]").expect("Valid index");
+    assert_eq!(rest, "");
+    let t: ValId = Ternary::conditional(high, low).unwrap().into();
+    let app: ValId = Sexpr::try_new(vec![t, lambda.result().clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "inverted_ternary",
+        i8_type.fn_type(&[context.bool_type().into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(c, Val::Value(f.get_nth_param(0).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    assert!(!ir.contains("xor"), "Unexpected IR: {}", ir);
+    assert!(
+        ir.contains("br i1 %0, label %low, label %high"),
+        "Unexpected IR: {}",
+        ir
+    );
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> u8> =
+        unsafe { execution_engine.get_function("inverted_ternary") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), 2);
+        assert_eq!(jit_f.call(false), 4);
+    }
+}
+
+#[test]
+fn flattened_mux_has_no_calls() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("flattened_mux");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_flatten(true);
+
+    let mux_p =
+        "|select: #bool high: #bool low: #bool| (#or (#and select high) (#and (#not select) low))";
+    let (rest, mux) = builder.parse_expr(mux_p).expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&mux)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert_eq!(ir.matches("define").count(), 1, "Unexpected IR: {}", ir);
+    assert!(!ir.contains("call"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn packed_finite_widths() {
+    let context = Context::create();
+    let module = context.create_module("packed_finite");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_packed_finite(true);
+
+    let expected_widths = [
+        (3, 2),
+        (4, 2),
+        (5, 3),
+        (6, 3),
+        (7, 3),
+        (8, 3),
+        (9, 4),
+        (10, 4),
+        (11, 4),
+        (12, 4),
+        (13, 4),
+        (14, 4),
+        (15, 4),
+        (16, 4),
+    ];
+    for (n, width) in expected_widths.iter().copied() {
+        assert_eq!(
+            codegen
+                .repr_finite(&Finite(n))
+                .expect("Valid representation"),
+            Repr::Type(context.custom_width_int_type(width).into()),
+            "Unexpected representation for #finite({})",
+            n
+        );
+    }
+    assert_eq!(
+        codegen
+            .repr_finite(&Finite(2))
+            .expect("Valid representation"),
+        Repr::Type(context.bool_type().into())
+    );
+}
+
+#[test]
+fn shim_passes_arrays_by_pointer() {
+    let context = Context::create();
+    let module = context.create_module("array_shim");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // A function returning the third element of an array
+    let i8_type = context.i8_type();
+    let array_type = i8_type.array_type(4);
+    let f =
+        codegen
+            .module()
+            .add_function("third", i8_type.fn_type(&[array_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let array = f.get_nth_param(0).unwrap().into_array_value();
+    let third = codegen
+        .builder()
+        .build_extract_value(array, 2, "third")
+        .unwrap();
+    codegen.builder().build_return(Some(&third));
+    assert!(f.verify(true));
+
+    let f_shim = codegen.build_shim(f, "third_shim", None);
+    assert!(f_shim.verify(true));
+    assert!(f_shim.get_nth_param(0).unwrap().is_pointer_value());
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(*mut [u8; 4]) -> u8> =
+        unsafe { execution_engine.get_function("third_shim") }.expect("Valid IR generated");
+
+    // Run
+    let mut array = [1, 2, 3, 4];
+    unsafe {
+        assert_eq!(jit_f.call(&mut array), 3);
+    }
+}
+
+#[test]
+fn c_header_for_array_returning_shim() {
+    let context = Context::create();
+    let module = context.create_module("c_header_array");
+    let mut codegen = Codegen::new(&context, module);
+
+    // A function returning an array of four bytes, all set to its argument
+    let i8_type = context.i8_type();
+    let array_type = i8_type.array_type(4);
+    let fn_type = array_type.fn_type(&[i8_type.into()], false);
+    let f = codegen.module().add_function("splat", fn_type, None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let byte = f.get_nth_param(0).unwrap();
+    let mut array = array_type.get_undef();
+    for ix in 0..4 {
+        array = codegen
+            .builder()
+            .build_insert_value(array, byte, ix, "splat")
+            .unwrap()
+            .into_array_value();
+    }
+    codegen.builder().build_return(Some(&array));
+    assert!(f.verify(true));
+
+    let f_shim = codegen.build_shim(f, "splat_shim", None);
+    assert!(f_shim.verify(true));
+    assert_eq!(f_shim.count_params(), 2);
+
+    let mut mapping = IxMap::with_capacity(1);
+    mapping.push_ix(0);
+    let repr = FunctionRepr {
+        mapping,
+        repr: fn_type,
+    };
+    let header = codegen.emit_c_header(&[("splat_shim", &repr)]);
+    assert!(
+        header.contains("int32_t splat_shim(uint8_t arg0, uint8_t *out);"),
+        "Unexpected header: {}",
+        header
+    );
+}
+
+/// A misbehaving lowering which builds a given value while lowering negation, to check cycle detection
+#[derive(Debug)]
+struct Reentrant(ValId);
+
+impl<'ctx> PrimitiveLowering<'ctx> for Reentrant {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        _args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        match f.as_enum() {
+            ValueEnum::Logical(l) if *l == logical::Not => Some(cg.build(&self.0)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn cyclic_builds_are_reported() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("cyclic");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match not.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    // Lowering `(#not x)` requires building `(#not x)`
+    codegen.register_lowering(Rc::new(Reentrant(lambda.result().clone())));
+
+    match codegen.build(&not) {
+        Err(Error::InternalError(msg)) => assert_eq!(msg, "cyclic value graph"),
+        r => panic!("Expected a cycle to be detected, got {:?}", r),
+    }
+    assert_eq!(codegen.module().get_first_function(), None);
+}
+
+#[test]
+fn bits_sum_folds_constant_operands() {
+    let context = Context::create();
+    let module = context.create_module("bits_sum");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let i8_type = context.i8_type();
+    let f = codegen
+        .module()
+        .add_function("sum", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let x = f.get_nth_param(0).unwrap().into_int_value();
+    let operands = [i8_type.const_int(1, false), x, i8_type.const_int(2, false)];
+    let result: IntValue = codegen
+        .build_bits_nary(BinOp::Add, &operands)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    assert_eq!(ir.matches(" add ").count(), 1, "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("sum") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0), 3);
+        assert_eq!(jit_f.call(254), 1);
+    }
+}
+
+#[test]
+fn unit_arguments_are_skipped_in_calls() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("unit_argument");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, callee) = builder
+        .parse_expr("|u: #finite(1) x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, unit) = builder.parse_expr("#ix(1)[0]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, t) = builder.parse_expr("#true").expect("Valid boolean");
+    assert_eq!(rest, "");
+
+    let mapping = match callee.as_enum() {
+        ValueEnum::Lambda(l) => codegen
+            .param_mapping(l.get_ty())
+            .expect("Valid parameter types"),
+        _ => panic!("Expected a lambda"),
+    };
+    let callee: FunctionValue = codegen
+        .build(&callee)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(callee.count_params(), 1);
+
+    let f = codegen.module().add_function(
+        "unit_argument",
+        context.bool_type().fn_type(&[], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let result: IntValue = codegen
+        .build_function_call(callee, &mapping, &[unit, t])
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+    assert!(f.print_to_string().to_string().contains("call"));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn() -> bool> =
+        unsafe { execution_engine.get_function("unit_argument") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(), false);
+    }
+}
+
+#[test]
+fn mixed_prop_and_value_parameters_are_mapped() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("mixed_parameters");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, callee) = builder
+        .parse_expr("|u: #finite(1) x: #bool v: #finite(1) y: #bool| (#and x (#not y))")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match callee.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let mapping = codegen
+        .param_mapping(lambda.get_ty())
+        .expect("Valid parameter types");
+    assert_eq!(
+        mapping.iter().collect::<Vec<_>>(),
+        vec![ReprIx::Prop, ReprIx::Val(0), ReprIx::Prop, ReprIx::Val(1)]
+    );
+    let (rest, unit) = builder.parse_expr("#ix(1)[0]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, t) = builder.parse_expr("#true").expect("Valid boolean");
+    assert_eq!(rest, "");
+    let (rest, f) = builder.parse_expr("#false").expect("Valid boolean");
+    assert_eq!(rest, "");
+
+    let callee: FunctionValue = codegen
+        .build(&callee)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(callee.count_params(), 2);
+
+    let caller = codegen.module().add_function(
+        "mixed_parameters",
+        context.bool_type().fn_type(&[], false),
+        None,
+    );
+    let entry = context.append_basic_block(caller, "entry");
+    codegen.builder().position_at_end(entry);
+    let result: IntValue = codegen
+        .build_function_call(callee, &mapping, &[unit.clone(), t, unit, f])
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(caller.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn() -> bool> =
+        unsafe { execution_engine.get_function("mixed_parameters") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(), true);
+    }
+}
+
+#[test]
+fn byte_bool_fields_are_widened_and_narrowed() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("byte_bool_fields");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_byte_bool_fields(true);
+
+    let (rest, product) = builder
+        .parse_expr("#product[#bool #finite(73)]")
+        .expect("Valid product");
+    assert_eq!(rest, "");
+    let product = match product.as_enum() {
+        ValueEnum::Product(p) => p,
+        _ => panic!("Expected a product"),
+    };
+    match codegen.repr_product(product) {
+        Ok(Repr::Product(p)) => assert_eq!(
+            p.repr,
+            context.struct_type(&[context.i8_type().into(), context.i8_type().into()], false)
+        ),
+        r => panic!("Expected a product representation, got {:?}", r),
+    }
+
+    let (rest, pack) = builder
+        .parse_expr("|x: #bool| [x #ix(73)[5]]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, unpack) = builder
+        .parse_expr("|x: #product[#bool #finite(73)]| (x #ix(2)[0])")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let pack: FunctionValue = codegen
+        .build(&pack)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(pack.verify(true));
+    let unpack: FunctionValue = codegen
+        .build(&unpack)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(unpack.verify(true));
+    assert_eq!(
+        unpack.get_type().get_return_type(),
+        Some(context.bool_type().into())
+    );
+
+    codegen.build_shim(pack, "pack_shim", None);
+    codegen.build_shim(unpack, "unpack_shim", None);
+
+    #[repr(C)]
+    struct Flagged {
+        flag: bool,
+        ix: u8,
+    }
+
+    // Jit
+    let pack_f: JitFunction<unsafe extern "C" fn(bool, *mut Flagged) -> i32> =
+        unsafe { execution_engine.get_function("pack_shim") }.expect("Valid IR generated");
+    let unpack_f: JitFunction<unsafe extern "C" fn(*mut Flagged) -> bool> =
+        unsafe { execution_engine.get_function("unpack_shim") }.expect("Valid IR generated");
+
+    // Run
+    for &flag in &[true, false] {
+        let mut flagged = Flagged { flag: !flag, ix: 0 };
+        unsafe {
+            assert_eq!(pack_f.call(flag, &mut flagged), 0);
+        }
+        assert_eq!(flagged.flag, flag);
+        assert_eq!(flagged.ix, 5);
+        unsafe {
+            assert_eq!(unpack_f.call(&mut flagged), flag);
+        }
+    }
+}
+
+#[test]
+fn xnor_is_lowered_directly() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("xnor");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, params) = builder
+        .parse_expr("|x: #bool y: #bool| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match params.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let region = lambda.get_ty().def_region().clone();
+    let x = ValId::from(region.clone().param(0).expect("Lambda has a parameter"));
+    let y = ValId::from(region.param(1).expect("Lambda has two parameters"));
+    let xnor: ValId = Sexpr::try_new(vec![logical::Iff.into(), x.clone(), y.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "xnor",
+        bool_type.fn_type(&[bool_type.into(), bool_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(x, Val::Value(f.get_nth_param(0).unwrap()));
+    locals.insert(y, Val::Value(f.get_nth_param(1).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(f, &xnor, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    assert!(
+        ir.contains("%pxor = xor i1 %0, %1"),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(
+        ir.contains("%pxnor = xor i1 %pxor, true"),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(!ir.contains("psplit"), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("xnor") }.expect("Valid IR generated");
+
+    // Run
+    for &x in &[true, false] {
+        for &y in &[true, false] {
+            unsafe {
+                assert_eq!(jit_f.call(x, y), x == y);
+            }
+        }
+    }
+}
+
+#[test]
+fn constant_thunks_are_folded() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("constant_thunk");
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_fold_constant_thunks(true);
+
+    let (rest, thunk) = builder
+        .parse_expr("|| (#xor #true #false)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+
+    let c: IntValue = codegen
+        .build(&thunk)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a constant");
+    assert!(c.is_const());
+    assert_eq!(c.get_zero_extended_constant(), Some(1));
+    assert!(codegen.module().get_first_function().is_none());
+
+    // Non-nullary lambdas are still compiled to functions
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+}
+
+#[test]
+fn global_ctors_are_registered() {
+    let context = Context::create();
+    let module = context.create_module("global_ctors");
+    let mut codegen = Codegen::new(&context, module);
+
+    let void_fn = context.void_type().fn_type(&[], false);
+    let mut ctors = Vec::new();
+    for name in &["init_tables", "init_env"] {
+        let f = codegen.module().add_function(name, void_fn, None);
+        let entry = context.append_basic_block(f, "entry");
+        codegen.builder().position_at_end(entry);
+        codegen.builder().build_return(None);
+        ctors.push(f);
+    }
+    codegen
+        .add_global_ctor(ctors[0], 65535)
+        .expect("Valid constructor");
+    codegen
+        .add_global_ctor(ctors[1], 101)
+        .expect("Valid constructor");
+    assert_eq!(codegen.global_ctors().len(), 2);
+
+    let bool_fn = context
+        .bool_type()
+        .fn_type(&[context.bool_type().into()], false);
+    let not_ctor = codegen.module().add_function("not_ctor", bool_fn, None);
+    match codegen.add_global_ctor(not_ctor, 0) {
+        Err(Error::InvalidFuncRepr) => {}
+        r => panic!("Expected an invalid function representation, got {:?}", r),
+    }
+
+    assert!(codegen.module().verify().is_ok());
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(
+        ir.contains("@llvm.global_ctors = appending global [2 x { i32, void ()*, i8* }]"),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(
+        ir.contains("{ i32 65535, void ()* @init_tables, i8* null }"),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(
+        ir.contains("{ i32 101, void ()* @init_env, i8* null }"),
+        "Unexpected IR: {}",
+        ir
+    );
+}
+
+#[test]
+fn bits_bound_in_locals_are_not_rebuilt() {
+    let context = Context::create();
+    let module = context.create_module("bits_locals");
+    let mut codegen = Codegen::new(&context, module);
+
+    let bits = BitsTy(8).data(7).unwrap();
+    let i8_type = context.i8_type();
+    let f = codegen.module().add_function(
+        "bits_local",
+        i8_type.fn_type(&[i8_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let x = f.get_nth_param(0).unwrap();
+
+    let mut locals = SymbolTable::default();
+    locals.insert(bits.clone().into_val(), Val::Value(x));
+    let result = codegen
+        .build_in_function(f, &bits.clone().into_val(), locals)
+        .expect("Compilation works");
+    assert_eq!(result, Val::Value(x));
+
+    // Outside of the local symbol table, the bits vector is a constant again
+    let constant: IntValue = codegen
+        .build(&bits.clone().into_val())
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert!(constant.is_const());
+    assert_eq!(constant.get_zero_extended_constant(), Some(7));
+
+    // Once the bits vector has a global definition, a local binding does not shadow it
+    let g = codegen.module().add_function(
+        "bits_global",
+        i8_type.fn_type(&[i8_type.into()], false),
+        None,
+    );
+    context.append_basic_block(g, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(
+        bits.clone().into_val(),
+        Val::Value(g.get_nth_param(0).unwrap()),
+    );
+    let result: IntValue = codegen
+        .build_in_function(g, &bits.clone().into_val(), locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(result, constant);
+
+    // Constants computed from local bindings are not cached as globals
+    let other: ValId = BitsTy(8).data(9).unwrap().into();
+    let sum: ValId = Sexpr::try_new(
+        vec![
+            BinOp::Add.into_var().as_val().clone(),
+            BitsTy(8).into_val(),
+            other.clone(),
+            bits.into_val(),
+        ]
+        .into(),
+    )
+    .expect("Valid application")
+    .into();
+    let h =
+        codegen
+            .module()
+            .add_function("bits_sum", i8_type.fn_type(&[i8_type.into()], false), None);
+    context.append_basic_block(h, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(other, Val::Value(h.get_nth_param(0).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(h, &sum, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert!(!result.is_const());
+    assert_eq!(codegen.get_compiled(&sum), None);
+}
+
+#[test]
+fn shared_repr_cache_is_reused_across_codegens() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let shared = Rc::new(RefCell::new(Default::default()));
+    let mut first =
+        Codegen::new_with_shared_reprs(&context, context.create_module("first"), shared.clone());
+    let mut second =
+        Codegen::new_with_shared_reprs(&context, context.create_module("second"), shared.clone());
+
+    let (rest, tuple) = builder
+        .parse_expr("[#ix(73)[1] #ix(1025)[2]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let product_ty = tuple.as_enum().ty().clone_ty();
+
+    let first_repr = first.repr(&product_ty).expect("Valid representation");
+    let cached = shared.borrow().len();
+    assert!(shared.borrow().get(&product_ty).is_some());
+
+    // The second codegen finds the representation in the shared cache, rather than computing it again
+    assert!(second.reprs().get(&product_ty).is_none());
+    let second_repr = second.repr(&product_ty).expect("Valid representation");
+    assert_eq!(first_repr, second_repr);
+    assert_eq!(shared.borrow().len(), cached);
+    assert_eq!(second.reprs().get(&product_ty), Some(&first_repr));
+
+    // Changing a representation setting detaches from the shared cache
+    second.set_packed_finite(true);
+    assert!(second.shared_reprs().is_none());
+    assert!(first.shared_reprs().is_some());
+}
+
+#[test]
+fn aliases_resolve_to_the_same_function() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("alias");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    codegen.add_alias("negate", f).expect("Fresh symbol");
+    match codegen.add_alias("negate", f) {
+        Err(Error::SymbolExists(name)) => assert_eq!(name, "negate"),
+        r => panic!("Expected a duplicate symbol error, got {:?}", r),
+    }
+    assert!(codegen.module().verify().is_ok());
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(
+        ir.contains(&format!("@negate = alias i1 (i1), i1 (i1)* @{}", f_name)),
+        "Unexpected IR: {}",
+        ir
+    );
+
+    // Jit
+    let address = execution_engine
+        .get_function_address(f_name)
+        .expect("Valid IR generated");
+    let alias_address = execution_engine
+        .get_function_address("negate")
+        .expect("Alias resolves");
+    assert_eq!(address, alias_address);
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("negate") }.expect("Alias resolves");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), false);
+        assert_eq!(jit_f.call(false), true);
+    }
+}
+
+#[test]
+fn bits_unsigned_division_and_remainder() {
+    let context = Context::create();
+    let module = context.create_module("bits_udiv");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let i8_type = context.i8_type();
+    let seventeen = i8_type.const_int(17, false);
+    let five = i8_type.const_int(5, false);
+    let zero = i8_type.const_int(0, false);
+
+    // Constant operands are folded
+    let quotient: IntValue = codegen
+        .build_bits_udiv(seventeen, five)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(quotient.get_zero_extended_constant(), Some(3));
+    let remainder: IntValue = codegen
+        .build_bits_urem(seventeen, five)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(remainder.get_zero_extended_constant(), Some(2));
+
+    // Division by a constant zero is reported rather than compiled
+    match codegen.build_bits_udiv(seventeen, zero) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected division by zero to be reported, got {:?}", r),
+    }
+    match codegen.build_bits_urem(seventeen, zero) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected division by zero to be reported, got {:?}", r),
+    }
+
+    let f = codegen.module().add_function(
+        "divmod",
+        i8_type.fn_type(&[i8_type.into(), i8_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let x = f.get_nth_param(0).unwrap().into_int_value();
+    let y = f.get_nth_param(1).unwrap().into_int_value();
+    let quotient: IntValue = codegen
+        .build_bits_udiv(x, y)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    let remainder: IntValue = codegen
+        .build_bits_urem(x, y)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    let shifted =
+        codegen
+            .builder()
+            .build_left_shift(quotient, i8_type.const_int(4, false), "shifted");
+    let result = codegen.builder().build_or(shifted, remainder, "result");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8, u8) -> u8> =
+        unsafe { execution_engine.get_function("divmod") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(17, 5), 0x32);
+        assert_eq!(jit_f.call(100, 7), 0xe2);
+    }
+}
+
+#[test]
+fn bits_shifts() {
+    let context = Context::create();
+    let module = context.create_module("shifts");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let one: IntValue = codegen
+        .build(&BitsTy(8).data(1).unwrap().into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    let i8_type = context.i8_type();
+    let shifted: IntValue = codegen
+        .build_shift_left(one, i8_type.const_int(3, false), 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(shifted.get_zero_extended_constant(), Some(8));
+    let shifted: IntValue = codegen
+        .build_logical_shift_right(shifted, i8_type.const_int(2, false), 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(shifted.get_zero_extended_constant(), Some(2));
+
+    // Shifting out every bit gives zero
+    let shifted: IntValue = codegen
+        .build_shift_left(one, i8_type.const_int(8, false), 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert!(shifted.is_const());
+    assert_eq!(shifted.get_zero_extended_constant(), Some(0));
+
+    // Runtime values
+    let f = codegen
+        .module()
+        .add_function("lshr", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = f.get_nth_param(0).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_logical_shift_right(arg, context.i32_type().const_int(3, false), 8)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("lshr") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0b1000_0001), 0b0001_0000);
+        assert_eq!(jit_f.call(0b0000_0111), 0);
+    }
+}
+
+#[test]
+fn bits_shifts_narrower_than_representation() {
+    let context = Context::create();
+    let module = context.create_module("shifts_narrow");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding: a `#bits(12)` is stored in an `i16`, but shifting it by 12 shifts out every bit
+    let i16_type = context.i16_type();
+    let x = i16_type.const_int(0x801, false);
+    let shifted: IntValue = codegen
+        .build_shift_left(x, i16_type.const_int(12, false), 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert!(shifted.is_const());
+    assert_eq!(shifted.get_zero_extended_constant(), Some(0));
+    let shifted: IntValue = codegen
+        .build_shift_left(x, i16_type.const_int(1, false), 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    assert_eq!(shifted.get_zero_extended_constant(), Some(0x002));
+
+    // Runtime values
+    let f = codegen.module().add_function(
+        "shl12",
+        i16_type.fn_type(&[i16_type.into(), i16_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let arg = f.get_nth_param(0).unwrap().into_int_value();
+    let amount = f.get_nth_param(1).unwrap().into_int_value();
+    let result: IntValue = codegen
+        .build_shift_left(arg, amount, 12)
+        .unwrap()
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u16, u16) -> u16> =
+        unsafe { execution_engine.get_function("shl12") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(0x801, 1), 0x002);
+        assert_eq!(jit_f.call(0x0FF, 8), 0xF00);
+        assert_eq!(jit_f.call(0x0FF, 12), 0);
+    }
+}
+
+#[test]
+fn function_members_are_stored_as_pointers() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("function_members");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, tuple) = builder
+        .parse_expr("[|x: #bool| (#not x) #ix(3)[1]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let product = tuple.as_enum().ty().clone_ty();
+
+    let bool_type = context.bool_type();
+    let fn_ptr = bool_type
+        .fn_type(&[bool_type.into()], false)
+        .ptr_type(AddressSpace::Generic);
+    let repr = match codegen.repr(&product).expect("Valid representation") {
+        Repr::Product(p) => p,
+        r => panic!("Expected a product representation, got {:?}", r),
+    };
+    assert_eq!(
+        repr.repr,
+        context.struct_type(&[fn_ptr.into(), context.i8_type().into()], false)
+    );
+
+    // Tuples with function members are constant structs of function pointers
+    let value = match codegen.build(&tuple).expect("Compilation works") {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    assert_eq!(value.get_type(), repr.repr);
+    assert!(codegen.module().verify().is_ok());
+}
+
+#[test]
+fn statically_false_refinements_are_contradictions() {
+    let context = Context::create();
+    let module = context.create_module("refinements");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let i8_type = context.i8_type();
+    let f =
+        codegen
+            .module()
+            .add_function("refined", i8_type.fn_type(&[i8_type.into()], false), None);
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+
+    // `1 == 2` is statically false, and `1 == 1` statically true
+    let one = i8_type.const_int(1, false);
+    let two = i8_type.const_int(2, false);
+    let impossible = codegen
+        .builder()
+        .build_int_compare(IntPredicate::EQ, one, two, "impossible");
+    assert_eq!(
+        codegen
+            .build_refinement(impossible)
+            .expect("Compilation works"),
+        Val::Contr
+    );
+    let trivial = codegen
+        .builder()
+        .build_int_compare(IntPredicate::EQ, one, one, "trivial");
+    assert_eq!(
+        codegen
+            .build_refinement(trivial)
+            .expect("Compilation works"),
+        Val::Unit
+    );
+
+    // Refinements on runtime values are assumed
+    let x = f.get_nth_param(0).unwrap().into_int_value();
+    let small = codegen.builder().build_int_compare(
+        IntPredicate::ULT,
+        x,
+        i8_type.const_int(10, false),
+        "small",
+    );
+    assert_eq!(
+        codegen.build_refinement(small).expect("Compilation works"),
+        Val::Unit
+    );
+    codegen.builder().build_return(Some(&x));
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(
+        ir.contains("call void @llvm.assume(i1 %small)"),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(!ir.contains("impossible"), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("refined") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(3), 3);
+    }
+}
+
+#[test]
+fn bits_comparison_selects_ternary_branch() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("bits_compare");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let i8_type = context.i8_type();
+    let three = i8_type.const_int(3, false);
+    let five = i8_type.const_int(5, false);
+    let lt: IntValue = codegen
+        .build_bits_compare(IntPredicate::ULT, three, five)
+        .unwrap()
+        .try_into()
+        .expect("A boolean");
+    assert_eq!(lt.get_type(), context.bool_type());
+    assert_eq!(lt.get_zero_extended_constant(), Some(1));
+    let eq: IntValue = codegen
+        .build_bits_compare(IntPredicate::EQ, three, five)
+        .unwrap()
+        .try_into()
+        .expect("A boolean");
+    assert_eq!(eq.get_zero_extended_constant(), Some(0));
+
+    // The comparison result is used directly as the condition of a ternary node
+    let (rest, id) = builder.parse_expr("|c: #bool| c").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match id.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let c = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let (rest, high) = builder.parse_expr("#ix(6)[4]").expect("Valid index");
+    assert_eq!(rest, "");
+    let (rest, low) = builder.parse_expr("#ix(6)[2]").expect("Valid index");
+    assert_eq!(rest, "");
+    let t: ValId = Ternary::conditional(high, low).unwrap().into();
+    let app: ValId = Sexpr::try_new(vec![t, c.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let f = codegen.module().add_function(
+        "less_than",
+        i8_type.fn_type(&[i8_type.into(), i8_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let x = f.get_nth_param(0).unwrap().into_int_value();
+    let y = f.get_nth_param(1).unwrap().into_int_value();
+    let cond = codegen
+        .build_bits_compare(IntPredicate::ULT, x, y)
+        .expect("Compilation works");
+    let mut locals = SymbolTable::default();
+    locals.insert(c, cond);
+    let result: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(u8, u8) -> u8> =
+        unsafe { execution_engine.get_function("less_than") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(3, 5), 4);
+        assert_eq!(jit_f.call(5, 3), 2);
+        assert_eq!(jit_f.call(5, 5), 2);
+    }
+}
+
+#[test]
+fn wide_bits_representation() {
+    let context = Context::create();
+    let module = context.create_module("wide_bits");
+    let mut codegen = Codegen::new(&context, module);
+
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(256))
+            .expect("Valid representation"),
+        Repr::Type(context.custom_width_int_type(256).into())
+    );
+    assert_eq!(
+        codegen
+            .repr_bitsty(&BitsTy(129))
+            .expect("Valid representation"),
+        Repr::Type(context.custom_width_int_type(129).into())
+    );
+
+    // Constants wider than 64 bits are built from their limbs
+    let data = (3 << 64) | 5;
+    let i: IntValue = codegen
+        .build(&BitsTy(128).data(data).unwrap().into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles values");
+    assert_eq!(i.get_type().get_bit_width(), 128);
+    assert!(i.is_const());
+    let ty = context.i128_type();
+    let shifted = i.const_rshr(ty.const_int(64, false));
+    assert_eq!(shifted.get_zero_extended_constant(), Some(3));
+    assert_eq!(
+        i.const_truncate(context.i64_type())
+            .get_zero_extended_constant(),
+        Some(5)
+    );
+}
+
+#[test]
+fn named_lambdas_use_their_names() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("named_lambda");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+    codegen.set_name_prefix("ignored_".to_string());
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    codegen.set_name(&not, "negate_bool".to_string());
+
+    let f: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(f.get_name().to_str(), Ok("negate_bool"));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("negate_bool") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), false);
+        assert_eq!(jit_f.call(false), true);
+    }
+}
+
+#[test]
+fn polymorphic_identity_returns_its_argument() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("polymorphic_identity");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder
+        .parse_expr("|T: #type x: T| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, product) = builder
+        .parse_expr("#product[#finite(73) #finite(1025)]")
+        .expect("Valid product");
+    assert_eq!(rest, "");
+    let (rest, params) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(1025)]| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match params.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let x = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let app: ValId = Sexpr::try_new(vec![id, product, x.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let struct_type = context.struct_type(
+        &[context.i8_type().into(), context.i16_type().into()],
+        false,
+    );
+    let f = codegen.module().add_function(
+        "polymorphic_identity",
+        struct_type.fn_type(&[struct_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    let param = f.get_nth_param(0).unwrap();
+    let mut locals = SymbolTable::default();
+    locals.insert(x, Val::Value(param));
+    let result = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works");
+
+    // The struct is returned as-is, without any instructions shuffling its fields
+    assert_eq!(result, Val::Value(param));
+    assert!(entry.get_first_instruction().is_none());
+}
+
+#[test]
+fn identity_application_emits_no_call() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("identity_application");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder.parse_expr("|x: #bool| x").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let t: ValId = true.into();
+    let app: ValId = Sexpr::try_new(vec![id.clone(), t.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "apply_identity",
+        bool_type.fn_type(&[bool_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    let param = f.get_nth_param(0).unwrap();
+    let mut locals = SymbolTable::default();
+    locals.insert(t, Val::Value(param));
+    let result = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works");
+    assert_eq!(result, Val::Value(param));
+    assert!(entry.get_first_instruction().is_none());
+    codegen.builder().build_return(Some(&param));
+    assert!(f.verify(true));
+
+    // Neither a function body for the identity lambda nor a call to it is emitted
+    assert_eq!(codegen.get_compiled(&id), None);
+    let ir = codegen.module().print_to_string().to_string();
+    assert_eq!(ir.matches("define").count(), 1, "Unexpected IR: {}", ir);
+    assert!(!ir.contains("call"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn bits_add_with_overflow() {
+    let context = Context::create();
+    let module = context.create_module("add_with_overflow");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding
+    let i8_type = context.i8_type();
+    let folded = match codegen
+        .build_bits_add_with_overflow(
+            i8_type.const_int(200, false),
+            i8_type.const_int(100, false),
+            8,
+        )
+        .expect("Compilation works")
+    {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    assert!(codegen
+        .module()
+        .get_function("llvm.uadd.with.overflow.i8")
+        .is_none());
+    assert_eq!(
+        folded.get_type(),
+        context.struct_type(&[i8_type.into(), context.bool_type().into()], false)
+    );
+
+    // Runtime values, with the carry bit and sum packed into an i16
+    let i16_type = context.i16_type();
+    let f = codegen.module().add_function(
+        "uadd8",
+        i16_type.fn_type(&[i8_type.into(), i8_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let x = f.get_nth_param(0).unwrap().into_int_value();
+    let y = f.get_nth_param(1).unwrap().into_int_value();
+    let result = match codegen
+        .build_bits_add_with_overflow(x, y, 8)
+        .expect("Compilation works")
+    {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    let b = codegen.builder();
+    let sum = b
+        .build_extract_value(result, 0, "sum")
+        .unwrap()
+        .into_int_value();
+    let carry = b
+        .build_extract_value(result, 1, "carry")
+        .unwrap()
+        .into_int_value();
+    let sum = b.build_int_z_extend(sum, i16_type, "sum16");
+    let carry = b.build_int_z_extend(carry, i16_type, "carry16");
+    let carry = b.build_left_shift(carry, i16_type.const_int(8, false), "carry_hi");
+    let packed = b.build_or(carry, sum, "packed");
+    b.build_return(Some(&packed));
+    assert!(f.verify(true));
+    assert!(f
+        .print_to_string()
+        .to_string()
+        .contains("@llvm.uadd.with.overflow.i8"));
+
+    // The 64-bit case, returning only the carry bit
+    let i64_type = context.i64_type();
+    let g = codegen.module().add_function(
+        "uadd64_carry",
+        context
+            .bool_type()
+            .fn_type(&[i64_type.into(), i64_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(g, "entry");
+    codegen.builder().position_at_end(entry);
+    let x = g.get_nth_param(0).unwrap().into_int_value();
+    let y = g.get_nth_param(1).unwrap().into_int_value();
+    let result = match codegen
+        .build_bits_add_with_overflow(x, y, 64)
+        .expect("Compilation works")
+    {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    let carry = codegen
+        .builder()
+        .build_extract_value(result, 1, "carry")
+        .unwrap();
+    codegen.builder().build_return(Some(&carry));
+    assert!(g.verify(true));
+
+    // Jit
+    let uadd8: JitFunction<unsafe extern "C" fn(u8, u8) -> u16> =
+        unsafe { execution_engine.get_function("uadd8") }.expect("Valid IR generated");
+    let uadd64: JitFunction<unsafe extern "C" fn(u64, u64) -> bool> =
+        unsafe { execution_engine.get_function("uadd64_carry") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(uadd8.call(200, 100), 0x100 | 44);
+        assert_eq!(uadd8.call(100, 100), 200);
+        assert_eq!(uadd64.call(u64::MAX, 1), true);
+        assert_eq!(uadd64.call(u64::MAX - 1, 1), false);
+    }
+}
+
+#[test]
+fn bits_add_with_overflow_narrower_than_representation() {
+    let context = Context::create();
+    let module = context.create_module("add_with_overflow_narrow");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Constant folding: a `#bits(12)` is stored in an `i16`, but carries out of its 12th bit
+    let i16_type = context.i16_type();
+    let folded = match codegen
+        .build_bits_add_with_overflow(
+            i16_type.const_int(0xF00, false),
+            i16_type.const_int(0x200, false),
+            12,
+        )
+        .expect("Compilation works")
+    {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    let sum = codegen
+        .builder()
+        .build_extract_value(folded, 0, "sum")
+        .unwrap()
+        .into_int_value()
+        .get_zero_extended_constant();
+    let carry = codegen
+        .builder()
+        .build_extract_value(folded, 1, "carry")
+        .unwrap()
+        .into_int_value()
+        .get_zero_extended_constant();
+    assert_eq!(sum, Some(0x100));
+    assert_eq!(carry, Some(1));
+
+    // Runtime values, with the carry bit packed into the top bit of the result
+    let f = codegen.module().add_function(
+        "uadd12",
+        i16_type.fn_type(&[i16_type.into(), i16_type.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let x = f.get_nth_param(0).unwrap().into_int_value();
+    let y = f.get_nth_param(1).unwrap().into_int_value();
+    let result = match codegen
+        .build_bits_add_with_overflow(x, y, 12)
+        .expect("Compilation works")
+    {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    let b = codegen.builder();
+    let sum = b
+        .build_extract_value(result, 0, "sum")
+        .unwrap()
+        .into_int_value();
+    let carry = b
+        .build_extract_value(result, 1, "carry")
+        .unwrap()
+        .into_int_value();
+    let carry = b.build_int_z_extend(carry, i16_type, "carry16");
+    let carry = b.build_left_shift(carry, i16_type.const_int(15, false), "carry_hi");
+    let packed = b.build_or(carry, sum, "packed");
+    b.build_return(Some(&packed));
+    assert!(f.verify(true));
+
+    // Jit
+    let uadd12: JitFunction<unsafe extern "C" fn(u16, u16) -> u16> =
+        unsafe { execution_engine.get_function("uadd12") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(uadd12.call(0xF00, 0x200), 0x8000 | 0x100);
+        assert_eq!(uadd12.call(0x7FF, 0x800), 0xFFF);
+        assert_eq!(uadd12.call(0xFFF, 0xFFF), 0x8000 | 0xFFE);
+    }
+}
+
+#[test]
+fn tuples_of_applications_are_built_with_insertvalue() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("tuple_of_applications");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, pair) = builder
+        .parse_expr("|x: #bool y: #bool z: #bool| [(#and x y) z]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&pair)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("pand"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("insertvalue"), "Unexpected IR: {}", ir);
+
+    codegen.build_shim(f, "pair_shim", None);
+
+    #[repr(C)]
+    struct Pair {
+        first: bool,
+        second: bool,
+    }
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool, bool, *mut Pair) -> i32> =
+        unsafe { execution_engine.get_function("pair_shim") }.expect("Valid IR generated");
+
+    // Run
+    for &x in &[true, false] {
+        for &y in &[true, false] {
+            for &z in &[true, false] {
+                let mut pair = Pair {
+                    first: !(x && y),
+                    second: !z,
+                };
+                unsafe {
+                    assert_eq!(jit_f.call(x, y, z, &mut pair), 0);
+                }
+                assert_eq!(pair.first, x && y);
+                assert_eq!(pair.second, z);
+            }
+        }
+    }
+}
+
+#[test]
+fn val_basic_types() {
+    let context = Context::create();
+    let module = context.create_module("val_types");
+    let i8_type = context.i8_type();
+    let f = module.add_function("f", i8_type.fn_type(&[i8_type.into()], false), None);
+
+    assert_eq!(
+        Val::Value(i8_type.const_int(3, false).into()).basic_type(),
+        Some(i8_type.into())
+    );
+    assert_eq!(
+        Val::Function(f).basic_type(),
+        Some(
+            i8_type
+                .fn_type(&[i8_type.into()], false)
+                .ptr_type(AddressSpace::Generic)
+                .into()
+        )
+    );
+    assert_eq!(Val::Unit.basic_type(), None);
+    assert_eq!(Val::Contr.basic_type(), None);
+}
+
+#[test]
+fn finite_types_compile_to_their_cardinality() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("finite_values");
+    let mut codegen = Codegen::new(&context, module);
+
+    for &(expr, cardinality) in &[("#finite(6)", 6), ("#finite(1)", 1), ("#finite(0)", 0)] {
+        let (rest, finite) = builder.parse_expr(expr).expect("Valid finite type");
+        assert_eq!(rest, "");
+        let i: IntValue = codegen
+            .build(&finite)
+            .expect("Compilation works")
+            .try_into()
+            .expect("An integer");
+        assert_eq!(i.get_type(), context.i128_type());
+        assert!(i.is_const());
+        assert_eq!(i.get_zero_extended_constant(), Some(cardinality));
+    }
+
+    let large: u128 = (1 << 100) + 7;
+    let i: IntValue = codegen
+        .build_finite(&Finite(large))
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(
+        i.const_truncate(context.i64_type())
+            .get_zero_extended_constant(),
+        Some(7)
+    );
+}
+
+#[test]
+fn indices_above_u64_max_compile() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("big_index");
+    let mut codegen = Codegen::new(&context, module);
+
+    // #ix(2^65 + 10)[2^64 + 5]
+    let (rest, ix_val) = builder
+        .parse_expr("#ix(36893488147419103242)[18446744073709551621]")
+        .expect("Valid Index Instance");
+    assert_eq!(rest, "");
+    let val: IntValue = codegen
+        .build(&ix_val)
+        .expect("Valid value")
+        .try_into()
+        .expect("Integer value");
+    assert_eq!(val.get_type(), context.i128_type());
+    assert!(val.is_const());
+    let i64_type = context.i64_type();
+    let low = val.const_truncate(i64_type);
+    let high = val
+        .const_rshr(context.i128_type().const_int(64, false), false)
+        .const_truncate(i64_type);
+    assert_eq!(low.get_zero_extended_constant(), Some(5));
+    assert_eq!(high.get_zero_extended_constant(), Some(1));
+}
+
+/// A lowering compiling logical negation as a call to a given function
+#[derive(Debug)]
+struct NotAsCall<'ctx>(FunctionValue<'ctx>);
+
+impl<'ctx> PrimitiveLowering<'ctx> for NotAsCall<'ctx> {
+    fn lower(
+        &self,
+        cg: &mut Codegen<'ctx>,
+        f: &ValId,
+        args: &[ValId],
+    ) -> Option<Result<Val<'ctx>, Error>> {
+        match f.as_enum() {
+            ValueEnum::Logical(l) if *l == logical::Not => {
+                let arg = match cg.build(&args[0]) {
+                    Ok(Val::Value(arg)) => arg,
+                    Ok(v) => panic!("Expected a boolean argument, got {:?}", v),
+                    Err(err) => return Some(Err(err)),
+                };
+                let call = cg.builder().build_call(self.0, &[arg], "flip");
+                Some(Ok(Val::Value(
+                    call.try_as_basic_value()
+                        .left()
+                        .expect("Negation returns a value"),
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn ternary_branch_calls_are_placed_in_branch_blocks() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("ternary_branch_calls");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // An out-of-line negation, called from each branch
+    let bool_type = context.bool_type();
+    let flip =
+        codegen
+            .module()
+            .add_function("flip", bool_type.fn_type(&[bool_type.into()], false), None);
+    let flip_entry = context.append_basic_block(flip, "entry");
+    codegen.builder().position_at_end(flip_entry);
+    let flipped = codegen
+        .builder()
+        .build_not(flip.get_nth_param(0).unwrap().into_int_value(), "flipped");
+    codegen.builder().build_return(Some(&flipped));
+    assert!(flip.verify(true));
+    codegen.register_lowering(Rc::new(NotAsCall(flip)));
+
+    let (rest, id) = builder.parse_expr("|c: #bool| c").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match id.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let c = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let high: ValId = Sexpr::try_new(vec![logical::Not.into(), true.into()].into())
+        .expect("Valid application")
+        .into();
+    let low: ValId = Sexpr::try_new(vec![logical::Not.into(), false.into()].into())
+        .expect("Valid application")
+        .into();
+    let t: ValId = Ternary::conditional(high, low).unwrap().into();
+    let app: ValId = Sexpr::try_new(vec![t, c.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    let f = codegen.module().add_function(
+        "branch_calls",
+        bool_type.fn_type(&[bool_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(c, Val::Value(f.get_nth_param(0).unwrap()));
+    let result: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Each call lands in its own branch block, rather than in the entry block
+    let ir = f.print_to_string().to_string();
+    assert_eq!(ir.matches("call").count(), 2, "Unexpected IR: {}", ir);
+    for block in f.get_basic_blocks() {
+        let name = block
+            .get_name()
+            .to_str()
+            .expect("Block names are valid UTF-8")
+            .to_owned();
+        let mut calls = 0;
+        let mut instruction = block.get_first_instruction();
+        while let Some(i) = instruction {
+            if i.get_opcode() == InstructionOpcode::Call {
+                calls += 1;
+            }
+            instruction = i.get_next_instruction();
+        }
+        let expected = if name == "high" || name == "low" {
+            1
+        } else {
+            0
+        };
+        assert_eq!(
+            calls, expected,
+            "Unexpected calls in block {}: {}",
+            name, ir
+        );
+    }
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("branch_calls") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), false);
+        assert_eq!(jit_f.call(false), true);
+    }
+}
+
+#[test]
+fn shim_pointer_types_are_typed() {
+    let context = Context::create();
+    let module = context.create_module("pointer_types");
+    let mut codegen = Codegen::new(&context, module);
+
+    // Shim parameters for struct arguments are typed pointers to the struct
+    let struct_ty = context.struct_type(
+        &[context.i8_type().into(), context.i16_type().into()],
+        false,
+    );
+    let f = codegen.module().add_function(
+        "first",
+        context.i8_type().fn_type(&[struct_ty.into()], false),
+        None,
+    );
+    let entry = context.append_basic_block(f, "entry");
+    codegen.builder().position_at_end(entry);
+    let first = codegen
+        .builder()
+        .build_extract_value(f.get_nth_param(0).unwrap().into_struct_value(), 0, "first")
+        .unwrap();
+    codegen.builder().build_return(Some(&first));
+    assert!(f.verify(true));
+
+    let struct_ptr = codegen.ptr_to(struct_ty, AddressSpace::Global);
+    let shim = codegen.build_shim(f, "first_shim", None);
+    assert!(shim.verify(true));
+    assert_eq!(shim.get_type().get_param_types(), vec![struct_ptr.into()]);
+    let ir = shim.print_to_string().to_string();
+    assert!(
+        ir.contains("{ i8, i16 } addrspace(1)*"),
+        "Unexpected IR: {}",
+        ir
+    );
+}
+
+#[test]
+fn compiled_lambdas_are_cached() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("lambda_cache");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match not.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let first: FunctionValue = codegen
+        .build_lambda(lambda)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    let second: FunctionValue = codegen
+        .build_lambda(lambda)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    let built: FunctionValue = codegen
+        .build(&not)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert_eq!(first, second);
+    assert_eq!(first, built);
+    assert_eq!(codegen.get_compiled(&not), Some(&Val::Function(first)));
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert_eq!(ir.matches("define").count(), 1, "Unexpected IR: {}", ir);
+    assert!(!ir.contains("__lambda_1"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn constant_functions_ignore_their_arguments() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("constant_functions");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, first) = builder
+        .parse_expr("|x: #bool y: #bool| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let pi = match first.as_enum() {
+        ValueEnum::Lambda(l) => l.get_ty().clone(),
+        _ => panic!("Expected a lambda"),
+    };
+    let f: FunctionValue = codegen
+        .build_constant(&pi, &true.into())
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    assert_eq!(f.count_params(), 2);
+
+    // Propositional results have no representation
+    let (rest, unit) = builder
+        .parse_expr("|x: #bool| #ix(1)[0]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let unit_pi = match unit.as_enum() {
+        ValueEnum::Lambda(l) => l.get_ty().clone(),
+        _ => panic!("Expected a lambda"),
+    };
+    let (rest, unit_ix) = builder.parse_expr("#ix(1)[0]").expect("Valid index");
+    assert_eq!(rest, "");
+    assert_eq!(
+        codegen
+            .build_constant(&unit_pi, &unit_ix)
+            .expect("Compilation works"),
+        Val::Unit
+    );
+
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, bool) -> bool> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    for &x in &[true, false] {
+        for &y in &[true, false] {
+            unsafe {
+                assert_eq!(jit_f.call(x, y), true);
+            }
+        }
+    }
+}
+
+#[test]
+fn if_then_else_lowering_selects_or_branches() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("if_then_else");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, ite) = builder
+        .parse_expr("|c: #bool a: #bool b: #bool| (#or (#and c a) (#and (#not c) b))")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    codegen.register_lowering(Rc::new(IteLowering(ite.clone())));
+
+    let (rest, params) = builder
+        .parse_expr("|c: #bool a: #bool b: #bool| c")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match params.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let region = lambda.get_ty().def_region().clone();
+    let c = ValId::from(region.clone().param(0).expect("Lambda has a parameter"));
+    let a = ValId::from(region.clone().param(1).expect("Lambda has two parameters"));
+    let b = ValId::from(region.param(2).expect("Lambda has three parameters"));
+
+    // Selecting between parameters, which are already compiled, needs no control flow
+    let select: ValId = Sexpr::try_new(vec![ite.clone(), c.clone(), a.clone(), b.clone()].into())
+        .expect("Valid application")
+        .into();
+    // Selecting between computations builds each in its own branch
+    let and: ValId = Sexpr::try_new(vec![logical::And.into(), a.clone(), b.clone()].into())
+        .expect("Valid application")
+        .into();
+    let or: ValId = Sexpr::try_new(vec![logical::Or.into(), a.clone(), b.clone()].into())
+        .expect("Valid application")
+        .into();
+    let branch: ValId = Sexpr::try_new(vec![ite, c.clone(), and, or].into())
+        .expect("Valid application")
+        .into();
+
+    let bool_type = context.bool_type();
+    for (name, app) in &[("ite_select", &select), ("ite_branch", &branch)] {
+        let f = codegen.module().add_function(
+            name,
+            bool_type.fn_type(
+                &[bool_type.into(), bool_type.into(), bool_type.into()],
+                false,
+            ),
+            None,
+        );
+        context.append_basic_block(f, "entry");
+        let mut locals = SymbolTable::default();
+        for (ix, param) in [&c, &a, &b].iter().enumerate() {
+            locals.insert(
+                (*param).clone(),
+                Val::Value(f.get_nth_param(ix as u32).unwrap()),
+            );
+        }
+        let result: IntValue = codegen
+            .build_in_function(f, app, locals)
+            .expect("Compilation works")
+            .try_into()
+            .expect("A boolean");
+        codegen.builder().build_return(Some(&result));
+        assert!(f.verify(true));
+    }
+
+    let select_ir = codegen
+        .module()
+        .get_function("ite_select")
+        .unwrap()
+        .print_to_string()
+        .to_string();
+    assert!(
+        select_ir.contains("select i1"),
+        "Unexpected IR: {}",
+        select_ir
+    );
+    assert!(!select_ir.contains("br "), "Unexpected IR: {}", select_ir);
+    let branch_ir = codegen
+        .module()
+        .get_function("ite_branch")
+        .unwrap()
+        .print_to_string()
+        .to_string();
+    assert!(branch_ir.contains("br i1"), "Unexpected IR: {}", branch_ir);
+    assert!(branch_ir.contains("phi"), "Unexpected IR: {}", branch_ir);
+
+    // Jit
+    let jit_select: JitFunction<unsafe extern "C" fn(bool, bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("ite_select") }.expect("Valid IR generated");
+    let jit_branch: JitFunction<unsafe extern "C" fn(bool, bool, bool) -> bool> =
+        unsafe { execution_engine.get_function("ite_branch") }.expect("Valid IR generated");
+
+    // Run
+    for &c in &[true, false] {
+        for &a in &[true, false] {
+            for &b in &[true, false] {
+                unsafe {
+                    assert_eq!(jit_select.call(c, a, b), if c { a } else { b });
+                    assert_eq!(jit_branch.call(c, a, b), if c { a && b } else { a || b });
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn named_products_are_named_structs() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("records");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, tuple) = builder
+        .parse_expr("[#ix(73)[1] #ix(1025)[2]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let product_ty = tuple.as_enum().ty().clone_ty();
+    codegen.set_record_name(&product_ty, "Pair".to_owned());
+    assert_eq!(
+        codegen.record_names().get(&product_ty).map(String::as_str),
+        Some("Pair")
+    );
+
+    let pair = match codegen.repr(&product_ty).expect("Valid representation") {
+        Repr::Product(p) => p.repr,
+        r => panic!("Expected a product representation, got {:?}", r),
+    };
+    assert_eq!(
+        pair.get_name().and_then(|name| name.to_str().ok()),
+        Some("Pair")
+    );
+    assert_eq!(
+        pair.get_field_types(),
+        vec![context.i8_type().into(), context.i16_type().into()]
+    );
+
+    let (rest, id) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(1025)]| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&id)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    assert_eq!(f.get_type().get_param_types(), vec![pair.into()]);
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(
+        ir.contains("%Pair = type { i8, i16 }"),
+        "Unexpected IR: {}",
+        ir
+    );
+}
+
+#[test]
+fn closures_capture_enclosing_parameters() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("closures");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, curried) = builder
+        .parse_expr("|x: #bool| |y: #bool| (#and x y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match curried.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let x = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let inner = lambda.result().clone();
+    let mapping = match inner.as_enum() {
+        ValueEnum::Lambda(l) => {
+            assert_eq!(Codegen::closure_captures(l), vec![x.clone()]);
+            codegen
+                .param_mapping(l.get_ty())
+                .expect("Valid parameter types")
+        }
+        _ => panic!("Expected a nested lambda"),
+    };
+
+    // Returning a closure would leak its stack-allocated environment
+    match codegen.build(&curried) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!(
+            "Expected returning a closure to be unsupported, got {:?}",
+            r
+        ),
+    }
+
+    // Build the closure, and call it, within a function
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "closure_and",
+        bool_type.fn_type(&[bool_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(x, Val::Value(f.get_nth_param(0).unwrap()));
+    let closure = match codegen
+        .build_in_function(f, &inner, locals)
+        .expect("Compilation works")
+    {
+        Val::Closure(closure) => closure,
+        v => panic!("Expected a closure, got {:?}", v),
+    };
+    // Closures point to their environment on the stack, so are never constant
+    assert!(!Val::Closure(closure).is_const());
+    let result: IntValue = codegen
+        .build_closure_call(closure, &mapping, &[true.into()])
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(ir.contains("alloca"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("define i1 @__closure"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("(i8* %0, i1 %1)"), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("closure_and") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), true);
+        assert_eq!(jit_f.call(false), false);
+    }
+}
+
+#[test]
+fn partial_application_to_non_constants_builds_closure() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("partial_non_const");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, and) = builder
+        .parse_expr("|x: #bool y: #bool| (#and x y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let t: ValId = true.into();
+    let partial: ValId = Sexpr::try_new(vec![and, t.clone()].into())
+        .expect("Valid application")
+        .into();
+
+    // Bind the applied argument to a parameter, so that it is not a constant
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "partial_non_const",
+        bool_type.fn_type(&[bool_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(t, Val::Value(f.get_nth_param(0).unwrap()));
+    let closure = match codegen
+        .build_in_function(f, &partial, locals)
+        .expect("Compilation works")
+    {
+        Val::Closure(closure) => closure,
+        v => panic!("Expected a closure, got {:?}", v),
+    };
+
+    // The closure takes its environment, followed by the remaining parameter
+    let mut mapping = IxMap::with_capacity(1);
+    mapping.push_ix(0);
+    let result: IntValue = codegen
+        .with_function(f, |codegen| {
+            codegen.build_closure_call(closure, &mapping, &[true.into()])
+        })
+        .expect("Compilation works")
+        .try_into()
+        .expect("A boolean");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(ir.contains("alloca"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("define i1 @__partial"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("(i8* %0, i1 %1)"), "Unexpected IR: {}", ir);
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("partial_non_const") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(true), true);
+        assert_eq!(jit_f.call(false), false);
+    }
+}
+
+#[test]
+fn curried_lambdas_are_applied_one_argument_at_a_time() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("curried");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, curried) = builder
+        .parse_expr("|x: #bool| |y: #bool| (#and x y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, uncurried) = builder
+        .parse_expr("|x: #bool y: #bool| (#and x y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, params) = builder.parse_expr("|y: #bool| y").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let y = match params.as_enum() {
+        ValueEnum::Lambda(l) => ValId::from(
+            l.get_ty()
+                .def_region()
+                .clone()
+                .param(0)
+                .expect("Lambda has a parameter"),
+        ),
+        _ => panic!("Expected a lambda"),
+    };
+
+    // Applying either lambda to a constant specializes it to a function of the remaining parameter
+    for (name, f) in &[
+        ("curried_true", (&curried, true)),
+        ("uncurried_true", (&uncurried, true)),
+        ("curried_false", (&curried, false)),
+    ] {
+        let (f, x) = *f;
+        let partial: ValId = Sexpr::try_new(vec![f.clone(), x.into()].into())
+            .expect("Valid application")
+            .into();
+        let specialized: FunctionValue = codegen
+            .build(&partial)
+            .expect("Compilation works")
+            .try_into()
+            .expect("Compiles to a function");
+        assert!(specialized.verify(true));
+        assert_eq!(specialized.count_params(), 1);
+
+        // Then apply the result to the second argument
+        let app: ValId = Sexpr::try_new(vec![partial, y.clone()].into())
+            .expect("Valid application")
+            .into();
+        let bool_type = context.bool_type();
+        let wrapper = codegen.module().add_function(
+            name,
+            bool_type.fn_type(&[bool_type.into()], false),
+            None,
+        );
+        context.append_basic_block(wrapper, "entry");
+        let mut locals = SymbolTable::default();
+        locals.insert(y.clone(), Val::Value(wrapper.get_nth_param(0).unwrap()));
+        let result: IntValue = codegen
+            .build_in_function(wrapper, &app, locals)
+            .expect("Compilation works")
+            .try_into()
+            .expect("A boolean");
+        codegen.builder().build_return(Some(&result));
+        assert!(wrapper.verify(true));
+    }
+
+    // Jit
+    let curried_true: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("curried_true") }.expect("Valid IR generated");
+    let uncurried_true: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("uncurried_true") }.expect("Valid IR generated");
+    let curried_false: JitFunction<unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function("curried_false") }.expect("Valid IR generated");
+
+    // Run
+    for &y in &[true, false] {
+        unsafe {
+            assert_eq!(curried_true.call(y), y);
+            assert_eq!(uncurried_true.call(y), y);
+            assert_eq!(curried_false.call(y), false);
+        }
+    }
+}
+
+#[cfg(feature = "strict")]
+#[test]
+fn strict_mode_reports_inconsistent_function_state() {
+    use rain_ir::region::Region;
+
+    let context = Context::create();
+    let module = context.create_module("strict_state");
+    let codegen = Codegen::new(&context, module);
+    let f =
+        codegen
+            .module()
+            .add_function("not_current", context.void_type().fn_type(&[], false), None);
+
+    // `f` is not the current function, as if the state had been corrupted while building its body
+    match codegen.check_function_state(&Region::NULL, f) {
+        Err(Error::InternalError(_)) => {}
+        r => panic!("Expected an internal error, got {:?}", r),
+    }
+}
+
+#[test]
+fn dependent_return_types_are_errors() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("dependent_return");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, id) = builder
+        .parse_expr("|T: #type x: T| x")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let pi = match id.as_enum() {
+        ValueEnum::Lambda(l) => l.get_ty().clone(),
+        _ => panic!("Expected a lambda"),
+    };
+
+    match codegen.build(&id) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected an error, got {:?}", r),
+    }
+    match codegen.build_constant(&pi, &true.into()) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected an error, got {:?}", r),
+    }
+    assert!(codegen.module().get_first_function().is_none());
+}
+
+#[test]
+fn bits_operator_arity_is_validated() {
+    let context = Context::create();
+    let module = context.create_module("bits_arity");
+    let mut codegen = Codegen::new(&context, module);
+
+    let x: ValId = BitsTy(8).data(3).unwrap().into();
+    let y: ValId = BitsTy(8).data(5).unwrap().into();
+
+    // Binary operators take a type and two operands, while negation takes a type and one operand
+    for op in vec![BinOp::Add, BinOp::Mul, BinOp::Sub] {
+        let op = op.into_var();
+        match codegen.build_app(op.as_val(), &[BitsTy(8).into_val(), x.clone()]) {
+            Err(Error::NotImplemented(_)) => {}
+            r => panic!(
+                "Expected partial application to be unsupported, got {:?}",
+                r
+            ),
+        }
+        let sum: IntValue = codegen
+            .build_app(op.as_val(), &[BitsTy(8).into_val(), x.clone(), y.clone()])
+            .expect("Compilation works")
+            .try_into()
+            .expect("An integer");
+        assert!(sum.is_const());
+    }
+    let neg = Neg.into_var();
+    match codegen.build_app(neg.as_val(), &[BitsTy(8).into_val(), x.clone(), y]) {
+        Err(Error::NotImplemented(_)) => {}
+        r => panic!("Expected over-application to be unsupported, got {:?}", r),
+    }
+    let negated: IntValue = codegen
+        .build_app(neg.as_val(), &[BitsTy(8).into_val(), x])
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(negated.get_zero_extended_constant(), Some(253));
+}
+
+#[test]
+fn matches_switch_on_variant_tags() {
+    let context = Context::create();
+    let module = context.create_module("variant_match");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // A two-variant union with a byte payload, i.e. `{ i1, i8 }`
+    let i8_type = context.i8_type();
+    let variant_ty = context.struct_type(&[context.bool_type().into(), i8_type.into()], false);
+    let f = codegen.module().add_function(
+        "variant_match",
+        i8_type.fn_type(&[context.bool_type().into(), i8_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let arms: Vec<(usize, ValId)> = vec![
+        (0, BitsTy(8).data(3).unwrap().into()),
+        (1, BitsTy(8).data(5).unwrap().into()),
+    ];
+    let result: IntValue = codegen
+        .with_function(f, |codegen| {
+            let tag = f.get_nth_param(0).unwrap();
+            let payload = f.get_nth_param(1).unwrap();
+            let variant = codegen
+                .builder()
+                .build_insert_value(variant_ty.get_undef(), tag, 0, "variant")
+                .unwrap()
+                .into_struct_value();
+            let variant = codegen
+                .builder()
+                .build_insert_value(variant, payload, 1, "variant")
+                .unwrap()
+                .into_struct_value();
+            codegen.build_match(Val::Value(variant.into()), &arms[..])
+        })
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("switch i1"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("phi i8"), "Unexpected IR: {}", ir);
+
+    // Constant tags select their arm directly
+    let folded: IntValue = codegen
+        .with_function(f, |codegen| {
+            codegen.build_match(
+                Val::Value(context.bool_type().const_int(1, false).into()),
+                &arms[..],
+            )
+        })
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(folded.get_zero_extended_constant(), Some(5));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool, u8) -> u8> =
+        unsafe { execution_engine.get_function("variant_match") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(false, 7), 3);
+        assert_eq!(jit_f.call(true, 7), 5);
+    }
+}
+
+#[test]
+fn matches_with_propositional_arms_are_propositional() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("propositional_match");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, unit) = builder.parse_expr("#ix(1)[0]").expect("Valid index");
+    assert_eq!(rest, "");
+    let bool_type = context.bool_type();
+    let f = codegen.module().add_function(
+        "propositional_match",
+        bool_type.fn_type(&[bool_type.into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let arms: Vec<(usize, ValId)> = vec![(0, unit.clone()), (1, unit)];
+    let tag = f.get_nth_param(0).unwrap();
+    let result = codegen
+        .with_function(f, |codegen| codegen.build_match(Val::Value(tag), &arms[..]))
+        .expect("Compilation works");
+    assert_eq!(result, Val::Unit);
+    codegen.builder().build_return(Some(&tag));
+    assert!(f.verify(true));
+
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("switch i1"), "Unexpected IR: {}", ir);
+    assert!(!ir.contains("phi"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn target_features_are_attached_to_functions() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("target_features");
+    let mut codegen = Codegen::new(&context, module);
+    codegen
+        .create_target_machine(OptimizationLevel::None)
+        .expect("The host is a valid target");
+    codegen.set_target_cpu(Some("generic".into()));
+    codegen.set_target_features(Some("+avx2".into()));
+    assert_eq!(codegen.target_features(), Some("+avx2"));
+
+    let (rest, bool_id) = builder.parse_expr("|x: #bool| x").expect("Valid function");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&bool_id)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+
+    let features = f
+        .get_string_attribute(AttributeLoc::Function, "target-features")
+        .expect("Target features are attached");
+    assert_eq!(features.get_string_value().to_str(), Ok("+avx2"));
+    let cpu = f
+        .get_string_attribute(AttributeLoc::Function, "target-cpu")
+        .expect("Target CPU is attached");
+    assert_eq!(cpu.get_string_value().to_str(), Ok("generic"));
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(
+        ir.contains("\"target-features\"=\"+avx2\""),
+        "Unexpected IR: {}",
+        ir
+    );
+}
+
+#[test]
+fn product_types_compile_to_their_size() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("product_types");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, product) = builder
+        .parse_expr("#product[#finite(73) #finite(1025)]")
+        .expect("Valid product");
+    assert_eq!(rest, "");
+    let size: IntValue = codegen
+        .build(&product)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert!(size.is_const());
+    assert_eq!(size.get_type().get_bit_width(), 64);
+    let repr = context.struct_type(
+        &[context.i8_type().into(), context.i16_type().into()],
+        false,
+    );
+    assert_eq!(Some(size), repr.size_of());
+
+    let (rest, unit) = builder
+        .parse_expr("#product[#finite(1) #finite(1)]")
+        .expect("Valid product");
+    assert_eq!(rest, "");
+    assert_eq!(codegen.build(&unit).expect("Compilation works"), Val::Unit);
+}
+
+#[test]
+fn tuples_with_interleaved_unit_members_compile() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("interleaved_units");
+    let mut codegen = Codegen::new(&context, module);
+    let i8_ty = context.i8_type().into();
+    let repr = context.struct_type(&[i8_ty, i8_ty], false);
+
+    // Constant members are placed in the slots they are mapped to
+    let (rest, tuple) = builder
+        .parse_expr("[#ix(1)[0] #ix(6)[3] #ix(1)[0] #ix(73)[5]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let tuple = match codegen.build(&tuple).expect("Compilation works") {
+        Val::Value(BasicValueEnum::StructValue(s)) => s,
+        v => panic!("Expected a struct, got {:?}", v),
+    };
+    assert_eq!(tuple.get_type(), repr);
+    assert_eq!(tuple.print_to_string().to_string(), "{ i8 3, i8 5 }");
+
+    // Non-constant members, in arbitrary order
+    let (rest, f) = builder
+        .parse_expr("|x: #ix(6) y: #ix(73)| [y #ix(1)[0] #ix(1)[0] x #ix(1)[0]]")
+        .expect("Valid function");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&f)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    assert_eq!(f.get_type().get_return_type(), Some(repr.into()));
+    let ir = f.print_to_string().to_string();
+    assert!(ir.contains("i8 %1, 0"), "Unexpected IR: {}", ir);
+    assert!(ir.contains("i8 %0, 1"), "Unexpected IR: {}", ir);
+}
+
+#[test]
+fn signed_bits_lowerings_compute_abs_and_sign() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("signed_bits");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    // Frontend definitions of abs and sign, whose applications are lowered directly
+    let (rest, abs) = builder.parse_expr("|x: #ix(256)| x").expect("Valid lambda");
+    assert_eq!(rest, "");
+    let (rest, sign) = builder
+        .parse_expr("|x: #ix(256)| #ix(256)[0]")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    codegen.register_lowering(Rc::new(SignedBitsLowering(abs.clone(), SignedBitsOp::Abs)));
+    codegen.register_lowering(Rc::new(SignedBitsLowering(
+        sign.clone(),
+        SignedBitsOp::Sign,
+    )));
+
+    // Constant inputs fold, with `-5` encoded as `251` in 8 bits and as `27` in 5 bits
+    for (f, width, x, expected) in &[
+        (&abs, 8, 251, 5),
+        (&abs, 8, 5, 5),
+        (&abs, 5, 27, 5),
+        (&sign, 8, 251, 255),
+        (&sign, 8, 5, 0),
+        (&sign, 5, 27, 31),
+    ] {
+        let x: ValId = BitsTy(*width).data(*x).unwrap().into();
+        let result: IntValue = codegen
+            .build_app(f, &[BitsTy(*width).into_val(), x])
+            .expect("Compilation works")
+            .try_into()
+            .expect("An integer");
+        assert!(result.is_const());
+        assert_eq!(result.get_zero_extended_constant(), Some(*expected));
+    }
+
+    // Non-constant inputs
+    let i8_type = context.i8_type();
+    let f =
+        codegen
+            .module()
+            .add_function("abs_i8", i8_type.fn_type(&[i8_type.into()], false), None);
+    context.append_basic_block(f, "entry");
+    let result = codegen
+        .with_function(f, |codegen| {
+            let x = f.get_nth_param(0).unwrap().into_int_value();
+            codegen.build_signed_bits_op(SignedBitsOp::Abs, x, 8)
+        })
+        .expect("Compilation works");
+    codegen.builder().build_return(Some(&result));
+    assert!(f.verify(true));
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(i8) -> i8> =
+        unsafe { execution_engine.get_function("abs_i8") }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        assert_eq!(jit_f.call(-5), 5);
+        assert_eq!(jit_f.call(7), 7);
+        assert_eq!(jit_f.call(0), 0);
+        assert_eq!(jit_f.call(-128), -128);
+    }
+}
+
+#[test]
+fn reprs_are_checked_against_expected_types() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("repr_types");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, tuple) = builder
+        .parse_expr("[#ix(73)[1] #ix(1025)[2]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let product_ty = tuple.as_enum().ty().clone_ty();
+    let repr = codegen.repr(&product_ty).expect("Valid representation");
+
+    let i8_ty = context.i8_type().into();
+    let i16_ty = context.i16_type().into();
+    repr.assert_type(context.struct_type(&[i8_ty, i16_ty], false).into())
+        .expect("Matches the C layout");
+    match repr.assert_type(context.struct_type(&[i16_ty, i8_ty], false).into()) {
+        Err(Error::ReprMismatch(_)) => {}
+        r => panic!("Expected a representation mismatch, got {:?}", r),
+    }
+    match Repr::Prop.assert_type(i8_ty) {
+        Err(Error::ReprMismatch(_)) => {}
+        r => panic!("Expected a representation mismatch, got {:?}", r),
+    }
+
+    // Functions are lowered to the function pointer type given by the context
+    let (rest, not) = builder
+        .parse_expr("|x: #bool| (#not x)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let lambda = match not.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let repr = codegen
+        .build_function_repr(lambda.get_ty())
+        .expect("Valid representation");
+    let fn_ty = match &repr {
+        Repr::Function(f) => f.repr,
+        r => panic!("Expected a function representation, got {:?}", r),
+    };
+    repr.assert_type_in(&codegen, codegen.fn_ptr_to(fn_ty).into())
+        .expect("Matches the function pointer type");
+    repr.assert_type(fn_ty.ptr_type(AddressSpace::Generic).into())
+        .expect("Matches the generic function pointer type");
+    match Repr::Prop.assert_type_in(&codegen, i8_ty) {
+        Err(Error::ReprMismatch(_)) => {}
+        r => panic!("Expected a representation mismatch, got {:?}", r),
+    }
+}
+
+#[test]
+fn tuples_with_runtime_members_compile() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("runtime_tuples");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, pair) = builder
+        .parse_expr("|x: #ix(6)| [x #ix(6)[2]]")
+        .expect("Valid function");
+    assert_eq!(rest, "");
+    let pair: FunctionValue = codegen
+        .build(&pair)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(pair.verify(true));
+    let ir = pair.print_to_string().to_string();
+    assert!(ir.contains("insertvalue"), "Unexpected IR: {}", ir);
+
+    // Extract both fields back out of the returned struct
+    let i8_type = context.i8_type();
+    for (name, ix) in &[("pair_fst", 0), ("pair_snd", 1)] {
+        let f =
+            codegen
+                .module()
+                .add_function(name, i8_type.fn_type(&[i8_type.into()], false), None);
+        let entry = context.append_basic_block(f, "entry");
+        codegen.builder().position_at_end(entry);
+        let result = codegen
+            .builder()
+            .build_call(pair, &[f.get_nth_param(0).unwrap()], "pair")
+            .try_as_basic_value()
+            .left()
+            .expect("A struct")
+            .into_struct_value();
+        let field = codegen
+            .builder()
+            .build_extract_value(result, *ix, "field")
+            .expect("Valid index");
+        codegen.builder().build_return(Some(&field));
+        assert!(f.verify(true));
+    }
+
+    // Jit
+    let fst: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("pair_fst") }.expect("Valid IR generated");
+    let snd: JitFunction<unsafe extern "C" fn(u8) -> u8> =
+        unsafe { execution_engine.get_function("pair_snd") }.expect("Valid IR generated");
+
+    // Run
+    for x in 0..6 {
+        unsafe {
+            assert_eq!(fst.call(x), x);
+            assert_eq!(snd.call(x), 2);
+        }
+    }
+}
+
+#[test]
+fn applications_of_pointer_backed_tuples_use_gep() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("pointer_tuple_app");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, lambda) = builder
+        .parse_expr("|x: #product[#finite(73) #finite(1025)]| x")
+        .expect("Valid function");
+    assert_eq!(rest, "");
+    let lambda = match lambda.as_enum() {
+        ValueEnum::Lambda(l) => l,
+        _ => panic!("Expected a lambda"),
+    };
+    let x = ValId::from(
+        lambda
+            .get_ty()
+            .def_region()
+            .clone()
+            .param(0)
+            .expect("Lambda has a parameter"),
+    );
+    let (rest, ix) = builder.parse_expr("#ix(2)[1]").expect("Valid index");
+    assert_eq!(rest, "");
+    let app: ValId = Sexpr::try_new(vec![x.clone(), ix].into())
+        .expect("Valid application")
+        .into();
+
+    // The tuple is passed by pointer, as by the shim
+    let i16_type = context.i16_type();
+    let struct_ty = context.struct_type(&[context.i8_type().into(), i16_type.into()], false);
+    let f = codegen.module().add_function(
+        "second_by_ptr",
+        i16_type.fn_type(&[struct_ty.ptr_type(AddressSpace::Generic).into()], false),
+        None,
+    );
+    context.append_basic_block(f, "entry");
+    let mut locals = SymbolTable::default();
+    locals.insert(x, Val::Value(f.get_nth_param(0).unwrap()));
+    let second: IntValue = codegen
+        .build_in_function(f, &app, locals)
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    codegen.builder().build_return(Some(&second));
+    assert!(f.verify(true));
+    let ir = f.print_to_string().to_string();
+    assert!(
+        ir.contains("getelementptr inbounds"),
+        "Unexpected IR: {}",
+        ir
+    );
+    assert!(!ir.contains("extractvalue"), "Unexpected IR: {}", ir);
+
+    #[repr(C)]
+    struct Pair {
+        first: u8,
+        second: u16,
+    }
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(*const Pair) -> u16> =
+        unsafe { execution_engine.get_function("second_by_ptr") }.expect("Valid IR generated");
+
+    // Run
+    let pair = Pair {
+        first: 72,
+        second: 1024,
+    };
+    assert_eq!(pair.first, 72);
+    unsafe {
+        assert_eq!(jit_f.call(&pair), 1024);
+    }
+}
+
+#[test]
+fn tuples_of_named_products_are_named_structs() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("record_tuples");
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, tuple) = builder
+        .parse_expr("[#ix(73)[1] #ix(1025)[2]]")
+        .expect("Valid tuple");
+    assert_eq!(rest, "");
+    let product_ty = tuple.as_enum().ty().clone_ty();
+    codegen.set_record_name(&product_ty, "Pair".to_owned());
+    let pair = match codegen.repr(&product_ty).expect("Valid representation") {
+        Repr::Product(p) => p.repr,
+        r => panic!("Expected a product representation, got {:?}", r),
+    };
+
+    // Constant tuples
+    match codegen.build(&tuple).expect("Compilation works") {
+        Val::Value(BasicValueEnum::StructValue(s)) => assert_eq!(s.get_type(), pair),
+        v => panic!("Expected a struct, got {:?}", v),
+    }
+
+    // Tuples built and returned at runtime
+    let (rest, f) = builder
+        .parse_expr("|x: #ix(73)| [x #ix(1025)[2]]")
+        .expect("Valid function");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&f)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    assert_eq!(f.get_type().get_return_type(), Some(pair.into()));
+
+    // Projections out of the record
+    let product = match product_ty.as_enum() {
+        ValueEnum::Product(p) => p,
+        _ => panic!("Expected a product"),
+    };
+    let proj: FunctionValue = codegen
+        .build_projection_fn(product, 1)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(proj.verify(true));
+    assert_eq!(proj.get_type().get_param_types(), vec![pair.into()]);
+    assert!(codegen.module().verify().is_ok());
+}
+
+#[test]
+fn wasm32_caps_integer_widths_of_values() {
+    let context = Context::create();
+    let module = context.create_module("wasm32_values");
+    let mut codegen = Codegen::new(&context, module);
+    let native_triple = codegen.module().get_triple().as_str().to_owned();
+    codegen.set_target_profile(TargetProfile::Wasm32);
+
+    // Values of types too wide for the profile are not representable
+    let wide: ValId = BitsTy(100).data(1).unwrap().into();
+    match codegen.build(&wide) {
+        Err(Error::UnrepresentableType) => {}
+        r => panic!("Expected an unrepresentable type, got {:?}", r),
+    }
+    match codegen.repr_finite(&Finite(1 << 100)) {
+        Err(Error::UnrepresentableType) => {}
+        r => panic!("Expected an unrepresentable type, got {:?}", r),
+    }
+
+    // Finite types are encoded as the widest integers allowed
+    let cardinality: IntValue = codegen
+        .build_finite(&Finite(6))
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(cardinality.get_type().get_bit_width(), 64);
+    assert_eq!(cardinality.get_zero_extended_constant(), Some(6));
+    match codegen.build_finite(&Finite(1 << 100)) {
+        Err(Error::UnrepresentableType) => {}
+        r => panic!("Expected an unrepresentable type, got {:?}", r),
+    }
+
+    // Switching back restores the original triple and integer widths
+    codegen.set_target_profile(TargetProfile::Native);
+    assert_eq!(
+        codegen.module().get_triple().as_str().to_owned(),
+        native_triple
+    );
+    let ir = codegen.module().print_to_string().to_string();
+    assert!(!ir.contains("wasm32"), "Unexpected IR: {}", ir);
+    let cardinality: IntValue = codegen
+        .build_finite(&Finite(6))
+        .expect("Compilation works")
+        .try_into()
+        .expect("An integer");
+    assert_eq!(cardinality.get_type().get_bit_width(), 128);
+    codegen.build(&wide).expect("Compilation works");
+}
+
+#[test]
+fn functions_returning_functions_return_pointers() {
+    let mut builder = Builder::<&str>::new();
+    let context = Context::create();
+    let module = context.create_module("higher_order");
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap();
+    let mut codegen = Codegen::new(&context, module);
+
+    let (rest, outer) = builder
+        .parse_expr("|x: #bool| |y: #bool| (#not y)")
+        .expect("Valid lambda");
+    assert_eq!(rest, "");
+    let f: FunctionValue = codegen
+        .build(&outer)
+        .expect("Compilation works")
+        .try_into()
+        .expect("Compiles to a function");
+    assert!(f.verify(true));
+    assert!(f
+        .get_type()
+        .get_return_type()
+        .expect("A non-void function")
+        .is_pointer_type());
+    let f_name = f
+        .get_name()
+        .to_str()
+        .expect("Generated name must be valid UTF-8");
+
+    // Jit
+    let jit_f: JitFunction<unsafe extern "C" fn(bool) -> unsafe extern "C" fn(bool) -> bool> =
+        unsafe { execution_engine.get_function(f_name) }.expect("Valid IR generated");
+
+    // Run
+    unsafe {
+        let not = jit_f.call(true);
+        assert_eq!(not(true), false);
+        assert_eq!(not(false), true);
+    }
+}